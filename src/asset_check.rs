@@ -0,0 +1,71 @@
+use bevy::{
+	asset::LoadState,
+	prelude::*,
+	render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+
+use crate::ui;
+
+// Rutas que el juego carga a mano, si falta alguna mejor decirlo que mostrar una pantalla negra
+const FONTS: &[&str] = &[ui::FONT_PATH];
+const TEXTURES: &[&str] = &["texture/player.png", "texture/enemy.png"];
+const OTHER: &[&str] = &["tilemap/main.ldtk"];
+// Los sonidos se pueden echar en falta sin que se rompa nada
+const SOUNDS: &[&str] = &["sounds/enemy_growl.ogg", "sounds/enemy_alert.ogg", "sounds/flash.ogg", "sounds/footstep.ogg"];
+
+// Magenta para que un sprite sin textura se vea enseguida
+const PLACEHOLDER_COLOR: [u8; 4] = [255, 0, 255, 255];
+
+#[derive(Copy, Clone, PartialEq)]
+enum AssetKind {
+	Font,
+	Texture,
+	Sound,
+	Other,
+}
+
+/// Assets still being watched by `check_assets`, dropped from the list once they load or fail
+#[derive(Default)]
+pub struct PendingAssets(Vec<(&'static str, AssetKind, HandleUntyped)>);
+
+pub fn load_critical_assets(
+	mut commands: Commands,
+	asset_server: Res<AssetServer>,
+) {
+	let lists = [(FONTS, AssetKind::Font), (TEXTURES, AssetKind::Texture), (SOUNDS, AssetKind::Sound), (OTHER, AssetKind::Other)];
+	let pending = lists.iter()
+		.flat_map(|(paths, kind)| paths.iter().map(move |path| (*path, *kind)))
+		.map(|(path, kind)| (path, kind, asset_server.load_untyped(path)))
+		.collect();
+	commands.insert_resource(PendingAssets(pending));
+}
+
+/// Logs every watched asset that failed to load and swaps missing textures for a magenta placeholder
+pub fn check_assets(
+	asset_server: Res<AssetServer>,
+	mut pending: ResMut<PendingAssets>,
+	mut images: ResMut<Assets<Image>>,
+) {
+	pending.0.retain(|(path, kind, handle)| match asset_server.get_load_state(handle) {
+		LoadState::Failed => {
+			match kind {
+				AssetKind::Texture => {
+					error!("Missing texture `assets/{}`, drawing a placeholder instead", path);
+					images.set_untracked(handle.id, Image::new_fill(
+						Extent3d { width: 1, height: 1, depth_or_array_layers: 1 },
+						TextureDimension::D2,
+						&PLACEHOLDER_COLOR,
+						TextureFormat::Rgba8UnormSrgb,
+					));
+				}
+				// Bevy no trae ninguna fuente de serie, el texto simplemente no se ve
+				AssetKind::Font => error!("Missing font `assets/{}`, text using it won't be drawn", path),
+				AssetKind::Sound => warn!("Missing sound `assets/{}`, it will stay silent", path),
+				AssetKind::Other => error!("Missing asset `assets/{}`", path),
+			}
+			false
+		}
+		LoadState::Loaded | LoadState::Unloaded => false,
+		_ => true,
+	});
+}
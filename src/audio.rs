@@ -0,0 +1,268 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+use bevy_kira_audio::{Audio, AudioChannel, AudioSource};
+
+use heron::rapier_plugin::PhysicsWorld;
+
+use crate::{components::*, navigation::{FloorMaterial, NavGrid}, resources::*, settings::Settings, systems::line_of_sight};
+
+// Un canal por grunido, los enemigos mas cercanos se reparten los canales
+const MAX_GROWLS: usize = 3;
+
+pub struct EnemySounds {
+	growl: Handle<AudioSource>,
+	alert: Handle<AudioSource>,
+	growl_channels: Vec<AudioChannel>,
+	alert_channel: AudioChannel,
+}
+
+/// One looping track of the adaptive music, loudest when the threat is at `peak`
+pub struct MusicLayer {
+	pub track: String,
+	pub peak: f32,
+	/// How far from `peak` the layer is still heard, fading linearly to silence
+	pub width: f32,
+}
+
+/// Threat is how much danger the player is in, from 0.0 (nobody after them) to 1.0
+pub struct MusicConfig {
+	/// All of them loop together from the start, so they stay in sync while crossfading
+	pub layers: Vec<MusicLayer>,
+	/// Threat added by each chasing enemy
+	pub chase_threat: f32,
+	/// Threat added by each searching enemy
+	pub search_threat: f32,
+	/// The nearest chasing or searching enemy adds up to 1.0 of threat inside this distance
+	pub proximity_range: f32,
+	/// Threat per second the music can rise towards the target, fast so a chase kicks in at once
+	pub rise: f32,
+	/// Threat per second the music can fall back, slow so it calms down gradually
+	pub fall: f32,
+}
+
+impl Default for MusicConfig {
+	fn default() -> Self {
+		Self {
+			layers: vec![
+				MusicLayer { track: "music/calm.ogg".to_string(), peak: 0.0, width: 0.7 },
+				MusicLayer { track: "music/uneasy.ogg".to_string(), peak: 0.4, width: 0.4 },
+				MusicLayer { track: "music/tense.ogg".to_string(), peak: 1.0, width: 0.7 },
+			],
+			chase_threat: 0.4,
+			search_threat: 0.15,
+			proximity_range: 200.0,
+			rise: 2.0,
+			fall: 0.25,
+		}
+	}
+}
+
+/// Channel of each `MusicConfig.layers` and the threat the music plays at right now
+pub struct Music {
+	channels: Vec<AudioChannel>,
+	intensity: f32,
+}
+
+pub struct PlayerSounds {
+	flash: Handle<AudioSource>,
+	footsteps: HashMap<FloorMaterial, Handle<AudioSource>>,
+	channel: AudioChannel,
+	// Aparte, para que los pasos no corten el destello
+	footstep_channel: AudioChannel,
+}
+
+/// 1.0 next to the listener down to 0.0 at `range`, squared so it fades in late like a real approach
+fn falloff(distance: f32, range: f32) -> f32 {
+	let t = (1.0 - distance / range).clamp(0.0, 1.0);
+	t * t
+}
+
+// 0.5 es el centro, cerca de 0 o 1 suena solo por un lado
+fn panning(offset: Vec2, range: f32) -> f32 {
+	0.5 + (offset.x / range).clamp(-1.0, 1.0) * 0.4
+}
+
+pub fn setup_enemy_audio(
+	mut commands: Commands,
+	asset_server: Res<AssetServer>,
+	audio: Res<Audio>,
+) {
+	let sounds = EnemySounds {
+		growl: asset_server.load("sounds/enemy_growl.ogg"),
+		alert: asset_server.load("sounds/enemy_alert.ogg"),
+		growl_channels: (0..MAX_GROWLS).map(|i| AudioChannel::new(format!("enemy_growl_{}", i))).collect(),
+		alert_channel: AudioChannel::new("enemy_alert".to_string()),
+	};
+	// Los bucles suenan siempre y se silencian cuando no hay nadie cerca
+	for channel in sounds.growl_channels.iter() {
+		audio.set_volume_in_channel(0.0, channel);
+		audio.play_looped_in_channel(sounds.growl.clone(), channel);
+	}
+	commands.insert_resource(sounds);
+}
+
+pub fn setup_music(
+	mut commands: Commands,
+	asset_server: Res<AssetServer>,
+	audio: Res<Audio>,
+	music_config: Res<MusicConfig>,
+) {
+	let channels: Vec<AudioChannel> = (0..music_config.layers.len()).map(|i| AudioChannel::new(format!("music_{}", i))).collect();
+	for (layer, channel) in music_config.layers.iter().zip(channels.iter()) {
+		audio.set_volume_in_channel(0.0, channel);
+		audio.play_looped_in_channel(asset_server.load(layer.track.as_str()), channel);
+	}
+	commands.insert_resource(Music { channels, intensity: 0.0 });
+}
+
+/// Eases the music towards the current threat and crossfades the layers to match, with nobody
+/// chasing or searching it settles back on the calm layer
+pub fn music_intensity(
+	time: Res<Time>,
+	audio: Res<Audio>,
+	settings: Res<Settings>,
+	music_config: Res<MusicConfig>,
+	mut music: ResMut<Music>,
+	player_query: Query<&Transform, With<Player>>,
+	enemy_query: Query<(&Transform, &EnemyState), (With<Enemy>, Without<Pooled>, Without<DyingEnemy>)>,
+) {
+	let player_translation = player_query.get_single().ok().map(|transform| transform.translation.truncate());
+	let mut threat = 0.0;
+	let mut nearest = f32::INFINITY;
+	for (transform, state) in enemy_query.iter() {
+		threat += match state {
+			EnemyState::Chase => music_config.chase_threat,
+			EnemyState::Search => music_config.search_threat,
+			_ => continue,
+		};
+		if let Some(player_translation) = player_translation {
+			nearest = nearest.min(transform.translation.truncate().distance(player_translation));
+		}
+	}
+	if nearest.is_finite() {
+		threat += falloff(nearest, music_config.proximity_range);
+	}
+	let target = threat.min(1.0);
+
+	let rate = if target > music.intensity { music_config.rise } else { music_config.fall };
+	let step = rate * time.delta_seconds();
+	music.intensity += (target - music.intensity).clamp(-step, step);
+	for (layer, channel) in music_config.layers.iter().zip(music.channels.iter()) {
+		let volume = (1.0 - (music.intensity - layer.peak).abs() / layer.width).clamp(0.0, 1.0);
+		audio.set_volume_in_channel(volume * settings.music_volume, channel);
+	}
+}
+
+pub fn setup_player_audio(
+	mut commands: Commands,
+	asset_server: Res<AssetServer>,
+) {
+	commands.insert_resource(PlayerSounds {
+		flash: asset_server.load("sounds/flash.ogg"),
+		footsteps: HashMap::from([
+			(FloorMaterial::Generic, asset_server.load("sounds/footstep.ogg")),
+			(FloorMaterial::Stone, asset_server.load("sounds/footstep_stone.ogg")),
+			(FloorMaterial::Ice, asset_server.load("sounds/footstep_ice.ogg")),
+			(FloorMaterial::Mud, asset_server.load("sounds/footstep_mud.ogg")),
+		]),
+		channel: AudioChannel::new("player".to_string()),
+		footstep_channel: AudioChannel::new("player_footsteps".to_string()),
+	});
+}
+
+pub fn flash_audio(
+	audio: Res<Audio>,
+	sounds: Res<PlayerSounds>,
+	settings: Res<Settings>,
+	mut events: EventReader<FlashBurst>,
+) {
+	if events.iter().count() == 0 { return }
+	audio.set_volume_in_channel(settings.sfx_volume, &sounds.channel);
+	audio.play_in_channel(sounds.flash.clone(), &sounds.channel);
+}
+
+/// Plays on the same `Footstep` events that kick up the dust, with the clip of the floor underfoot
+pub fn footstep_audio(
+	audio: Res<Audio>,
+	sounds: Res<PlayerSounds>,
+	settings: Res<Settings>,
+	nav_grid: Res<NavGrid>,
+	mut events: EventReader<Footstep>,
+) {
+	let footstep = match events.iter().last() {
+		Some(footstep) => footstep,
+		None => return,
+	};
+	let material = nav_grid.material(nav_grid.to_grid(footstep.position.truncate()));
+	let clip = sounds.footsteps.get(&material).or_else(|| sounds.footsteps.get(&FloorMaterial::Generic));
+	if let Some(clip) = clip {
+		audio.set_volume_in_channel(settings.sfx_volume, &sounds.footstep_channel);
+		audio.play_in_channel(clip.clone(), &sounds.footstep_channel);
+	}
+}
+
+/// Idle growls from the nearest enemies and an alert cry when one starts chasing, both fading
+/// with distance and muffled by walls in between
+#[allow(clippy::too_many_arguments)]
+pub fn enemy_audio(
+	audio: Res<Audio>,
+	sounds: Res<EnemySounds>,
+	settings: Res<Settings>,
+	enemy_config: Res<EnemyConfig>,
+	physics_world: PhysicsWorld,
+	mut chasing: Local<HashSet<Entity>>,
+	player_query: Query<&Transform, With<Player>>,
+	enemy_query: Query<(Entity, &Transform, &EnemyState), (With<Enemy>, Without<Pooled>, Without<DyingEnemy>)>,
+) {
+	let player_position = match player_query.get_single() {
+		Ok(transform) => transform.translation,
+		Err(_) => return,
+	};
+	let player_translation = player_position.truncate();
+	let range = enemy_config.hearing_range;
+
+	// (distancia, offset, volumen extra por paredes en medio)
+	let mut audible: Vec<(f32, Vec2, f32)> = Vec::new();
+	let mut loudest_alert: Option<(f32, Vec2, f32)> = None;
+	for (entity, transform, state) in enemy_query.iter() {
+		let offset = transform.translation.truncate() - player_translation;
+		let distance = offset.length();
+		// Solo se lanza el rayo a los que se pueden oir
+		let gain = if distance >= range {
+			0.0
+		} else if line_of_sight(player_position, transform.translation, &physics_world) {
+			1.0
+		} else {
+			enemy_config.wall_muffle
+		};
+		if distance < range { audible.push((distance, offset, gain)) }
+
+		if *state == EnemyState::Chase {
+			// Solo grita al empezar a perseguir, y solo el mas cercano si gritan varios a la vez
+			if chasing.insert(entity) && distance < range && loudest_alert.is_none_or(|(d, _, _)| distance < d) {
+				loudest_alert = Some((distance, offset, gain));
+			}
+		} else {
+			chasing.remove(&entity);
+		}
+	}
+	chasing.retain(|entity| enemy_query.get(*entity).is_ok());
+
+	audible.sort_by(|a, b| a.0.total_cmp(&b.0));
+	for (i, channel) in sounds.growl_channels.iter().enumerate() {
+		match audible.get(i) {
+			Some(&(distance, offset, gain)) => {
+				audio.set_volume_in_channel(falloff(distance, range) * gain * settings.sfx_volume, channel);
+				audio.set_panning_in_channel(panning(offset, range), channel);
+			}
+			None => audio.set_volume_in_channel(0.0, channel),
+		}
+	}
+
+	if let Some((distance, offset, gain)) = loudest_alert {
+		audio.set_volume_in_channel(falloff(distance, range).sqrt() * gain * settings.sfx_volume, &sounds.alert_channel);
+		audio.set_panning_in_channel(panning(offset, range), &sounds.alert_channel);
+		audio.play_in_channel(sounds.alert.clone(), &sounds.alert_channel);
+	}
+}
@@ -0,0 +1,471 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::{math::Mat2, prelude::*};
+use bevy_ecs_ldtk::prelude::*;
+use heron::{prelude::*, rapier_plugin::PhysicsWorld, SensorShape};
+
+use crate::{components::*, resources::*, settings::Settings, systems::{kill_enemy, layer_contact, line_of_sight}, ui::{self, UiFont}};
+
+// Mas corto que HitStop para que el parpadeo se note aunque la fisica siga congelada
+const HURT_FLASH_SECONDS: f32 = 0.1;
+const PROJECTILE_SIZE: f32 = 4.0;
+const PROJECTILE_COLOR: Color = Color::rgb(1.0, 0.4, 0.2);
+
+// Sobre la oscuridad (998.9) y el destello (999.4), debajo de la mira
+const DAMAGE_NUMBER_Z: f32 = 999.5;
+const DAMAGE_NUMBER_SIZE: f32 = 10.0;
+// Empieza encima de la cabeza y sube mientras se desvanece
+const DAMAGE_NUMBER_HEIGHT: f32 = 10.0;
+const DAMAGE_NUMBER_RISE: f32 = 20.0;
+const DAMAGE_NUMBER_SECONDS: f32 = 0.7;
+const PLAYER_DAMAGE_COLOR: Color = Color::rgb(1.0, 0.3, 0.3);
+const ENEMY_DAMAGE_COLOR: Color = Color::rgb(1.0, 0.9, 0.5);
+// Opacidad al despertar, sube hasta 1 cuando termina la inmunidad
+const SPAWN_IMMUNITY_ALPHA: f32 = 0.3;
+
+/// With `EnemyContact::Sensor` the player's body stops colliding with enemies and a sensor
+/// child of the same shape reports the contacts instead, so `damage_on_contact` works the same
+pub fn insert_contact_sensor(
+	mut commands: Commands,
+	combat_config: Res<CombatConfig>,
+	mut query: Query<(Entity, &CollisionShape, &mut CollisionLayers), Added<Player>>,
+) {
+	if combat_config.enemy_contact != EnemyContact::Sensor { return }
+	for (entity, shape, mut layers) in query.iter_mut() {
+		*layers = layers.without_mask(Layer::Enemy);
+		commands.entity(entity).with_children(|parent| {
+			parent.spawn_bundle((
+				shape.clone(),
+				SensorShape,
+				CollisionLayers::none().with_group(Layer::Player).with_mask(Layer::Enemy),
+				Transform::default(),
+				GlobalTransform::default(),
+			));
+		});
+	}
+}
+
+/// Queues contact damage every frame for each enemy touching the player, `apply_damage` sorts out i-frames
+#[allow(clippy::too_many_arguments)]
+pub fn damage_on_contact(
+	combat_config: Res<CombatConfig>,
+	difficulty: Res<LevelDifficulty>,
+	mut physic_event: EventReader<CollisionEvent>,
+	mut damage_queue: ResMut<DamageQueue>,
+	mut touching: Local<HashSet<(Entity, Entity)>>,
+	player_query: Query<(), With<Player>>,
+	hidden_query: Query<(), With<Hidden>>,
+	enemy_query: Query<Option<&SpawnImmunity>, (With<Enemy>, Without<DyingEnemy>)>,
+) {
+	for event in physic_event.iter() {
+		if let Some(pair) = layer_contact(event, Layer::Player, Layer::Enemy) {
+			if event.is_started() {
+				touching.insert(pair);
+			} else {
+				touching.remove(&pair);
+			}
+		}
+	}
+	// Un despawn no siempre manda el evento de fin de contacto, y un enemigo muriendo ya no hace dano
+	touching.retain(|&(player, enemy)| player_query.get(player).is_ok() && enemy_query.get(enemy).is_ok());
+	if combat_config.contact_damage <= 0.0 { return }
+	// El contacto sigue contando mientras es inmune, asi hace dano en cuanto deja de serlo
+	let hurts = |&&(player, enemy): &&(Entity, Entity)| hidden_query.get(player).is_err() && matches!(enemy_query.get(enemy), Ok(None));
+	for &(player, _) in touching.iter().filter(hurts) {
+		damage_queue.0.push(Damage { target: player, amount: combat_config.contact_damage * difficulty.enemy_damage_mult, source: None });
+	}
+}
+
+/// Enemies that `attacks` wind up a hit once the player is in reach, it lands only if the player
+/// is still in reach when the windup ends
+pub fn enemy_attack(
+	time: Res<Time>,
+	combat_config: Res<CombatConfig>,
+	difficulty: Res<LevelDifficulty>,
+	mut damage_queue: ResMut<DamageQueue>,
+	player_query: Query<(Entity, &Transform), (With<Player>, Without<Hidden>)>,
+	mut enemy_query: Query<(&mut Attack, &EnemyKind, &Transform), (With<Enemy>, Without<Dormant>, Without<Stunned>, Without<DyingEnemy>, Without<SpawnImmunity>)>,
+) {
+	let player = player_query.get_single().ok();
+	for (mut attack, kind, transform) in enemy_query.iter_mut() {
+		if !kind.attacks() { continue }
+		attack.cooldown.tick(time.delta());
+		let in_reach = player.filter(|(_, player_transform)| {
+			player_transform.translation.truncate().distance(transform.translation.truncate()) <= combat_config.attack_range
+		});
+
+		let landed = match &mut attack.windup {
+			Some(windup) => windup.tick(time.delta()).finished(),
+			None => {
+				if in_reach.is_some() && attack.cooldown.finished() {
+					attack.windup = Some(Timer::from_seconds(combat_config.attack_windup, false));
+				}
+				continue;
+			}
+		};
+		if !landed { continue }
+		attack.windup = None;
+		attack.cooldown = Timer::from_seconds(combat_config.attack_cooldown, false);
+		if let Some((target, _)) = in_reach.filter(|_| combat_config.attack_damage > 0.0) {
+			damage_queue.0.push(Damage { target, amount: combat_config.attack_damage * difficulty.enemy_damage_mult, source: Some(transform.translation) });
+		}
+	}
+}
+
+/// Enemies with a `Shooter` fire at a player they can see, once every `fire_interval`
+pub fn fire_projectiles(
+	mut commands: Commands,
+	time: Res<Time>,
+	projectile_config: Res<ProjectileConfig>,
+	player_query: Query<&Transform, With<Player>>,
+	mut enemy_query: Query<(&mut Shooter, &Vision, &Transform), (With<Enemy>, Without<Dormant>, Without<Stunned>, Without<DyingEnemy>, Without<SpawnImmunity>)>,
+) {
+	let player_translation = match player_query.get_single() {
+		Ok(transform) => transform.translation,
+		Err(_) => return,
+	};
+	for (mut shooter, vision, transform) in enemy_query.iter_mut() {
+		let kind = match shooter.kind {
+			Some(kind) => kind,
+			None => continue,
+		};
+		shooter.cooldown.tick(time.delta());
+		if !vision.can_see_player || !shooter.cooldown.finished() { continue }
+		shooter.cooldown = Timer::from_seconds(projectile_config.fire_interval, false);
+
+		let (speed, lifetime) = match kind {
+			ProjectileKind::Straight => (projectile_config.speed, projectile_config.lifetime),
+			ProjectileKind::Homing => (projectile_config.homing_speed, projectile_config.homing_lifetime),
+		};
+		let direction = (player_translation - transform.translation).truncate().normalize_or_zero();
+		let mut projectile = commands.spawn_bundle(SpriteBundle {
+			sprite: Sprite {
+				color: PROJECTILE_COLOR,
+				custom_size: Some(Vec2::splat(PROJECTILE_SIZE)),
+				..Default::default()
+			},
+			transform: Transform::from_translation(transform.translation),
+			..Default::default()
+		});
+		projectile
+			.insert(CollisionShape::Sphere { radius: PROJECTILE_SIZE / 2.0 })
+			.insert(RigidBody::KinematicVelocityBased)
+			.insert(SensorShape)
+			.insert(Velocity::from_linear(direction.extend(0.0) * speed))
+			.insert(layers_for("Projectile"))
+			.insert(Projectile { damage: projectile_config.damage })
+			.insert(Lifetime { timer: Timer::from_seconds(lifetime, false) });
+		if kind == ProjectileKind::Homing {
+			projectile.insert(Homing { turn_rate: projectile_config.homing_turn_rate });
+		}
+	}
+}
+
+pub fn home_projectiles(
+	time: Res<Time>,
+	player_query: Query<&Transform, With<Player>>,
+	mut query: Query<(&mut Velocity, &Homing, &Transform), With<Projectile>>,
+) {
+	let player_translation = match player_query.get_single() {
+		Ok(transform) => transform.translation,
+		Err(_) => return,
+	};
+	for (mut velocity, homing, transform) in query.iter_mut() {
+		let heading = velocity.linear.truncate();
+		let desired = (player_translation - transform.translation).truncate();
+		let angle = heading.angle_between(desired);
+		if angle.is_nan() { continue }
+		let max_angle = homing.turn_rate * time.delta_seconds();
+		velocity.linear = (Mat2::from_angle(angle.clamp(-max_angle, max_angle)) * heading).extend(0.0);
+	}
+}
+
+/// Despawns projectiles about to enter a wall this frame and lands the ones that reach the player
+pub fn projectile_hits(
+	mut commands: Commands,
+	time: Res<Time>,
+	physics_world: PhysicsWorld,
+	mut physic_event: EventReader<CollisionEvent>,
+	difficulty: Res<LevelDifficulty>,
+	mut damage_queue: ResMut<DamageQueue>,
+	query: Query<(Entity, &Projectile, &Velocity, &Transform)>,
+) {
+	for event in physic_event.iter().filter(|event| event.is_started()) {
+		if let Some((player, projectile_entity)) = layer_contact(event, Layer::Player, Layer::Projectile) {
+			if let Ok((_, projectile, _, transform)) = query.get(projectile_entity) {
+				damage_queue.0.push(Damage { target: player, amount: projectile.damage * difficulty.enemy_damage_mult, source: Some(transform.translation) });
+				commands.entity(projectile_entity).despawn();
+			}
+		}
+	}
+	// Un sensor cinematico no recibe eventos contra las paredes estaticas
+	for (entity, _, velocity, transform) in query.iter() {
+		let next = transform.translation + velocity.linear * time.delta_seconds();
+		if !line_of_sight(transform.translation, next, &physics_world) {
+			commands.entity(entity).despawn();
+		}
+	}
+}
+
+/// Takes `amount` off `health`, true if that leaves it at zero
+pub fn apply_damage_to(health: &mut Health, amount: f32) -> bool {
+	health.current = (health.current - amount).max(0.0);
+	health.current <= 0.0
+}
+
+/// Number rising from whoever took `amount`, red for the player
+fn spawn_damage_number(commands: &mut Commands, font: &UiFont, translation: Vec3, amount: f32, player: bool) {
+	let color = if player { PLAYER_DAMAGE_COLOR } else { ENEMY_DAMAGE_COLOR };
+	let translation = (translation.truncate() + Vec2::Y * DAMAGE_NUMBER_HEIGHT).extend(DAMAGE_NUMBER_Z);
+	let entity = ui::world_text(commands, font, &amount.to_string(), DAMAGE_NUMBER_SIZE, color, translation);
+	commands.entity(entity)
+		.insert(Drift { velocity: Vec2::Y * DAMAGE_NUMBER_RISE })
+		.insert(Fade { alpha: 1.0 })
+		.insert(Lifetime { timer: Timer::from_seconds(DAMAGE_NUMBER_SECONDS, false) });
+}
+
+/// Lands at most one hit per entity per frame, the largest one, and only outside its i-frames
+#[allow(clippy::too_many_arguments)]
+pub fn apply_damage(
+	mut commands: Commands,
+	font: Res<UiFont>,
+	settings: Res<Settings>,
+	combat_config: Res<CombatConfig>,
+	mut damage_queue: ResMut<DamageQueue>,
+	mut hit_stop: ResMut<HitStop>,
+	mut died: EventWriter<PlayerDied>,
+	mut query: Query<(&mut Health, &Transform, Option<&mut Velocity>, Option<&Player>, Option<&Enemy>), (Without<Invincible>, Without<RespawnProtection>)>,
+) {
+	let mut hits: HashMap<Entity, (f32, Option<Vec3>)> = HashMap::new();
+	for damage in damage_queue.0.drain(..) {
+		let hit = hits.entry(damage.target).or_insert((0.0, None));
+		if damage.amount > hit.0 {
+			*hit = (damage.amount, damage.source);
+		}
+	}
+
+	for (entity, (amount, source)) in hits {
+		let (mut health, transform, velocity, player, enemy) = match query.get_mut(entity) {
+			Ok(target) => target,
+			// Invencible o ya sin salud
+			Err(_) => continue,
+		};
+		let dead = apply_damage_to(&mut health, amount);
+		if settings.damage_numbers {
+			spawn_damage_number(&mut commands, &font, transform.translation, amount, player.is_some());
+		}
+		// Los golpes pequenos, como cada tick de la linterna, no congelan
+		if (dead && enemy.is_some()) || amount >= hit_stop.min_damage {
+			hit_stop.start();
+		}
+		commands.entity(entity).insert(HitFlash { timer: Timer::from_seconds(HURT_FLASH_SECONDS, false) });
+		// Solo el jugador tiene i-frames, un enemigo recibe cada golpe que le llega
+		if player.is_some() {
+			commands.entity(entity).insert(Invincible { timer: Timer::from_seconds(combat_config.invincibility, false) });
+		}
+
+		if dead {
+			if player.is_some() {
+				died.send(PlayerDied);
+			} else if let (Some(_), Some(mut velocity)) = (enemy, velocity) {
+				let away = source.map_or(Vec2::ZERO, |source| (transform.translation - source).truncate().normalize_or_zero());
+				kill_enemy(&mut commands, entity, &mut velocity, away, hit_stop.duration);
+			} else {
+				commands.entity(entity).despawn_recursive();
+			}
+		}
+	}
+}
+
+/// Back to full health for enemies taken out of the pool
+pub fn restore_enemy_health(
+	recycled: RemovedComponents<Pooled>,
+	mut query: Query<&mut Health, With<Enemy>>,
+) {
+	for entity in recycled.iter() {
+		if let Ok(mut health) = query.get_mut(entity) {
+			health.current = health.max;
+		}
+	}
+}
+
+pub fn insert_spawn_immunity(
+	mut commands: Commands,
+	combat_config: Res<CombatConfig>,
+	query: Query<Entity, Added<Enemy>>,
+	recycled: RemovedComponents<Pooled>,
+) {
+	if combat_config.spawn_immunity <= 0.0 { return }
+	for entity in query.iter().chain(recycled.iter()) {
+		commands.entity(entity).insert(SpawnImmunity { timer: Timer::from_seconds(combat_config.spawn_immunity, false) });
+	}
+}
+
+/// Counts down the immunity of awake enemies and fades them in meanwhile, at the end they're
+/// fully opaque and hurt like any other
+pub fn spawn_immunity(
+	mut commands: Commands,
+	time: Res<Time>,
+	mut query: Query<(Entity, &mut SpawnImmunity, &mut TextureAtlasSprite), (Without<Dormant>, Without<Pooled>, Without<DyingEnemy>)>,
+) {
+	for (entity, mut immunity, mut sprite) in query.iter_mut() {
+		if immunity.timer.tick(time.delta()).finished() {
+			commands.entity(entity).remove::<SpawnImmunity>();
+			continue;
+		}
+		// spawn_anim puede ir todavia por debajo
+		let fade = SPAWN_IMMUNITY_ALPHA + (1.0 - SPAWN_IMMUNITY_ALPHA) * immunity.timer.percent();
+		let alpha = sprite.color.a().min(fade);
+		sprite.color.set_a(alpha);
+	}
+}
+
+/// The player reloaded after a death starts out protected, the enemies reloaded with it are
+/// already back to their default state
+pub fn protect_respawn(
+	mut commands: Commands,
+	combat_config: Res<CombatConfig>,
+	mut pending_respawn: ResMut<PendingRespawn>,
+	query: Query<Entity, Added<Player>>,
+) {
+	for entity in query.iter() {
+		if pending_respawn.0 && combat_config.respawn_protection > 0.0 {
+			commands.entity(entity).insert(RespawnProtection { timer: Timer::from_seconds(combat_config.respawn_protection, false) });
+		}
+		pending_respawn.0 = false;
+	}
+}
+
+/// Protection lasts until it times out or the player presses anything, so it can't be abused
+/// to walk through enemies
+pub fn tick_respawn_protection(
+	mut commands: Commands,
+	time: Res<Time>,
+	keys: Res<Input<KeyCode>>,
+	mouse: Res<Input<MouseButton>>,
+	mut query: Query<(Entity, &mut RespawnProtection)>,
+) {
+	let input = keys.get_just_pressed().next().is_some() || mouse.get_just_pressed().next().is_some();
+	for (entity, mut protection) in query.iter_mut() {
+		if protection.timer.tick(time.delta()).finished() || input {
+			commands.entity(entity).remove::<RespawnProtection>();
+		}
+	}
+}
+
+pub fn tick_invincibility(
+	mut commands: Commands,
+	time: Res<Time>,
+	mut query: Query<(Entity, &mut Invincible)>,
+) {
+	for (entity, mut invincible) in query.iter_mut() {
+		if invincible.timer.tick(time.delta()).finished() {
+			commands.entity(entity).remove::<Invincible>();
+		}
+	}
+}
+
+/// Restarts the current level from scratch when the player dies
+pub fn restart_on_death(
+	mut commands: Commands,
+	mut died: EventReader<PlayerDied>,
+	mut pending_respawn: ResMut<PendingRespawn>,
+	ldtk_query: Query<(Entity, &Handle<LdtkAsset>)>,
+) {
+	if died.iter().count() == 0 { return }
+	pending_respawn.0 = true;
+	// Volver a poner el handle cuenta como Added, bevy_ecs_ldtk despawnea el mundo y lo vuelve a cargar
+	for (entity, handle) in ldtk_query.iter() {
+		commands.entity(entity).remove::<Handle<LdtkAsset>>().insert(handle.clone());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{settings::apply_settings, test_util::*};
+
+	fn damage_app() -> App {
+		let mut app = test_app();
+		app.insert_resource(UiFont(Handle::default()))
+			.insert_resource(Settings { damage_numbers: false, ..Default::default() })
+			.add_event::<PlayerDied>()
+			.add_system(apply_damage);
+		app
+	}
+
+	#[test]
+	fn sensor_contact_comes_from_settings() {
+		let mut app = test_app();
+		app.insert_resource(Settings { enemy_contact: EnemyContact::Sensor, ..Default::default() })
+			.add_system(apply_settings.label("settings"))
+			.add_system(insert_contact_sensor.after("settings"));
+		let player = spawn_player(&mut app.world, Vec2::ZERO);
+		app.update();
+
+		assert!(!app.world.get::<CollisionLayers>(player).unwrap().contains_mask(Layer::Enemy));
+		let children = app.world.get::<Children>(player).unwrap();
+		assert_eq!(children.len(), 1);
+		let sensor = app.world.entity(children[0]);
+		assert!(sensor.get::<SensorShape>().is_some());
+		assert!(sensor.get::<CollisionLayers>().unwrap().contains_mask(Layer::Enemy));
+	}
+
+	#[test]
+	fn level_difficulty_scales_contact_damage() {
+		let mut app = test_app();
+		app.insert_resource(LevelDifficulty { enemy_damage_mult: 2.5, ..Default::default() })
+			.add_event::<CollisionEvent>()
+			.add_system(damage_on_contact);
+		let player = spawn_player(&mut app.world, Vec2::ZERO);
+		let enemy = spawn_enemy(&mut app.world, Vec2::ZERO);
+		send(&mut app, contact_started(player, "Player", enemy, "Enemy"));
+		app.update();
+
+		let contact_damage = CombatConfig::default().contact_damage;
+		let queued: Vec<f32> = app.world.get_resource::<DamageQueue>().unwrap().0.iter().map(|damage| damage.amount).collect();
+		assert_eq!(queued, vec![contact_damage * 2.5]);
+	}
+
+	fn hit(app: &mut App, target: Entity, amount: f32) {
+		app.world.get_resource_mut::<DamageQueue>().unwrap().0.push(Damage { target, amount, source: None });
+	}
+
+	#[test]
+	fn only_the_largest_hit_of_a_frame_lands() {
+		let mut app = damage_app();
+		let player = spawn_player(&mut app.world, Vec2::ZERO);
+		let max = app.world.get::<Health>(player).unwrap().max;
+		hit(&mut app, player, 1.0);
+		hit(&mut app, player, 2.0);
+		hit(&mut app, player, 0.5);
+		app.update();
+
+		assert_eq!(app.world.get::<Health>(player).unwrap().current, max - 2.0);
+		assert!(app.world.get::<Invincible>(player).is_some());
+		assert!(app.world.get_resource::<DamageQueue>().unwrap().0.is_empty());
+
+		// Los i-frames se comen el siguiente golpe
+		hit(&mut app, player, 1.0);
+		app.update();
+		assert_eq!(app.world.get::<Health>(player).unwrap().current, max - 2.0);
+	}
+
+	#[test]
+	fn light_hits_on_enemies_dont_freeze_or_grant_i_frames() {
+		let mut app = damage_app();
+		let enemy = spawn_enemy(&mut app.world, Vec2::ZERO);
+		let max = app.world.get::<Health>(enemy).unwrap().max;
+		hit(&mut app, enemy, 0.25);
+		hit(&mut app, enemy, 0.5);
+		app.update();
+
+		assert_eq!(app.world.get::<Health>(enemy).unwrap().current, max - 0.5);
+		assert!(app.world.get::<Invincible>(enemy).is_none());
+		assert!(app.world.get_resource::<HitStop>().unwrap().timer.is_none());
+
+		hit(&mut app, enemy, 0.5);
+		app.update();
+		assert_eq!(app.world.get::<Health>(enemy).unwrap().current, max - 1.0);
+	}
+}
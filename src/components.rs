@@ -1,14 +1,39 @@
+// The Bundle derive forgets its fields after moving them into the world
+#![allow(clippy::forget_non_drop)]
+
+use std::collections::VecDeque;
+
 use bevy::{prelude::*, math::vec3};
-use bevy_ecs_ldtk::prelude::*;
-use heron::prelude::*;
+use bevy_ecs_ldtk::{prelude::*, utils};
+use heron::{prelude::*, SensorShape};
+use serde::{Deserialize, Serialize};
+
+const PLAYER_MAX_HEALTH: f32 = 3.0;
+const PLAYER_CORNER_RADIUS: f32 = 2.0;
 
 #[derive(Default, Component)]
 pub struct MainCamera {
 	pub last_cursor_position: Vec2,
+	/// Center of the dead zone, only dragged along when the player leaves the box
+	pub focus: Vec2,
+	/// Eased offset towards where the player is walking
+	pub look_ahead: Vec2,
+}
+
+#[derive(Default, Component)]
+pub struct DiagnosticsText;
+
+/// Full screen overlay parented to the camera, tinted by `AmbientTint`
+#[derive(Default, Component)]
+pub struct Darkness;
+
+#[derive(Default, Component)]
+pub struct Parallax {
+	pub factor: f32,
 }
 
 #[derive(Default, Bundle)]
-struct ColliderEntityBundle {
+pub(crate) struct ColliderEntityBundle {
 	pub collider: CollisionShape,
 	pub rigid_body: RigidBody,
 	pub velovity: Velocity,
@@ -17,40 +42,87 @@ struct ColliderEntityBundle {
 //	pub physic_material: PhysicMaterial,
 }
 
-#[derive(PhysicsLayer)]
+#[derive(Clone, Copy, PhysicsLayer)]
 pub enum Layer {
+	Wall,
 	Player,
+	Enemy,
 	Win,
+	Pickup,
+	Platform,
+	Projectile,
+}
+
+/// Group and masks of everything that collides, by LDtk identifier, the whole interaction matrix
+///
+/// Sensors (win, pickups) only mask the player, so enemies walk through them. Anything
+/// unknown collides with nothing.
+pub fn layers_for(identifier: &str) -> CollisionLayers {
+	let (group, masks): (Layer, &[Layer]) = match identifier {
+		"Player" => (Layer::Player, &[Layer::Wall, Layer::Enemy, Layer::Win, Layer::Pickup, Layer::Platform, Layer::Projectile]),
+		"Enemy" => (Layer::Enemy, &[Layer::Wall, Layer::Player, Layer::Enemy]),
+		"Wall" => (Layer::Wall, &[Layer::Wall, Layer::Player, Layer::Enemy]),
+		// Cerrada es una pared mas, tambien para los raycasts, ver apply_gates
+		"Gate" => (Layer::Wall, &[Layer::Wall, Layer::Player, Layer::Enemy]),
+		"Win" => (Layer::Win, &[Layer::Player]),
+		"Collectible" => (Layer::Pickup, &[Layer::Player]),
+		"Patroller" => (Layer::Platform, &[Layer::Player]),
+		// Las paredes se comprueban con un raycast, ver move_projectiles
+		"Projectile" => (Layer::Projectile, &[Layer::Player]),
+		_ => return CollisionLayers::none(),
+	};
+	CollisionLayers::none().with_group(group).with_masks(masks.iter().copied())
 }
 
 impl From<EntityInstance> for ColliderEntityBundle{
 	fn from(entity_instance: EntityInstance) -> Self {
 		let rotation_constraints = RotationConstraints::lock();
 
-		match entity_instance.identifier.as_ref() {
+		let bundle = match entity_instance.identifier.as_ref() {
 			"Player" => Self {
+				// Esquinas redondeadas para deslizar por las esquinas de las paredes sin engancharse,
+				// el borde se suma a half_extends y el total sigue siendo 7x14
 				collider: CollisionShape::Cuboid {
-					half_extends: vec3(7., 14., 0.),
-					border_radius: None,
+					half_extends: vec3(7. - PLAYER_CORNER_RADIUS, 14. - PLAYER_CORNER_RADIUS, 0.),
+					border_radius: Some(PLAYER_CORNER_RADIUS),
 				},
 				rigid_body: RigidBody::Dynamic,
 				rotation_constraints,
-				collision_layer: CollisionLayers::none()
-					.with_mask(Layer::Player)
-					.with_groups([Layer::Win]),
 				..Default::default()
 			},
-			"Win" =>{println!("win spawn"); Self {
+			"Win" => Self {
 				collider: CollisionShape::Cuboid {
 					half_extends: vec3(8., 8., 0.),
 					border_radius: None
 				},
 				rigid_body: RigidBody::Sensor,
-				collision_layer: CollisionLayers::none()
-					.with_mask(Layer::Win)
-					.with_groups([Layer::Player]),
 				..Default::default()
-			}},
+			},
+			"Collectible" => Self {
+				collider: CollisionShape::Cuboid {
+					half_extends: vec3(8., 8., 0.),
+					border_radius: None
+				},
+				rigid_body: RigidBody::Sensor,
+				..Default::default()
+			},
+			"Patroller" => Self {
+				collider: CollisionShape::Cuboid {
+					half_extends: vec3(entity_instance.width as f32 / 2., entity_instance.height as f32 / 2., 0.),
+					border_radius: None
+				},
+				rigid_body: RigidBody::KinematicVelocityBased,
+				rotation_constraints,
+				..Default::default()
+			},
+			"Gate" => Self {
+				collider: CollisionShape::Cuboid {
+					half_extends: vec3(entity_instance.width as f32 / 2., entity_instance.height as f32 / 2., 0.),
+					border_radius: None
+				},
+				rigid_body: RigidBody::Static,
+				..Default::default()
+			},
 			"Enemy" => Self {
 				collider: CollisionShape::Cuboid {
 					half_extends: vec3(8., 8., 0.),
@@ -60,25 +132,78 @@ impl From<EntityInstance> for ColliderEntityBundle{
 				//rotation_constraints,
 				..Default::default()
 			},
-			_ => Self {
-				collision_layer: CollisionLayers::none(),
-				..Default::default()
-			}
+			_ => Self::default(),
+		};
+		Self {
+			collision_layer: layers_for(&entity_instance.identifier),
+			..bundle
 		}
 	}
 }
 
-#[derive(Default, Bundle)]
-struct TimerBundle {
+/// Frames of the entity's sprite sheet, the `Timer` next to it paces them
+#[derive(Component)]
+pub struct AnimationDef {
+	/// Walking frames, from the start of the sheet
+	pub frames: usize,
+	/// First and last frame of the death row, played by `DyingEnemy`s
+	pub death: Option<(usize, usize)>,
+}
+
+/// Sprite sheet plus frame timer, laid out by the LDtk entity fields `sprite`, `tile_size`,
+/// `columns`, `rows`, `frame_duration` and `death_row`
+///
+/// With `death_row` (0 based) that row is the death animation and the rows above it the walk.
+/// Any missing field falls back to the built-in sheet for the entity's identifier.
+#[derive(Bundle)]
+pub struct AnimatedSpriteBundle {
+	#[bundle]
+	sprite_sheet_bundle: SpriteSheetBundle,
+	animation_def: AnimationDef,
 	timer: Timer,
 }
 
-impl From<EntityInstance> for TimerBundle {
-	fn from(entity_instance: EntityInstance) -> Self {
-		match entity_instance.identifier.as_ref() {
-			"Player" => Self { timer: Timer::from_seconds(1./8., true) },
-			"Enemy"  => Self { timer: Timer::from_seconds(1./12., true) },
-			_ => Self::default()
+impl LdtkEntity for AnimatedSpriteBundle {
+	fn bundle_entity(
+		entity_instance: &EntityInstance,
+		_: &LayerInstance,
+		_: Option<&Handle<Image>>,
+		_: Option<&TilesetDefinition>,
+		asset_server: &AssetServer,
+		texture_atlases: &mut Assets<TextureAtlas>,
+	) -> Self {
+		let mut death_row = None;
+		let (mut sprite, mut tile_size, mut columns, mut rows, mut frame_duration) = match entity_instance.identifier.as_ref() {
+			"Player" => ("texture/player.png".to_string(), 32, 6, 1, 1./8.),
+			_ => ("texture/enemy.png".to_string(), 32, 4, 1, 1./12.),
+		};
+		for field in entity_instance.field_instances.iter() {
+			match (field.identifier.as_ref(), &field.value) {
+				("sprite", FieldValue::FilePath(Some(path)) | FieldValue::String(Some(path))) => sprite = path.clone(),
+				("tile_size", FieldValue::Int(Some(value))) => tile_size = *value,
+				("columns", FieldValue::Int(Some(value))) => columns = *value as usize,
+				("rows", FieldValue::Int(Some(value))) => rows = *value as usize,
+				("frame_duration", FieldValue::Float(Some(value))) => frame_duration = *value,
+				("death_row", FieldValue::Int(Some(value))) => death_row = Some(*value as usize),
+				_ => (),
+			}
+		}
+
+		AnimatedSpriteBundle {
+			sprite_sheet_bundle: SpriteSheetBundle {
+				texture_atlas: texture_atlases.add(TextureAtlas::from_grid(
+					asset_server.load(sprite.as_str()),
+					Vec2::splat(tile_size as f32),
+					columns,
+					rows,
+				)),
+				..Default::default()
+			},
+			animation_def: match death_row.filter(|row| *row < rows) {
+				Some(row) => AnimationDef { frames: (columns * row).max(1), death: Some((columns * row, columns * (row + 1) - 1)) },
+				None => AnimationDef { frames: (columns * rows).max(1), death: None },
+			},
+			timer: Timer::from_seconds(frame_duration, true),
 		}
 	}
 }
@@ -86,37 +211,577 @@ impl From<EntityInstance> for TimerBundle {
 #[derive(Default, Component)]
 pub struct Player;
 
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum FlashlightMode {
+	/// Wide, short and cheap, barely hurts
+	#[default]
+	Wide,
+	/// Long bright beam that burns enemies fast and empties the battery in a few seconds
+	Narrow,
+	/// No beam, clicking fires a burst that stuns everything around, see `FlashConfig`
+	Flash,
+}
+
+impl FlashlightMode {
+	pub fn next(self) -> Self {
+		match self {
+			FlashlightMode::Wide => FlashlightMode::Narrow,
+			FlashlightMode::Narrow => FlashlightMode::Flash,
+			FlashlightMode::Flash => FlashlightMode::Wide,
+		}
+	}
+
+	pub fn range(self) -> f32 {
+		match self {
+			FlashlightMode::Wide => 120.0,
+			FlashlightMode::Narrow => 240.0,
+			FlashlightMode::Flash => 0.0,
+		}
+	}
+
+	/// Half the cone's opening, in radians
+	pub fn half_angle(self) -> f32 {
+		match self {
+			FlashlightMode::Wide => 0.7,
+			FlashlightMode::Narrow => 0.2,
+			FlashlightMode::Flash => 0.0,
+		}
+	}
+
+	/// Battery used per second while on
+	pub fn drain(self) -> f32 {
+		match self {
+			FlashlightMode::Wide => 0.04,
+			FlashlightMode::Narrow => 0.15,
+			FlashlightMode::Flash => 0.0,
+		}
+	}
+
+	/// Light exposure dealt per second to enemies in the cone
+	pub fn exposure_rate(self) -> f32 {
+		match self {
+			FlashlightMode::Wide => 0.5,
+			FlashlightMode::Narrow => 2.0,
+			FlashlightMode::Flash => 0.0,
+		}
+	}
+}
+
+/// Cone of light the player points at the cursor, toggled with the left mouse button
+/// and switched between modes with Q or the mouse wheel
+#[derive(Component)]
+pub struct Flashlight {
+	pub on: bool,
+	/// Unit vector from the player towards the cursor, kept by `aim_flashlight`
+	pub direction: Vec2,
+	pub mode: FlashlightMode,
+	/// Time until the next flash burst
+	pub flash_cooldown: Timer,
+}
+
+impl Default for Flashlight {
+	fn default() -> Self {
+		Self {
+			on: false,
+			direction: Vec2::X,
+			mode: FlashlightMode::default(),
+			flash_cooldown: Timer::default(),
+		}
+	}
+}
+
+impl Flashlight {
+	/// Whether `point` falls inside the cone cast from `origin`, walls are not checked
+	pub fn contains(&self, origin: Vec2, point: Vec2) -> bool {
+		let offset = point - origin;
+		let distance = offset.length();
+		distance <= self.mode.range()
+			&& (distance == 0.0 || self.direction.angle_between(offset).abs() <= self.mode.half_angle())
+	}
+}
+
+/// Charge left for the flashlight, from 0.0 to 1.0, refills slowly while the light is off
+#[derive(Component)]
+pub struct Battery {
+	pub charge: f32,
+}
+
+impl Default for Battery {
+	fn default() -> Self {
+		Self { charge: 1.0 }
+	}
+}
+
+#[derive(Default, Component)]
+pub struct HudText;
+
+#[derive(Component)]
+pub struct Health {
+	pub current: f32,
+	pub max: f32,
+}
+
+impl Health {
+	pub fn new(max: f32) -> Self {
+		Self { current: max, max }
+	}
+
+	/// The `max_health` field of the LDtk entity, if it has one
+	pub fn max_field(entity_instance: &EntityInstance) -> Option<f32> {
+		entity_instance.field_instances.iter().find_map(|field| match (field.identifier.as_ref(), &field.value) {
+			("max_health", FieldValue::Int(Some(max))) => Some(*max as f32),
+			("max_health", FieldValue::Float(Some(max))) => Some(*max),
+			_ => None,
+		})
+	}
+}
+
+/// Read from the `max_health` field of the LDtk entity, without it the player gets
+/// `PLAYER_MAX_HEALTH` and enemies `EnemyKind::max_health`
+impl From<EntityInstance> for Health {
+	fn from(entity_instance: EntityInstance) -> Self {
+		let max = Health::max_field(&entity_instance).unwrap_or_else(|| match entity_instance.identifier.as_ref() {
+			"Player" => PLAYER_MAX_HEALTH,
+			_ => EnemyKind::from(entity_instance).max_health(),
+		});
+		Health::new(max)
+	}
+}
+
+/// Ignores every hit until the timer finishes, granted by `apply_damage`
+#[derive(Component)]
+pub struct Invincible {
+	pub timer: Timer,
+}
+
+/// I-frames of a player that just respawned, unlike `Invincible` they end on the first input
+#[derive(Component)]
+pub struct RespawnProtection {
+	pub timer: Timer,
+}
+
+/// Short burst of speed on Space, `active` runs for the dash itself and `cooldown` until the next one
+#[derive(Default, Component)]
+pub struct Dash {
+	pub active: Option<Timer>,
+	pub cooldown: Timer,
+	pub direction: Vec2,
+	/// Layers from before the dash, put back when it ends if it phased through enemies
+	pub saved_layers: Option<CollisionLayers>,
+}
+
+/// Despawns the entity once the timer finishes
+#[derive(Component)]
+pub struct Lifetime {
+	pub timer: Timer,
+}
+
+/// Sprite alpha that goes from `alpha` down to 0.0 over the entity's `Lifetime`
+#[derive(Component)]
+pub struct Fade {
+	pub alpha: f32,
+}
+
+/// Moves the entity at a constant `velocity`, in world units per second, with no physics
+#[derive(Component)]
+pub struct Drift {
+	pub velocity: Vec2,
+}
+
+/// Eases a freshly loaded entity in with `SpawnAnimConfig.effect`, removed once the timer finishes
+#[derive(Component)]
+pub struct SpawnAnim {
+	pub timer: Timer,
+}
+
+/// Scorch mark left where an enemy died, `DecalConfig.max` of them at most
+#[derive(Component)]
+pub struct Decal;
+
+/// The moving platform the player is standing on, if any
+#[derive(Default, Component)]
+pub struct Riding {
+	pub platform: Option<Entity>,
+}
+
 #[derive(Bundle, LdtkEntity)]
 pub struct PlayerBundle {
 	pub player: Player,
 	#[from_entity_instance]
+	health: Health,
+	riding: Riding,
+	flashlight: Flashlight,
+	battery: Battery,
+	dash: Dash,
+	#[from_entity_instance]
 	#[bundle]
 	collider: ColliderEntityBundle,
-	#[sprite_sheet_bundle("texture/player.png", 32.0, 32.0, 6, 1, 0.0, 0)]
-	#[bundle]
-	pub sprite_sheet_bundle: SpriteSheetBundle,
-	#[from_entity_instance]
+	#[ldtk_entity]
 	#[bundle]
-	timer_bundle: TimerBundle,
+	animated_sprite: AnimatedSpriteBundle,
 }
 
 #[derive(Default, Component)]
 pub struct Enemy;
 
+/// Read from the `kind` enum field of the LDtk enemy, missing or unknown values are `Stalker`
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub enum EnemyKind {
+	#[default]
+	Stalker,
+	/// Too tough to be burnt away by the flashlight
+	Brute,
+	/// Like a brute, but also follows the player into safe zones
+	Boss,
+	/// Leaves a fake trail of the player's movement that lures the rest of the pack, see `ScentTrail`
+	Mimic,
+}
+
+impl EnemyKind {
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"Stalker" => Some(EnemyKind::Stalker),
+			"Brute" => Some(EnemyKind::Brute),
+			"Boss" => Some(EnemyKind::Boss),
+			"Mimic" => Some(EnemyKind::Mimic),
+			_ => None,
+		}
+	}
+
+	pub fn light_vulnerable(&self) -> bool {
+		matches!(self, EnemyKind::Stalker)
+	}
+
+	pub fn respects_safe_zones(&self) -> bool {
+		!matches!(self, EnemyKind::Boss)
+	}
+
+	/// Hits it takes at 1 damage each, light included
+	pub fn max_health(&self) -> f32 {
+		match self {
+			EnemyKind::Stalker | EnemyKind::Mimic => 1.0,
+			EnemyKind::Brute => 3.0,
+			EnemyKind::Boss => 6.0,
+		}
+	}
+
+	/// Brutes hunt alone, they neither call nor answer the pack
+	pub fn shares_alerts(&self) -> bool {
+		!matches!(self, EnemyKind::Brute)
+	}
+
+	/// Brutes and Bosses wind up hits on top of hurting on touch, see `CombatConfig`
+	pub fn attacks(&self) -> bool {
+		matches!(self, EnemyKind::Brute | EnemyKind::Boss)
+	}
+
+	/// Bosses follow the player anywhere, whatever their `leash_radius` says
+	pub fn can_be_leashed(&self) -> bool {
+		!matches!(self, EnemyKind::Boss)
+	}
+}
+
+impl From<EntityInstance> for EnemyKind {
+	fn from(entity_instance: EntityInstance) -> Self {
+		let kind = entity_instance.field_instances.iter().find_map(|field| match (field.identifier.as_ref(), &field.value) {
+			("kind", FieldValue::Enum(Some(kind)) | FieldValue::String(Some(kind))) => Some(kind.clone()),
+			_ => None,
+		});
+		kind.as_deref().and_then(EnemyKind::from_name).unwrap_or_default()
+	}
+}
+
+/// Seconds spent inside the flashlight, the enemy is destroyed once `exposure` reaches `threshold`
+///
+/// Inserted on spawn for every `EnemyKind` that is `light_vulnerable`.
+#[derive(Component)]
+pub struct LightVulnerable {
+	pub exposure: f32,
+	pub threshold: f32,
+}
+
+/// Enemy knocked back by the light that killed it, fading out with its AI and contact damage off
+///
+/// Goes back to the `EnemyPool` when the timer finishes.
+#[derive(Component)]
+pub struct DyingEnemy {
+	pub timer: Timer,
+}
+
+/// Enemy parked in the `EnemyPool`, hidden, dormant and out of the physics until acquired again
+#[derive(Component)]
+pub struct Pooled;
+
+/// Frozen in place with its AI suspended until the timer finishes
+#[derive(Component)]
+pub struct Stunned {
+	pub timer: Timer,
+}
+
+/// Marks where the flashlight points, positioned by `update_reticle`
+#[derive(Default, Component)]
+pub struct Reticle;
+
+/// Red edge around the screen that pulses during a `LastStand`
+#[derive(Default, Component)]
+pub struct Vignette;
+
+/// Full screen white sprite in front of the darkness, lit up by the flash burst and faded out
+#[derive(Default, Component)]
+pub struct FlashOverlay;
+
+/// Enemy waiting for its turn to activate after the level loads, it can't think or touch anything
+///
+/// `layers` are the real collision layers, put back on activation.
+#[derive(Component)]
+pub struct Dormant {
+	pub layers: CollisionLayers,
+}
+
+/// Tints the sprite white until the timer finishes
+#[derive(Component)]
+pub struct HitFlash {
+	pub timer: Timer,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub enum EnemyState {
+	#[default]
+	Idle,
+	Chase,
+	/// Heading to where another enemy spotted the player, see `Alert`
+	Search,
+	/// Gave up the chase past its `Leash` and is walking back, blind to the player until it gets there
+	Return,
+}
+
+/// Last alert this enemy heard and the cooldown before it can send or receive another one
+#[derive(Default, Component)]
+pub struct Alert {
+	pub target: Vec2,
+	pub cooldown: Timer,
+}
+
+/// "!" or "?" child over an enemy while `Settings.alert_icons` is on, see `update_alert_icons`
+#[derive(Default, Component)]
+pub struct AlertIcon;
+
+/// How far an enemy chases from where it spawned before giving up and heading back
+///
+/// `radius` is the `leash_radius` field of the LDtk entity, without it the enemy is never leashed.
+/// `home` is filled in by `set_leash_home` once the enemy is placed.
+#[derive(Default, Component)]
+pub struct Leash {
+	pub radius: Option<f32>,
+	pub home: Vec2,
+}
+
+impl Leash {
+	pub fn strayed(&self, translation: Vec2) -> bool {
+		self.radius.is_some_and(|radius| translation.distance(self.home) > radius)
+	}
+}
+
+impl From<EntityInstance> for Leash {
+	fn from(entity_instance: EntityInstance) -> Self {
+		let radius = entity_instance.field_instances.iter().find_map(|field| match (field.identifier.as_ref(), &field.value) {
+			("leash_radius", FieldValue::Int(Some(radius))) => Some(*radius as f32),
+			("leash_radius", FieldValue::Float(Some(radius))) => Some(*radius),
+			_ => None,
+		});
+		Self { radius, ..Default::default() }
+	}
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum PatrolMode {
+	/// Back to the first point after the last
+	#[default]
+	Loop,
+	/// Walks the points back in reverse after the last
+	PingPong,
+}
+
+/// Points an idle enemy walks through in order, from the `patrol` point array field of the
+/// LDtk entity and its `patrol_mode` ("Loop" or "PingPong")
+///
+/// Without points the enemy wanders around its `Leash.home` instead, see `patrol_movement`.
+#[derive(Default, Component)]
+pub struct PatrolRoute {
+	pub points: Vec<Vec2>,
+	pub mode: PatrolMode,
+	/// Index of the point it is heading to
+	pub next: usize,
+	pub backwards: bool,
+	/// Where a wandering enemy is heading, and how long it stands still after getting there
+	pub wander: Option<Vec2>,
+	pub rest: Timer,
+}
+
+impl PatrolRoute {
+	pub fn advance(&mut self) {
+		let last = self.points.len().saturating_sub(1);
+		if last == 0 { return }
+		match self.mode {
+			PatrolMode::Loop => self.next = (self.next + 1) % self.points.len(),
+			PatrolMode::PingPong => {
+				if self.next == last { self.backwards = true }
+				if self.next == 0 { self.backwards = false }
+				self.next = if self.backwards { self.next - 1 } else { self.next + 1 };
+			}
+		}
+	}
+}
+
+impl LdtkEntity for PatrolRoute {
+	fn bundle_entity(
+		entity_instance: &EntityInstance,
+		layer_instance: &LayerInstance,
+		_: Option<&Handle<Image>>,
+		_: Option<&TilesetDefinition>,
+		_: &AssetServer,
+		_: &mut Assets<TextureAtlas>,
+	) -> Self {
+		let grid_size = IVec2::splat(layer_instance.grid_size);
+		let mut route = PatrolRoute::default();
+		for field in entity_instance.field_instances.iter() {
+			match (field.identifier.as_ref(), &field.value) {
+				("patrol", FieldValue::Points(points)) => route.points = points.iter().flatten().map(|point| {
+					utils::ldtk_grid_coords_to_translation_centered(*point, layer_instance.c_hei, grid_size)
+				}).collect(),
+				("patrol_mode", FieldValue::Enum(Some(mode)) | FieldValue::String(Some(mode))) if mode == "PingPong" => route.mode = PatrolMode::PingPong,
+				_ => (),
+			}
+		}
+		route
+	}
+}
+
+/// Swing of an enemy whose kind `attacks`, `windup` runs from starting it until it lands
+#[derive(Default, Component)]
+pub struct Attack {
+	pub windup: Option<Timer>,
+	pub cooldown: Timer,
+}
+
+/// Enemy that just appeared and can't hurt yet, it fades in until the timer finishes
+#[derive(Component)]
+pub struct SpawnImmunity {
+	pub timer: Timer,
+}
+
+/// Cell centers left to walk towards the player, the next one is last
+#[derive(Default, Component)]
+pub struct NavPath {
+	pub waypoints: Vec<Vec2>,
+	pub repath: Timer,
+}
+
+/// Whether the enemy saw the player this tick, computed once by `enemy_vision`
+#[derive(Default, Component)]
+pub struct Vision {
+	pub can_see_player: bool,
+	/// Seconds since the current chase started, kept by `enemy_movement`
+	pub chase_time: f32,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ProjectileKind {
+	/// Flies along the aim it was fired with
+	Straight,
+	/// Slower, curves towards the player and expires after a while so it can be outrun
+	Homing,
+}
+
+/// Ranged attack of an enemy, read from the `projectile` enum field of the LDtk enemy
+///
+/// `Straight` or `Homing`, a missing field or any other value leaves the enemy melee only.
+#[derive(Default, Component)]
+pub struct Shooter {
+	pub kind: Option<ProjectileKind>,
+	pub cooldown: Timer,
+}
+
+impl From<EntityInstance> for Shooter {
+	fn from(entity_instance: EntityInstance) -> Self {
+		let kind = entity_instance.field_instances.iter().find_map(|field| match (field.identifier.as_ref(), &field.value) {
+			("projectile", FieldValue::Enum(Some(kind)) | FieldValue::String(Some(kind))) => Some(kind.clone()),
+			_ => None,
+		});
+		Self {
+			kind: match kind.as_deref() {
+				Some("Straight") => Some(ProjectileKind::Straight),
+				Some("Homing") => Some(ProjectileKind::Homing),
+				_ => None,
+			},
+			cooldown: Timer::default(),
+		}
+	}
+}
+
+#[derive(Component)]
+pub struct Projectile {
+	pub damage: f32,
+}
+
+/// Radians per second a homing projectile can turn towards the player
+#[derive(Component)]
+pub struct Homing {
+	pub turn_rate: f32,
+}
+
+/// The player's recent positions as a `Mimic` saw them, replayed as `ScentMarker`s from where it stands
+#[derive(Default, Component)]
+pub struct ScentTrail {
+	pub history: VecDeque<Vec2>,
+	pub timer: Timer,
+	/// Next sample of `history` to replay
+	pub replay: usize,
+}
+
+/// Fake player noise, enemies that can't see the player go search it while it lasts
+#[derive(Component)]
+pub struct ScentMarker;
+
 #[derive(Bundle, LdtkEntity)]
 pub struct EnemyBundle {
 	#[from_entity_instance]
 	#[bundle]
 	collider: ColliderEntityBundle,
-	#[sprite_sheet_bundle("texture/enemy.png", 32.0, 32.0, 4, 1, 0.0, 0)]
+	#[ldtk_entity]
 	#[bundle]
-	sprite_sheet_bundle: SpriteSheetBundle,
+	animated_sprite: AnimatedSpriteBundle,
 	enemy: Enemy,
 	#[from_entity_instance]
-	#[bundle]
-	timer_bundle: TimerBundle,
+	kind: EnemyKind,
+	#[from_entity_instance]
+	health: Health,
+	#[from_entity_instance]
+	shooter: Shooter,
+	#[from_entity_instance]
+	leash: Leash,
+	#[ldtk_entity]
+	patrol: PatrolRoute,
+	attack: Attack,
+	scent: ScentTrail,
+	state: EnemyState,
+	alert: Alert,
+	vision: Vision,
+	path: NavPath,
 }
 
+/// Turns into an enemy when its level loads, of a kind rolled against the level's `SpawnTable`
+///
+/// The rest of the enemy (sprite, size, projectile...) comes from the spawn point's own fields.
+#[derive(Default, Component)]
+pub struct SpawnPoint;
+
+#[derive(Bundle, LdtkEntity)]
+pub struct SpawnPointBundle {
+	spawn_point: SpawnPoint,
+	#[from_entity_instance]
+	instance: EntityInstance,
+}
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
 pub struct Wall;
@@ -126,9 +791,25 @@ pub struct WallBundle {
 	wall: Wall,
 }
 
+/// Int grid value 2, enemies that respect safe zones never follow the player into one
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct SafeZone;
+
+#[derive(Clone, Debug, Default, Bundle, LdtkIntCell)]
+pub struct SafeZoneBundle {
+	safe_zone: SafeZone,
+}
+
 #[derive(Default, Component)]
 pub struct Win;
 
+#[derive(Default, Component)]
+pub struct WinUi;
+
+/// Countdown text shown during the `LevelGrace`
+#[derive(Component)]
+pub struct GraceUi;
+
 #[derive(Bundle, LdtkEntity)]
 pub struct WinBundle {
 	#[from_entity_instance]
@@ -141,3 +822,280 @@ pub struct WinBundle {
 	win: Win,
 }
 
+
+#[derive(Default, Component)]
+pub struct Collectible;
+
+/// Tells the collectibles of a level apart across reloads, LDtk entities here have no iid
+#[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Hash, Debug, Default, Component)]
+pub struct CollectibleId {
+	pub def_uid: i32,
+	pub px: IVec2,
+}
+
+impl From<EntityInstance> for CollectibleId {
+	fn from(entity_instance: EntityInstance) -> Self {
+		Self { def_uid: entity_instance.def_uid, px: entity_instance.px }
+	}
+}
+
+#[derive(Bundle, LdtkEntity)]
+pub struct CollectibleBundle {
+	#[from_entity_instance]
+	#[bundle]
+	collider_bundle: ColliderEntityBundle,
+	// Usa el tile elegido en LDtk, asi cada coleccionable puede tener su propio sprite
+	#[sprite_bundle]
+	#[bundle]
+	pub sprite_bundle: SpriteBundle,
+
+	collectible: Collectible,
+	#[from_entity_instance]
+	id: CollectibleId,
+}
+
+/// Moves back and forth between where it was placed in LDtk and its `end` point field
+#[derive(Default, Component)]
+pub struct Patroller {
+	pub start: Vec2,
+	pub end: Vec2,
+	pub speed: f32,
+	pub towards_end: bool,
+}
+
+impl LdtkEntity for Patroller {
+	fn bundle_entity(
+		entity_instance: &EntityInstance,
+		layer_instance: &LayerInstance,
+		_: Option<&Handle<Image>>,
+		_: Option<&TilesetDefinition>,
+		_: &AssetServer,
+		_: &mut Assets<TextureAtlas>,
+	) -> Self {
+		let grid_size = IVec2::splat(layer_instance.grid_size);
+		let start = utils::ldtk_pixel_coords_to_translation_pivoted(
+			entity_instance.px,
+			layer_instance.c_hei * layer_instance.grid_size,
+			IVec2::new(entity_instance.width, entity_instance.height),
+			entity_instance.pivot,
+		);
+		let mut patroller = Patroller { start, end: start, speed: 50., towards_end: true };
+
+		for field in entity_instance.field_instances.iter() {
+			match (field.identifier.as_ref(), &field.value) {
+				("end", FieldValue::Point(Some(end))) => patroller.end = utils::ldtk_grid_coords_to_translation_centered(
+					*end,
+					layer_instance.c_hei,
+					grid_size,
+				),
+				("speed", FieldValue::Float(Some(speed))) => patroller.speed = *speed,
+				_ => (),
+			}
+		}
+		patroller
+	}
+}
+
+#[derive(Bundle, LdtkEntity)]
+pub struct PatrollerBundle {
+	#[from_entity_instance]
+	#[bundle]
+	collider_bundle: ColliderEntityBundle,
+	// El jugador se para encima, no lo empuja
+	sensor: SensorShape,
+	#[sprite_bundle]
+	#[bundle]
+	pub sprite_bundle: SpriteBundle,
+	#[ldtk_entity]
+	patroller: Patroller,
+}
+
+/// Static light placed in the level, read from the LDtk fields `radius`, `color`, `flicker` and `burns`
+#[derive(Clone, Component)]
+pub struct LightSource {
+	pub radius: f32,
+	pub color: Color,
+	/// Torch-like, the glow wavers in size and brightness
+	pub flicker: bool,
+	/// Burns `LightVulnerable` enemies inside the radius like the flashlight cone does
+	pub burns: bool,
+}
+
+impl From<EntityInstance> for LightSource {
+	fn from(entity_instance: EntityInstance) -> Self {
+		let mut light = Self {
+			radius: 64.0,
+			color: Color::rgb(1.0, 0.75, 0.4),
+			flicker: false,
+			burns: false,
+		};
+		for field in entity_instance.field_instances.iter() {
+			match (field.identifier.as_ref(), &field.value) {
+				("radius", FieldValue::Float(Some(radius))) => light.radius = *radius,
+				("radius", FieldValue::Int(Some(radius))) => light.radius = *radius as f32,
+				("color", FieldValue::Color(color)) => light.color = *color,
+				("flicker", FieldValue::Bool(flicker)) => light.flicker = *flicker,
+				("burns", FieldValue::Bool(burns)) => light.burns = *burns,
+				_ => (),
+			}
+		}
+		light
+	}
+}
+
+#[derive(Bundle, LdtkEntity)]
+pub struct LightBundle {
+	#[from_entity_instance]
+	light: LightSource,
+	#[from_entity_instance]
+	linked: Linked,
+}
+
+/// Lever toggled with E, powers every `Linked` entity whose `id` matches its `target` field
+#[derive(Component)]
+pub struct Switch {
+	pub target: String,
+	pub on: bool,
+}
+
+impl From<EntityInstance> for Switch {
+	fn from(entity_instance: EntityInstance) -> Self {
+		let mut switch = Self { target: String::new(), on: false };
+		for field in entity_instance.field_instances.iter() {
+			match (field.identifier.as_ref(), &field.value) {
+				("target", FieldValue::String(Some(target))) => switch.target = target.clone(),
+				("on", FieldValue::Bool(on)) => switch.on = *on,
+				_ => (),
+			}
+		}
+		switch
+	}
+}
+
+#[derive(Bundle, LdtkEntity)]
+pub struct SwitchBundle {
+	#[sprite_bundle]
+	#[bundle]
+	pub sprite_bundle: SpriteBundle,
+	#[from_entity_instance]
+	switch: Switch,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum SwitchLogic {
+	/// Powered while any of its switches is on
+	#[default]
+	Any,
+	/// Powered only while all of its switches are on
+	All,
+}
+
+/// Something switches can power, read from the LDtk fields `id` and `logic` ("Any" or "All")
+///
+/// Without an `id` nothing controls it, lights stay lit and gates stay shut.
+#[derive(Component)]
+pub struct Linked {
+	pub id: Option<String>,
+	pub logic: SwitchLogic,
+	pub powered: bool,
+}
+
+impl From<EntityInstance> for Linked {
+	fn from(entity_instance: EntityInstance) -> Self {
+		let mut linked = Self { id: None, logic: SwitchLogic::default(), powered: true };
+		for field in entity_instance.field_instances.iter() {
+			match (field.identifier.as_ref(), &field.value) {
+				("id", FieldValue::String(Some(id))) if !id.is_empty() => {
+					linked.id = Some(id.clone());
+					linked.powered = false;
+				}
+				("logic", FieldValue::Enum(Some(logic)) | FieldValue::String(Some(logic))) if logic == "All" => linked.logic = SwitchLogic::All,
+				_ => (),
+			}
+		}
+		linked
+	}
+}
+
+/// Wall that opens while its switches power it
+#[derive(Default, Component)]
+pub struct Gate;
+
+#[derive(Bundle, LdtkEntity)]
+pub struct GateBundle {
+	#[from_entity_instance]
+	#[bundle]
+	collider_bundle: ColliderEntityBundle,
+	#[sprite_bundle]
+	#[bundle]
+	pub sprite_bundle: SpriteBundle,
+	gate: Gate,
+	#[from_entity_instance]
+	linked: Linked,
+}
+
+/// Locker, bush... the player can step into with E to hide, see `hiding::toggle_hiding`
+#[derive(Default, Component)]
+pub struct HidingSpot;
+
+#[derive(Bundle, LdtkEntity)]
+pub struct HidingSpotBundle {
+	#[sprite_bundle]
+	#[bundle]
+	pub sprite_bundle: SpriteBundle,
+	hiding_spot: HidingSpot,
+}
+
+/// Player inside a `HidingSpot`, enemies can't see or smell them and they can't move until
+/// they step back out to `exit`
+///
+/// `timer` runs the step in, and again the step out once `leaving` is set.
+#[derive(Component)]
+pub struct Hidden {
+	pub spot: Vec2,
+	pub exit: Vec2,
+	pub timer: Timer,
+	pub leaving: bool,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const IDENTIFIERS: [&str; 8] = ["Player", "Enemy", "Wall", "Gate", "Win", "Collectible", "Patroller", "Projectile"];
+
+	fn collides(a: &str, b: &str) -> bool {
+		layers_for(a).interacts_with(layers_for(b))
+	}
+
+	#[test]
+	fn interaction_matrix() {
+		let expected = [
+			("Player", "Enemy"), ("Player", "Wall"), ("Player", "Gate"), ("Player", "Win"),
+			("Player", "Collectible"), ("Player", "Patroller"), ("Player", "Projectile"),
+			("Enemy", "Enemy"), ("Enemy", "Wall"), ("Enemy", "Gate"),
+			("Wall", "Wall"), ("Wall", "Gate"), ("Gate", "Gate"),
+		];
+		for a in IDENTIFIERS {
+			for b in IDENTIFIERS {
+				let should = expected.contains(&(a, b)) || expected.contains(&(b, a));
+				assert_eq!(collides(a, b), should, "{} with {}", a, b);
+			}
+		}
+	}
+
+	#[test]
+	fn unknown_identifiers_collide_with_nothing() {
+		for identifier in IDENTIFIERS {
+			assert!(!collides("Decoration", identifier));
+		}
+	}
+
+	#[test]
+	fn colliders_get_their_layers() {
+		for identifier in ["Player", "Enemy", "Gate", "Win", "Collectible", "Patroller"] {
+			let bundle = ColliderEntityBundle::from(EntityInstance { identifier: identifier.to_string(), ..Default::default() });
+			assert_eq!(bundle.collision_layer, layers_for(identifier));
+		}
+	}
+}
@@ -0,0 +1,377 @@
+use std::{collections::HashMap, f32::consts::TAU};
+
+use bevy::{
+	diagnostic::{Diagnostics, EntityCountDiagnosticsPlugin, FrameTimeDiagnosticsPlugin},
+	input::mouse::MouseWheel,
+	math::Mat2,
+	prelude::*,
+	sprite::Mesh2dHandle,
+};
+use bevy_ecs_ldtk::prelude::*;
+use bevy_prototype_debug_lines::DebugLines;
+use heron::{prelude::*, rapier_plugin::PhysicsWorld};
+
+use crate::{components::*, navigation::NavGrid, resources::*, settings::Settings, systems::line_of_sight, ui::UiFont};
+
+// Las lineas se ordenan por el z de su mesh, lo subimos para que queden encima del nivel
+const DEBUG_LINES_Z: f32 = 900.0;
+const CIRCLE_SEGMENTS: usize = 32;
+// Unidades por segundo con zoom 1, con mas zoom se mueve mas lento
+const FREE_LOOK_SPEED: f32 = 600.0;
+const FREE_LOOK_ZOOM_STEP: f32 = 0.1;
+// Por encima de la oscuridad y las zonas seguras, por debajo de la mira
+const HEATMAP_Z: f32 = 999.05;
+// Alfa por cada enemigo que ve la celda
+const HEATMAP_ALPHA_STEP: f32 = 0.15;
+const HEATMAP_MAX_ALPHA: f32 = 0.6;
+
+#[derive(Component)]
+pub struct HeatCell;
+
+#[derive(Component)]
+pub struct InspectorText;
+
+/// Entity picked with the inspector, None until something is clicked
+#[derive(Default)]
+pub struct Inspected(pub Option<Entity>);
+
+pub fn toggle_debug(
+	input: Res<Input<KeyCode>>,
+	mut debug_config: ResMut<DebugConfig>,
+) {
+	if input.just_pressed(KeyCode::F1) { debug_config.ai = !debug_config.ai }
+	if input.just_pressed(KeyCode::F2) { debug_config.free_look = !debug_config.free_look }
+	if input.just_pressed(KeyCode::F3) { debug_config.diagnostics = !debug_config.diagnostics }
+	if input.just_pressed(KeyCode::F4) { debug_config.grid = !debug_config.grid }
+	if input.just_pressed(KeyCode::F5) { debug_config.heatmap = !debug_config.heatmap }
+	if input.just_pressed(KeyCode::F6) { debug_config.inspector = !debug_config.inspector }
+}
+
+/// Pans and zooms the camera while free look is on, restoring the zoom when it's turned off
+pub fn free_look_camera(
+	time: Res<Time>,
+	input: Res<Input<KeyCode>>,
+	debug_config: Res<DebugConfig>,
+	mut mouse_wheel: EventReader<MouseWheel>,
+	mut saved_scale: Local<Option<f32>>,
+	mut camera_query: Query<(&mut Transform, &mut OrthographicProjection), With<MainCamera>>,
+	mut darkness_query: Query<&mut Visibility, With<Darkness>>,
+) {
+	for mut visibility in darkness_query.iter_mut() {
+		visibility.is_visible = !debug_config.free_look;
+	}
+	let (mut transform, mut projection) = camera_query.single_mut();
+	if !debug_config.free_look {
+		if let Some(scale) = saved_scale.take() { projection.scale = scale }
+		return;
+	}
+	saved_scale.get_or_insert(projection.scale);
+
+	let pressed = |keys: [KeyCode; 2]| keys.iter().any(|key| input.pressed(*key));
+	let mut delta = Vec2::ZERO;
+	if pressed([KeyCode::W, KeyCode::Up]) { delta.y += 1.0 }
+	if pressed([KeyCode::A, KeyCode::Left]) { delta.x -= 1.0 }
+	if pressed([KeyCode::S, KeyCode::Down]) { delta.y -= 1.0 }
+	if pressed([KeyCode::D, KeyCode::Right]) { delta.x += 1.0 }
+	transform.translation += (delta.normalize_or_zero() * FREE_LOOK_SPEED * projection.scale * time.delta_seconds()).extend(0.0);
+
+	for event in mouse_wheel.iter() {
+		projection.scale = (projection.scale * (1.0 - event.y * FREE_LOOK_ZOOM_STEP)).clamp(0.1, 10.0);
+	}
+}
+
+pub fn raise_debug_lines(mut query: Query<&mut Transform, Added<Mesh2dHandle>>) {
+	for mut transform in query.iter_mut() {
+		transform.translation.z = DEBUG_LINES_Z;
+	}
+}
+
+fn circle(lines: &mut DebugLines, center: Vec3, radius: f32, color: Color) {
+	let point = |i: usize| {
+		let angle = i as f32 / CIRCLE_SEGMENTS as f32 * TAU;
+		center + Vec3::new(angle.cos(), angle.sin(), 0.0) * radius
+	};
+	for i in 0..CIRCLE_SEGMENTS {
+		lines.line_colored(point(i), point(i + 1), 0.0, color);
+	}
+}
+
+fn cone(lines: &mut DebugLines, origin: Vec3, flashlight: &Flashlight, color: Color) {
+	let edge = |angle: f32| origin + (Mat2::from_angle(angle) * flashlight.direction * flashlight.mode.range()).extend(0.0);
+	let half_angle = flashlight.mode.half_angle();
+	let (left, right) = (edge(half_angle), edge(-half_angle));
+	lines.line_colored(origin, left, 0.0, color);
+	lines.line_colored(origin, right, 0.0, color);
+	lines.line_colored(left, right, 0.0, color);
+}
+
+/// Draws each enemy's detection radius, a line to whatever it is chasing and the lit flashlight cone
+pub fn debug_enemy_ai(
+	debug_config: Res<DebugConfig>,
+	enemy_config: Res<EnemyConfig>,
+	difficulty: Res<LevelDifficulty>,
+	settings: Res<Settings>,
+	mut lines: ResMut<DebugLines>,
+	player_query: Query<(&Transform, &Flashlight), With<Player>>,
+	enemy_query: Query<(&Transform, &EnemyState, &Alert), With<Enemy>>,
+) {
+	if !debug_config.ai { return }
+
+	let player = player_query.get_single().ok();
+	if let Some((Transform { translation, .. }, flashlight)) = player.filter(|(_, flashlight)| flashlight.on) {
+		cone(&mut lines, *translation, flashlight, Color::YELLOW);
+	}
+	let player_translation = player.map(|(t, _)| t.translation);
+	for (Transform { translation, .. }, &state, alert) in enemy_query.iter() {
+		let color = settings.palette.state_color(state);
+		circle(&mut lines, *translation, enemy_config.detection_range * difficulty.enemy_sight_mult, color);
+		match (state, player_translation) {
+			(EnemyState::Chase, Some(target)) => lines.line_colored(*translation, target, 0.0, color),
+			(EnemyState::Search, _) => lines.line_colored(*translation, alert.target.extend(translation.z), 0.0, color),
+			_ => (),
+		}
+	}
+}
+
+/// Grid lines over the loaded level, they follow the `NavGrid` so they change with the level
+pub fn debug_grid(
+	debug_config: Res<DebugConfig>,
+	nav_grid: Res<NavGrid>,
+	mut lines: ResMut<DebugLines>,
+) {
+	if !debug_config.grid || nav_grid.is_empty() { return }
+	let size = nav_grid.grid_size as f32;
+	let (width, height) = (nav_grid.width as f32 * size, nav_grid.height as f32 * size);
+	let point = |x: f32, y: f32| (nav_grid.offset + Vec2::new(x, y)).extend(0.0);
+	for x in 0..=nav_grid.width {
+		let x = x as f32 * size;
+		lines.line_colored(point(x, 0.0), point(x, height), 0.0, debug_config.grid_color);
+	}
+	for y in 0..=nav_grid.height {
+		let y = y as f32 * size;
+		lines.line_colored(point(0.0, y), point(width, y), 0.0, debug_config.grid_color);
+	}
+}
+
+/// Counts, for each walkable cell, the enemies in range of it with line of sight and tints it
+#[allow(clippy::too_many_arguments)]
+pub fn debug_heatmap(
+	mut commands: Commands,
+	time: Res<Time>,
+	physics_world: PhysicsWorld,
+	debug_config: Res<DebugConfig>,
+	enemy_config: Res<EnemyConfig>,
+	difficulty: Res<LevelDifficulty>,
+	nav_grid: Res<NavGrid>,
+	mut refresh: Local<Option<Timer>>,
+	enemy_query: Query<&Transform, (With<Enemy>, Without<Pooled>, Without<Dormant>, Without<DyingEnemy>)>,
+	cell_query: Query<Entity, With<HeatCell>>,
+) {
+	if !debug_config.heatmap || nav_grid.is_empty() {
+		*refresh = None;
+		cell_query.for_each(|entity| commands.entity(entity).despawn());
+		return;
+	}
+	// El primer frame ya se pinta, luego solo cada heatmap_interval
+	let timer = refresh.get_or_insert_with(|| Timer::from_seconds(0.0, false));
+	if !timer.tick(time.delta()).finished() { return }
+	*timer = Timer::from_seconds(debug_config.heatmap_interval, false);
+
+	cell_query.for_each(|entity| commands.entity(entity).despawn());
+	let range = enemy_config.detection_range * difficulty.enemy_sight_mult;
+	let cells = (range / nav_grid.grid_size as f32).ceil() as i32;
+	let mut seen: HashMap<GridCoords, usize> = HashMap::new();
+	for Transform { translation, .. } in enemy_query.iter() {
+		let center = nav_grid.to_grid(translation.truncate());
+		for x in center.x - cells..=center.x + cells {
+			for y in center.y - cells..=center.y + cells {
+				let coords = GridCoords { x, y };
+				if !nav_grid.is_walkable(coords) { continue }
+				let cell = nav_grid.to_translation(coords).extend(translation.z);
+				if cell.distance(*translation) < range && line_of_sight(*translation, cell, &physics_world) {
+					*seen.entry(coords).or_default() += 1;
+				}
+			}
+		}
+	}
+	for (coords, count) in seen {
+		commands.spawn_bundle(SpriteBundle {
+			sprite: Sprite {
+				color: Color::rgba(1.0, 0.2, 0.1, (count as f32 * HEATMAP_ALPHA_STEP).min(HEATMAP_MAX_ALPHA)),
+				custom_size: Some(Vec2::splat(nav_grid.grid_size as f32)),
+				..Default::default()
+			},
+			transform: Transform::from_translation(nav_grid.to_translation(coords).extend(HEATMAP_Z)),
+			..Default::default()
+		}).insert(HeatCell);
+	}
+}
+
+pub fn setup_diagnostics(
+	mut commands: Commands,
+	font: Res<UiFont>,
+) {
+	let style = font.style(20.0, Color::WHITE);
+	commands.spawn_bundle(TextBundle {
+		style: Style {
+			position_type: PositionType::Absolute,
+			position: Rect {
+				top: Val::Px(5.0),
+				right: Val::Px(5.0),
+				..Default::default()
+			},
+			..Default::default()
+		},
+		text: Text {
+			sections: vec![
+				TextSection { value: String::new(), style: style.clone() },
+				TextSection { value: String::new(), style: style.clone() },
+				TextSection { value: String::new(), style },
+			],
+			..Default::default()
+		},
+		visibility: Visibility { is_visible: false },
+		..Default::default()
+	}).insert(DiagnosticsText);
+}
+
+pub fn update_diagnostics(
+	debug_config: Res<DebugConfig>,
+	diagnostics: Res<Diagnostics>,
+	mut query: Query<(&mut Text, &mut Visibility), With<DiagnosticsText>>,
+) {
+	let (mut text, mut visibility) = query.single_mut();
+	visibility.is_visible = debug_config.diagnostics;
+	if !debug_config.diagnostics { return }
+
+	let average = |id| diagnostics.get(id).and_then(|d| d.average()).unwrap_or_default();
+	text.sections[0].value = format!("FPS: {:.0}\n", average(FrameTimeDiagnosticsPlugin::FPS));
+	text.sections[1].value = format!("Frame: {:.2} ms\n", average(FrameTimeDiagnosticsPlugin::FRAME_TIME) * 1000.0);
+	text.sections[2].value = format!("Entities: {:.0}", average(EntityCountDiagnosticsPlugin::ENTITY_COUNT));
+}
+
+/// Half size of the box around a collider, curved shapes get the box that holds them
+fn half_extents(shape: &CollisionShape) -> Vec2 {
+	match shape {
+		CollisionShape::Cuboid { half_extends, .. } => half_extends.truncate(),
+		CollisionShape::Sphere { radius } => Vec2::splat(*radius),
+		CollisionShape::Capsule { half_segment, radius } => Vec2::new(*radius, half_segment + radius),
+		_ => Vec2::ZERO,
+	}
+}
+
+/// Clicking picks the smallest moving body under the cursor, walls and other static colliders
+/// are never picked
+pub fn select_inspected(
+	debug_config: Res<DebugConfig>,
+	mouse_input: Res<Input<MouseButton>>,
+	mut inspected: ResMut<Inspected>,
+	camera_query: Query<(&Transform, &MainCamera)>,
+	query: Query<(Entity, &Transform, &CollisionShape), (With<Velocity>, Without<Pooled>)>,
+) {
+	if !debug_config.inspector {
+		inspected.0 = None;
+		return;
+	}
+	if !mouse_input.just_pressed(MouseButton::Left) { return }
+	let (camera_transform, mc) = camera_query.single();
+	let cursor = camera_transform.translation.truncate() + mc.last_cursor_position;
+	inspected.0 = query.iter()
+		.map(|(entity, transform, shape)| (entity, transform.translation.truncate(), half_extents(shape)))
+		.filter(|(_, center, half)| (cursor - *center).abs().cmple(*half).all())
+		.min_by(|(_, _, a), (_, _, b)| (a.x * a.y).total_cmp(&(b.x * b.y)))
+		.map(|(entity, ..)| entity);
+}
+
+pub fn setup_inspector(
+	mut commands: Commands,
+	font: Res<UiFont>,
+) {
+	commands.spawn_bundle(TextBundle {
+		style: Style {
+			position_type: PositionType::Absolute,
+			position: Rect {
+				top: Val::Px(5.0),
+				left: Val::Px(5.0),
+				..Default::default()
+			},
+			..Default::default()
+		},
+		text: Text::with_section("", font.style(20.0, Color::WHITE), Default::default()),
+		visibility: Visibility { is_visible: false },
+		..Default::default()
+	}).insert(InspectorText);
+}
+
+/// Refreshes the panel every frame and outlines the inspected entity's collider, with a line to
+/// wherever its AI is heading
+pub fn update_inspector(
+	debug_config: Res<DebugConfig>,
+	mut inspected: ResMut<Inspected>,
+	mut lines: ResMut<DebugLines>,
+	query: Query<(&Transform, &CollisionShape, Option<&Velocity>, Option<&Health>, Option<&EnemyKind>, Option<&EnemyState>, Option<&NavPath>, Option<&Alert>)>,
+	flags_query: Query<(Option<&Invincible>, Option<&Stunned>, Option<&Dormant>, Option<&SpawnImmunity>, Option<&RespawnProtection>)>,
+	mut text_query: Query<(&mut Text, &mut Visibility), With<InspectorText>>,
+) {
+	let (mut text, mut visibility) = text_query.single_mut();
+	// Si se despawnea deja de estar seleccionado
+	let selected = inspected.0.and_then(|entity| query.get(entity).ok().map(|components| (entity, components)));
+	if selected.is_none() { inspected.0 = None }
+	visibility.is_visible = debug_config.inspector;
+	let (entity, (transform, shape, velocity, health, kind, state, path, alert)) = match selected {
+		Some(selected) => selected,
+		None => {
+			text.sections[0].value = "Click an entity to inspect it".to_string();
+			return;
+		}
+	};
+
+	let translation = transform.translation;
+	let half = half_extents(shape);
+	let mut value = format!("{:?}\nPosition: {:.0}, {:.0}\nCollider: {:.0} x {:.0}", entity, translation.x, translation.y, half.x * 2.0, half.y * 2.0);
+	if let Some(health) = health {
+		value += &format!("\nHealth: {} / {}", health.current, health.max);
+	}
+	if let Some(velocity) = velocity {
+		let linear = velocity.linear.truncate();
+		value += &format!("\nVelocity: {:.0}, {:.0} ({:.0})", linear.x, linear.y, linear.length());
+	}
+	if let Some(kind) = kind {
+		value += &format!("\nKind: {:?}", kind);
+	}
+	// El mismo objetivo que sigue enemy_movement, el siguiente waypoint si tiene camino
+	let target = match (state, path, alert) {
+		(Some(state), Some(path), Some(alert)) => {
+			value += &format!("\nState: {:?}", state);
+			match state {
+				EnemyState::Chase | EnemyState::Return => path.waypoints.last().copied(),
+				EnemyState::Search => Some(path.waypoints.last().copied().unwrap_or(alert.target)),
+				_ => None,
+			}
+		}
+		_ => None,
+	};
+	if let Some(target) = target {
+		value += &format!("\nTarget: {:.0}, {:.0}", target.x, target.y);
+		lines.line_colored(translation, target.extend(translation.z), 0.0, Color::CYAN);
+	}
+	if let Ok((invincible, stunned, dormant, immunity, protection)) = flags_query.get(entity) {
+		let flags: Vec<&str> = [
+			(invincible.is_some(), "Invincible"),
+			(stunned.is_some(), "Stunned"),
+			(dormant.is_some(), "Dormant"),
+			(immunity.is_some(), "Spawn Immunity"),
+			(protection.is_some(), "Respawn Protection"),
+		].into_iter().filter(|(on, _)| *on).map(|(_, name)| name).collect();
+		if !flags.is_empty() {
+			value += &format!("\n{}", flags.join(", "));
+		}
+	}
+	text.sections[0].value = value;
+
+	let corner = |x: f32, y: f32| translation + Vec3::new(half.x * x, half.y * y, 0.0);
+	let corners = [corner(-1.0, -1.0), corner(1.0, -1.0), corner(1.0, 1.0), corner(-1.0, 1.0)];
+	for i in 0..corners.len() {
+		lines.line_colored(corners[i], corners[(i + 1) % corners.len()], 0.0, Color::CYAN);
+	}
+}
@@ -0,0 +1,132 @@
+use std::fs;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{resources::*, settings::Settings};
+
+// Se lee al arrancar, asi se puede equilibrar sin recompilar
+const PRESETS_PATH: &str = "assets/difficulty.ron";
+
+#[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum Difficulty {
+	Easy,
+	#[default]
+	Normal,
+	Hard,
+}
+
+impl Difficulty {
+	pub fn next(self) -> Self {
+		match self {
+			Difficulty::Easy => Difficulty::Normal,
+			Difficulty::Normal => Difficulty::Hard,
+			Difficulty::Hard => Difficulty::Easy,
+		}
+	}
+}
+
+/// Multipliers over the default configs, 1.0 everywhere plays the game as tuned
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct DifficultyPreset {
+	pub enemy_speed: f32,
+	pub enemy_sight: f32,
+	/// Contact and projectile damage
+	pub enemy_damage: f32,
+	pub battery_drain: f32,
+}
+
+impl Default for DifficultyPreset {
+	fn default() -> Self {
+		Self {
+			enemy_speed: 1.0,
+			enemy_sight: 1.0,
+			enemy_damage: 1.0,
+			battery_drain: 1.0,
+		}
+	}
+}
+
+/// One preset per `Difficulty`, read from `assets/difficulty.ron` when it exists
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct DifficultyPresets {
+	pub easy: DifficultyPreset,
+	pub normal: DifficultyPreset,
+	pub hard: DifficultyPreset,
+}
+
+impl Default for DifficultyPresets {
+	fn default() -> Self {
+		Self {
+			easy: DifficultyPreset {
+				enemy_speed: 0.8,
+				enemy_sight: 0.8,
+				enemy_damage: 0.5,
+				battery_drain: 0.7,
+			},
+			normal: DifficultyPreset::default(),
+			hard: DifficultyPreset {
+				enemy_speed: 1.2,
+				enemy_sight: 1.25,
+				enemy_damage: 1.5,
+				battery_drain: 1.3,
+			},
+		}
+	}
+}
+
+impl DifficultyPresets {
+	pub fn load() -> Self {
+		let contents = match fs::read_to_string(PRESETS_PATH) {
+			Ok(contents) => contents,
+			Err(_) => return Self::default(),
+		};
+		ron::from_str(&contents).unwrap_or_else(|error| {
+			warn!("Ignoring invalid {}: {}", PRESETS_PATH, error);
+			Self::default()
+		})
+	}
+
+	pub fn get(&self, difficulty: Difficulty) -> &DifficultyPreset {
+		match difficulty {
+			Difficulty::Easy => &self.easy,
+			Difficulty::Normal => &self.normal,
+			Difficulty::Hard => &self.hard,
+		}
+	}
+}
+
+/// The difficulty the current run is played at, `Settings.difficulty` only takes over on a new game
+#[derive(Default)]
+pub struct ActiveDifficulty {
+	pub difficulty: Difficulty,
+	pub preset: DifficultyPreset,
+}
+
+/// Scales the default configs by the chosen preset at startup and on every new game
+#[allow(clippy::too_many_arguments)]
+pub fn apply_difficulty(
+	mut started: Local<bool>,
+	mut new_game: EventReader<NewGame>,
+	settings: Res<Settings>,
+	presets: Res<DifficultyPresets>,
+	mut active: ResMut<ActiveDifficulty>,
+	mut enemy_config: ResMut<EnemyConfig>,
+	mut combat_config: ResMut<CombatConfig>,
+	mut projectile_config: ResMut<ProjectileConfig>,
+) {
+	if new_game.iter().count() == 0 && *started { return }
+	*started = true;
+
+	let preset = presets.get(settings.difficulty).clone();
+	let (enemy_defaults, combat_defaults, projectile_defaults) = (EnemyConfig::default(), CombatConfig::default(), ProjectileConfig::default());
+	enemy_config.speed = enemy_defaults.speed * preset.enemy_speed;
+	enemy_config.detection_range = enemy_defaults.detection_range * preset.enemy_sight;
+	enemy_config.lose_range = enemy_defaults.lose_range * preset.enemy_sight;
+	combat_config.contact_damage = combat_defaults.contact_damage * preset.enemy_damage;
+	combat_config.attack_damage = combat_defaults.attack_damage * preset.enemy_damage;
+	projectile_config.damage = projectile_defaults.damage * preset.enemy_damage;
+	*active = ActiveDifficulty { difficulty: settings.difficulty, preset };
+}
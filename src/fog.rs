@@ -0,0 +1,138 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use heron::rapier_plugin::PhysicsWorld;
+
+use crate::{
+	components::*,
+	navigation::NavGrid,
+	systems::{int_grid_layer, layer_offset, line_of_sight},
+};
+
+// Encima de la oscuridad (998.9) y debajo de las zonas seguras (999)
+const FOG_Z: f32 = 998.95;
+
+/// Memory of the cells the player has seen in the current level, the rest stays pitch black
+///
+/// Off by default. Cleared when a different level loads, and on a restart too unless
+/// `persist_across_deaths` is set.
+pub struct FogOfWar {
+	pub enabled: bool,
+	pub persist_across_deaths: bool,
+	/// Cells this close to the player are always revealed, the lit flashlight reveals the rest
+	pub reveal_radius: f32,
+	/// Extra darkness over unexplored cells, on top of the level's ambient darkness
+	pub unexplored_alpha: f32,
+	pub level_uid: Option<i32>,
+	pub explored: HashSet<GridCoords>,
+}
+
+impl Default for FogOfWar {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			persist_across_deaths: true,
+			reveal_radius: 48.0,
+			unexplored_alpha: 0.9,
+			level_uid: None,
+			explored: HashSet::new(),
+		}
+	}
+}
+
+#[derive(Component)]
+pub struct FogCell {
+	coords: GridCoords,
+}
+
+/// Covers every unexplored cell of a level that just loaded
+pub fn spawn_fog(
+	mut commands: Commands,
+	mut level_events: EventReader<LevelEvent>,
+	mut fog: ResMut<FogOfWar>,
+	levels: Res<Assets<LdtkLevel>>,
+	level_query: Query<(Entity, &Handle<LdtkLevel>)>,
+) {
+	for event in level_events.iter() {
+		let uid = match event {
+			LevelEvent::Transformed(uid) => *uid,
+			_ => continue,
+		};
+		if !fog.enabled { continue }
+		// Morir recarga el mismo nivel, cambiar de nivel siempre empieza de cero
+		if fog.level_uid != Some(uid) || !fog.persist_across_deaths {
+			fog.explored.clear();
+		}
+		fog.level_uid = Some(uid);
+
+		for (level_entity, handle) in level_query.iter() {
+			let layer = match levels.get(handle).filter(|level| level.level.uid == uid).and_then(|level| int_grid_layer(&level.level)) {
+				Some(layer) => layer,
+				None => continue,
+			};
+			let size = layer.grid_size as f32;
+			let offset = layer_offset(layer);
+			for x in 0..layer.c_wid {
+				for y in 0..layer.c_hei {
+					let coords = GridCoords { x, y };
+					if fog.explored.contains(&coords) { continue }
+					let center = (Vec2::new(x as f32, y as f32) + 0.5) * size + offset;
+					commands.spawn_bundle(SpriteBundle {
+						sprite: Sprite {
+							color: Color::rgba(0.0, 0.0, 0.0, fog.unexplored_alpha),
+							custom_size: Some(Vec2::splat(size)),
+							..Default::default()
+						},
+						transform: Transform::from_translation(center.extend(FOG_Z)),
+						..Default::default()
+					})
+					.insert(FogCell { coords })
+					.insert(Parent(level_entity));
+				}
+			}
+		}
+	}
+}
+
+/// Explores the cells around the player and inside the lit flashlight cone that are in line of sight
+pub fn reveal_fog(
+	mut commands: Commands,
+	mut fog: ResMut<FogOfWar>,
+	nav_grid: Res<NavGrid>,
+	physics_world: PhysicsWorld,
+	player_query: Query<(&Transform, &Flashlight), With<Player>>,
+	cell_query: Query<(Entity, &FogCell)>,
+) {
+	if !fog.enabled || nav_grid.is_empty() { return }
+	let (Transform { translation: player_translation, .. }, flashlight) = match player_query.get_single() {
+		Ok(player) => player,
+		Err(_) => return,
+	};
+	let origin = player_translation.truncate();
+	let reach = if flashlight.on { fog.reveal_radius.max(flashlight.mode.range()) } else { fog.reveal_radius };
+
+	let (min, max) = (nav_grid.to_grid(origin - reach), nav_grid.to_grid(origin + reach));
+	let mut revealed = Vec::new();
+	for x in min.x..=max.x {
+		for y in min.y..=max.y {
+			let coords = GridCoords { x, y };
+			if fog.explored.contains(&coords) { continue }
+			let center = nav_grid.to_translation(coords);
+			let visible = center.distance(origin) <= fog.reveal_radius
+				|| (flashlight.on && flashlight.contains(origin, center));
+			// Solo las celdas nuevas pagan el raycast, las exploradas ya no se miran
+			if visible && line_of_sight(*player_translation, center.extend(player_translation.z), &physics_world) {
+				revealed.push(coords);
+			}
+		}
+	}
+	if revealed.is_empty() { return }
+
+	fog.explored.extend(revealed);
+	for (entity, cell) in cell_query.iter() {
+		if fog.explored.contains(&cell.coords) {
+			commands.entity(entity).despawn();
+		}
+	}
+}
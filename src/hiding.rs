@@ -0,0 +1,106 @@
+use bevy::prelude::*;
+use heron::{prelude::*, rapier_plugin::PhysicsWorld};
+
+use crate::{components::*, resources::*, systems::line_of_sight};
+
+// Igual que los interruptores, algo mas que medio tile
+pub const HIDE_REACH: f32 = 20.0;
+const HIDE_SECONDS: f32 = 0.25;
+
+/// Whether an enemy on the hunt has the spot in sight, nobody slips in or out under its nose
+fn watched(
+	spot: Vec2,
+	detection_range: f32,
+	physics_world: &PhysicsWorld,
+	enemy_query: &Query<(&mut EnemyState, &mut Alert, &Transform), (With<Enemy>, Without<Dormant>, Without<DyingEnemy>)>,
+) -> bool {
+	enemy_query.iter().any(|(state, _, transform)| {
+		matches!(state, EnemyState::Chase | EnemyState::Search)
+			&& transform.translation.truncate().distance(spot) < detection_range
+			&& line_of_sight(transform.translation, spot.extend(transform.translation.z), physics_world)
+	})
+}
+
+/// E steps into the nearest `HidingSpot` in reach, or back out of the one the player is in
+///
+/// Enemies chasing the player lose them and search where they were last seen. Not while dashing.
+#[allow(clippy::too_many_arguments)]
+pub fn toggle_hiding(
+	mut commands: Commands,
+	input: Res<Input<KeyCode>>,
+	physics_world: PhysicsWorld,
+	enemy_config: Res<EnemyConfig>,
+	difficulty: Res<LevelDifficulty>,
+	mut player_query: Query<(Entity, &Transform, Option<&mut Hidden>, Option<&Dash>), With<Player>>,
+	spot_query: Query<&Transform, With<HidingSpot>>,
+	mut enemy_query: Query<(&mut EnemyState, &mut Alert, &Transform), (With<Enemy>, Without<Dormant>, Without<DyingEnemy>)>,
+) {
+	if !input.just_pressed(KeyCode::E) { return }
+	let (player, transform, hidden, dash) = match player_query.get_single_mut() {
+		Ok(player) => player,
+		Err(_) => return,
+	};
+	let player_translation = transform.translation.truncate();
+	let detection_range = enemy_config.detection_range * difficulty.enemy_sight_mult;
+
+	if let Some(mut hidden) = hidden {
+		// A medio paso no se puede dar la vuelta
+		if hidden.leaving || !hidden.timer.finished() { return }
+		if watched(hidden.spot, detection_range, &physics_world, &enemy_query) { return }
+		hidden.leaving = true;
+		hidden.timer = Timer::from_seconds(HIDE_SECONDS, false);
+		return;
+	}
+
+	// dash solo devuelve las capas sin Hidden, escondido a medio dash atravesaria enemigos
+	if dash.is_some_and(|dash| dash.active.is_some()) { return }
+	let spot = spot_query.iter()
+		.map(|transform| transform.translation.truncate())
+		.filter(|spot| spot.distance(player_translation) <= HIDE_REACH)
+		.min_by(|a, b| a.distance(player_translation).total_cmp(&b.distance(player_translation)));
+	let spot = match spot {
+		Some(spot) => spot,
+		None => return,
+	};
+	if watched(spot, detection_range, &physics_world, &enemy_query) { return }
+
+	commands.entity(player).insert(Hidden {
+		spot,
+		exit: player_translation,
+		timer: Timer::from_seconds(HIDE_SECONDS, false),
+		leaving: false,
+	});
+	for (mut state, mut alert, _) in enemy_query.iter_mut() {
+		if *state == EnemyState::Chase {
+			*state = EnemyState::Search;
+			alert.target = player_translation;
+		}
+	}
+}
+
+/// Walks the player into or out of the spot while fading them out or back in
+pub fn hiding(
+	mut commands: Commands,
+	time: Res<Time>,
+	mut query: Query<(Entity, &mut Hidden, &Transform, &mut Velocity, &mut TextureAtlasSprite), With<Player>>,
+) {
+	let (entity, mut hidden, transform, mut velocity, mut sprite) = match query.get_single_mut() {
+		Ok(player) => player,
+		Err(_) => return,
+	};
+	hidden.timer.tick(time.delta());
+	let target = if hidden.leaving { hidden.exit } else { hidden.spot };
+	let remaining = hidden.timer.duration().as_secs_f32() - hidden.timer.elapsed_secs();
+	velocity.linear = if hidden.timer.finished() {
+		Vec3::ZERO
+	} else {
+		((target - transform.translation.truncate()) / remaining.max(time.delta_seconds())).extend(0.0)
+	};
+
+	let visible = if hidden.leaving { hidden.timer.percent() } else { hidden.timer.percent_left() };
+	sprite.color.set_a(visible);
+	if hidden.leaving && hidden.timer.finished() {
+		sprite.color.set_a(1.0);
+		commands.entity(entity).remove::<Hidden>();
+	}
+}
@@ -0,0 +1,124 @@
+use std::f32::consts::TAU;
+
+use bevy::{
+	prelude::*,
+	render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+
+use crate::components::*;
+
+// Encima de la niebla (998.95) y debajo de las zonas seguras (999)
+const LIGHT_Z: f32 = 998.97;
+const GLOW_TEXTURE_SIZE: u32 = 64;
+
+/// Lights drawn over the darkness, the player's flashlight plus every `LightSource` in the level
+pub struct LightConfig {
+	/// Only this many lights, the nearest to the player, get a glow. The glow sprites are spawned
+	/// once at startup and blended over the darkness every frame, so keep it low
+	pub max_lights: usize,
+	/// Radius of the glow around the player while the flashlight is on
+	pub player_radius: f32,
+	pub player_color: Color,
+	/// Strength of the glow at its center, fading to nothing at the radius
+	pub intensity: f32,
+	/// Wavers per second of a flickering light
+	pub flicker_speed: f32,
+	/// Fraction of the radius and brightness a flickering light can lose
+	pub flicker_amount: f32,
+}
+
+impl Default for LightConfig {
+	fn default() -> Self {
+		Self {
+			max_lights: 8,
+			player_radius: 40.0,
+			player_color: Color::rgb(1.0, 1.0, 0.85),
+			intensity: 0.35,
+			flicker_speed: 3.0,
+			flicker_amount: 0.2,
+		}
+	}
+}
+
+#[derive(Component)]
+pub struct LightGlow;
+
+/// 0.0 to 1.0, two sines out of step so it never looks like a plain pulse
+pub fn flicker(seconds: f32, phase: f32) -> f32 {
+	let wave = (seconds * TAU + phase).sin() * 0.6 + (seconds * TAU * 2.3 + phase * 1.7).sin() * 0.4;
+	wave * 0.5 + 0.5
+}
+
+fn glow_image() -> Image {
+	let half = GLOW_TEXTURE_SIZE as f32 / 2.0;
+	let data = (0..GLOW_TEXTURE_SIZE * GLOW_TEXTURE_SIZE).flat_map(|i| {
+		let offset = Vec2::new((i % GLOW_TEXTURE_SIZE) as f32 + 0.5, (i / GLOW_TEXTURE_SIZE) as f32 + 0.5) - half;
+		let t = (1.0 - offset.length() / half).clamp(0.0, 1.0);
+		[255, 255, 255, (t * t * 255.0) as u8]
+	}).collect();
+	Image::new(
+		Extent3d { width: GLOW_TEXTURE_SIZE, height: GLOW_TEXTURE_SIZE, depth_or_array_layers: 1 },
+		TextureDimension::D2,
+		data,
+		TextureFormat::Rgba8UnormSrgb,
+	)
+}
+
+/// Spawns the `max_lights` glow sprites that `lights` moves around
+pub fn setup_lights(
+	mut commands: Commands,
+	mut images: ResMut<Assets<Image>>,
+	light_config: Res<LightConfig>,
+) {
+	let texture = images.add(glow_image());
+	for _ in 0..light_config.max_lights {
+		commands.spawn_bundle(SpriteBundle {
+			texture: texture.clone(),
+			visibility: Visibility { is_visible: false },
+			..Default::default()
+		}).insert(LightGlow);
+	}
+}
+
+/// Gathers every active light and puts a glow on the nearest ones
+pub fn lights(
+	time: Res<Time>,
+	light_config: Res<LightConfig>,
+	player_query: Query<(&GlobalTransform, &Flashlight), With<Player>>,
+	light_query: Query<(Entity, &GlobalTransform, &LightSource, &Linked)>,
+	mut glow_query: Query<(&mut Transform, &mut Sprite, &mut Visibility), With<LightGlow>>,
+) {
+	let player = player_query.get_single().ok();
+	let center = player.map_or(Vec2::ZERO, |(transform, _)| transform.translation.truncate());
+
+	// (posicion, radio, color)
+	let mut active: Vec<(Vec2, f32, Color)> = light_query.iter().filter(|(.., linked)| linked.powered).map(|(entity, transform, light, _)| {
+		let mut radius = light.radius;
+		let mut color = light.color;
+		if light.flicker {
+			// Cada antorcha con su propio desfase para que no parpadeen a la vez
+			let dim = flicker(time.seconds_since_startup() as f32 * light_config.flicker_speed, entity.id() as f32) * light_config.flicker_amount;
+			radius *= 1.0 - dim;
+			color.set_a(color.a() * (1.0 - dim));
+		}
+		(transform.translation.truncate(), radius, color)
+	}).collect();
+	if let Some((transform, _)) = player.filter(|(_, flashlight)| flashlight.on) {
+		active.push((transform.translation.truncate(), light_config.player_radius, light_config.player_color));
+	}
+	active.sort_by(|a, b| a.0.distance_squared(center).total_cmp(&b.0.distance_squared(center)));
+
+	let mut active = active.into_iter();
+	for (mut transform, mut sprite, mut visibility) in glow_query.iter_mut() {
+		match active.next() {
+			Some((position, radius, color)) => {
+				transform.translation = position.extend(LIGHT_Z);
+				sprite.custom_size = Some(Vec2::splat(radius * 2.0));
+				sprite.color = color;
+				sprite.color.set_a(color.a() * light_config.intensity);
+				visibility.is_visible = true;
+			}
+			None => visibility.is_visible = false,
+		}
+	}
+}
@@ -1,12 +1,40 @@
+// Las queries de bevy se pasan del limite enseguida
+#![allow(clippy::type_complexity)]
+
+mod asset_check;
+mod audio;
+mod combat;
 mod components;
+mod debug;
+mod difficulty;
+mod fog;
+mod hiding;
+mod lights;
+mod menu;
+mod navigation;
+mod pause;
+mod records;
+mod resources;
+mod save;
+mod scent;
+mod settings;
+mod spawning;
+mod switches;
 mod systems;
+#[cfg(test)]
+mod test_util;
+mod ui;
 
 use bevy::{
+	diagnostic::{EntityCountDiagnosticsPlugin, FrameTimeDiagnosticsPlugin},
 	input::system::exit_on_esc_system,
 	prelude::*, render::options::{WgpuOptions, WgpuLimits},
 };
 use bevy_ecs_ldtk::prelude::*;
+use bevy_kira_audio::AudioPlugin;
+use bevy_prototype_debug_lines::DebugLinesPlugin;
 use heron::prelude::*;
+use resources::GameState;
 
 
 /*
@@ -18,7 +46,33 @@ use heron::prelude::*;
  */
 
 fn main() {
+	let game_config = resources::GameConfig::default();
+	let rng = spawning::GameRng::new(game_config.seed);
+
+	// La IA de los enemigos va a paso fijo si se pide, el resto sigue el ritmo de los frames
+	let ai_systems = SystemSet::new()
+		.with_system(systems::move_patroller)
+		.with_system(systems::enemy_vision.label("enemy_vision"))
+		.with_system(systems::enemy_pathfinding.label("enemy_pathfinding").after("enemy_vision"))
+		.with_system(systems::enemy_movement.label("enemy_movement").after("enemy_pathfinding"))
+		.with_system(systems::propagate_alert.after("enemy_movement"))
+		.with_system(systems::patrol_movement.after("enemy_movement"));
+	// Con paso fijo la fisica avanza en los mismos frames que la IA
+	let ai_systems = match game_config.fixed_timestep {
+		Some(_) => ai_systems
+			.with_system(pause::step_physics)
+			.with_run_criteria(pause::frame_delta.chain(pause::fixed_step).chain(pause::playing_fixed_step)),
+		None => ai_systems.with_run_criteria(State::on_update(GameState::Playing)),
+	};
+
 	App::new()
+		.insert_resource(WindowDescriptor {
+			title: game_config.title.clone(),
+			width: game_config.width,
+			height: game_config.height,
+			resizable: game_config.resizable,
+			..Default::default()
+		})
 		.insert_resource(WgpuOptions {
 			limits: WgpuLimits {
 				max_texture_array_layers: 2048,
@@ -27,23 +81,213 @@ fn main() {
 			..Default::default()
 		})
 		.add_plugins(DefaultPlugins)
+		.insert_resource(game_config)
+		.add_plugin(AudioPlugin)
 		.add_plugin(LdtkPlugin)
 		.add_plugin(PhysicsPlugin::default())
+		.add_plugin(DebugLinesPlugin::default())
+		.add_plugin(FrameTimeDiagnosticsPlugin)
+		.add_plugin(EntityCountDiagnosticsPlugin)
+		.add_state(GameState::Playing)
 		.insert_resource(LevelSelection::Uid(0))
+		.init_resource::<ui::UiFont>()
+		.init_resource::<resources::TimeScale>()
+		.init_resource::<resources::AiTime>()
+		.init_resource::<resources::HitStop>()
+		.init_resource::<resources::LastStand>()
+		.init_resource::<resources::CombatConfig>()
+		.init_resource::<resources::FlashConfig>()
+		.init_resource::<resources::ProjectileConfig>()
+		.add_event::<resources::FlashBurst>()
+		.add_event::<resources::Footstep>()
+		.init_resource::<resources::DamageQueue>()
+		.init_resource::<resources::PendingRespawn>()
+		.add_event::<resources::PlayerDied>()
+		.add_event::<resources::NewGame>()
+		.init_resource::<pause::AutoPaused>()
+		.init_resource::<menu::QuitPoint>()
+		.init_resource::<resources::LevelTransition>()
+		.init_resource::<resources::LevelGrace>()
+		.init_resource::<resources::LevelStart>()
+		.init_resource::<resources::EnemyPool>()
+		.init_resource::<resources::DecalConfig>()
+		.init_resource::<resources::SpawnAnimConfig>()
+		.insert_resource(rng)
+		.init_resource::<spawning::SpawnTable>()
+		.init_resource::<scent::ScentConfig>()
+		.init_resource::<fog::FogOfWar>()
+		.init_resource::<lights::LightConfig>()
+		.init_resource::<audio::MusicConfig>()
+		.insert_resource(settings::Settings::load())
+		.insert_resource(records::BestTimes::load())
+		.init_resource::<records::LevelTimer>()
+		.insert_resource(difficulty::DifficultyPresets::load())
+		.init_resource::<difficulty::ActiveDifficulty>()
+		.init_resource::<resources::LightingConfig>()
+		.init_resource::<resources::AmbientTint>()
+		.init_resource::<resources::Score>()
+		.init_resource::<resources::CollectedEntities>()
+		.init_resource::<resources::Stats>()
+		.init_resource::<resources::DebugConfig>()
+		.init_resource::<debug::Inspected>()
+		.init_resource::<resources::ReticleConfig>()
+		.init_resource::<resources::EnemyConfig>()
+		.init_resource::<resources::LevelDifficulty>()
+		.init_resource::<resources::CameraConfig>()
+		.init_resource::<resources::DashConfig>()
+		.init_resource::<resources::DustConfig>()
+		.init_resource::<navigation::NavGrid>()
+		// Capas de fondo, ej: ParallaxLayer { texture: "texture/fondo.png".into(), factor: 0.8 }
+		.insert_resource(resources::ParallaxLayers(vec![]))
 		.add_startup_system(systems::setup)
+		.add_startup_system(asset_check::load_critical_assets)
+		.add_startup_system(debug::setup_diagnostics)
+		.add_startup_system(debug::setup_inspector)
+		.add_startup_system(audio::setup_enemy_audio)
+		.add_startup_system(audio::setup_player_audio)
+		.add_startup_system(audio::setup_music)
+		.add_startup_system(systems::setup_hud)
+		.add_startup_system(systems::setup_reticle)
+		.add_startup_system(systems::setup_vignette)
+		.add_startup_system(lights::setup_lights)
 		.add_system(exit_on_esc_system)
-		.add_system(systems::movement)
-		.add_system(systems::camera_cursor_position)
-		.add_system(systems::camera_controller)
-		.add_system(systems::animation)
-		.add_system(systems::pause_physics_during_load)
-		.add_system(systems::win)
-		.add_system(systems::spawn_wall_collision)
-		.add_system(systems::enemy_movement)
+		.add_system_to_stage(CoreStage::First, pause::hold_physics)
+		.add_system(systems::update_ai_time.before("enemy_vision"))
+		.add_system(asset_check::check_assets)
+		.add_system(systems::camera_cursor_position.label("cursor"))
+		.add_system(systems::parallax_scroll.after("camera_controller"))
+		.add_system(systems::pause_physics_during_load.before("time_scale"))
+		.add_system(systems::read_ambient_tint)
+		.add_system(systems::read_level_difficulty)
+		.add_system(systems::record_level_start.label("record_level_start"))
+		.add_system(spawning::procedural_spawn.after("record_level_start"))
+		.add_system(systems::start_level_grace)
+		.add_system(spawning::fire_spawn_points)
+		.add_system(settings::apply_settings.before("lighting"))
+		.add_system(settings::save_settings)
+		.add_system(records::save_records)
+		.add_system(records::reset_level_timer)
+		.add_system(systems::apply_ambient_tint.label("lighting"))
+		.add_system(systems::count_collectibles.label("count_collectibles"))
+		.add_system(systems::skip_collected.after("count_collectibles"))
+		.add_system(systems::invalidate_nav_grid.before("spawn_wall_collision"))
+		.add_system(systems::read_floor_materials)
+		.add_system(systems::spawn_wall_collision.label("spawn_wall_collision"))
+		.add_system(systems::spawn_safe_zones.after("spawn_wall_collision"))
+		.add_system(fog::spawn_fog)
+		.add_system(systems::insert_light_vulnerable)
+		.add_system(systems::set_leash_home)
+		.add_system(systems::spawn_alert_icons)
+		.add_system(systems::insert_spawn_anim)
+		.add_system(combat::insert_contact_sensor)
+		.add_system(combat::restore_enemy_health)
+		.add_system(combat::insert_spawn_immunity)
+		.add_system(combat::protect_respawn)
+		.add_system(systems::make_enemies_dormant)
+		.add_system(systems::activate_enemies.after("time_scale"))
+		.add_system(systems::new_game)
+		.add_system(pause::toggle_pause)
+		.add_system(pause::new_game_from_pause)
+		.add_system(menu::quit_to_menu)
+		.add_system(menu::menu_buttons)
+		.add_system(pause::choose_difficulty)
+		.add_system(pause::choose_accessibility)
+		.add_system(pause::reset_records)
+		.add_system(save::export_save_key)
+		.add_system(save::import_save_key)
+		.add_system(pause::update_pause_ui)
+		.add_system(difficulty::apply_difficulty)
+		.add_system(pause::pause_on_focus_loss)
+		.add_system(audio::music_intensity)
+		.add_system(pause::apply_time_scale.label("time_scale"))
+		.add_system_set(SystemSet::on_enter(GameState::Paused).with_system(pause::spawn_pause_ui))
+		.add_system_set(SystemSet::on_exit(GameState::Paused).with_system(pause::despawn_pause_ui))
+		.add_system_set(SystemSet::on_enter(GameState::MainMenu).with_system(menu::spawn_main_menu))
+		.add_system_set(SystemSet::on_exit(GameState::MainMenu).with_system(menu::despawn_main_menu))
+		.add_system_set(ai_systems)
+		// Todo lo que avanza el juego se congela en pausa
+		.add_system_set(
+			SystemSet::on_update(GameState::Playing)
+				.with_system(systems::movement.label("movement"))
+				.with_system(systems::track_riding)
+				.with_system(systems::dash.label("dash").after("movement"))
+				.with_system(systems::carry_rider.after("dash"))
+				.with_system(systems::spawn_afterimages.after("dash"))
+				.with_system(systems::footsteps.label("footsteps").after("dash"))
+				.with_system(systems::spawn_dust.after("footsteps"))
+				.with_system(systems::tick_lifetimes.label("lifetimes"))
+				.with_system(systems::fade_out.after("lifetimes"))
+				.with_system(systems::drift)
+				.with_system(systems::cap_decals)
+				.with_system(systems::aim_flashlight.label("aim_flashlight").after("camera_controller"))
+				.with_system(systems::drain_battery.after("aim_flashlight"))
+				.with_system(systems::flash_burst.label("flash_burst").after("aim_flashlight"))
+				.with_system(systems::tick_stun)
+				.with_system(systems::fade_flash_overlay.before("flash_burst"))
+				.with_system(systems::recenter_camera.before("camera_controller"))
+				.with_system(systems::camera_controller.label("camera_controller").after("cursor"))
+				.with_system(systems::animation)
+				.with_system(systems::collect.before("win"))
+				.with_system(systems::win.label("win"))
+				.with_system(switches::toggle_switches.before("switches"))
+				.with_system(hiding::toggle_hiding.before("hiding"))
+				.with_system(hiding::hiding.label("hiding").after("dash"))
+				.with_system(switches::switches.label("switches"))
+				.with_system(switches::apply_gates.after("switches"))
+				.with_system(records::tick_level_timer.before("win"))
+				.with_system(systems::advance_level)
+				.with_system(systems::tick_level_grace.before("enemy_vision"))
+				.with_system(scent::drop_scent)
+				.with_system(scent::follow_scent.after("enemy_vision").before("enemy_pathfinding"))
+				.with_system(audio::enemy_audio.after("enemy_movement"))
+				.with_system(audio::flash_audio.after("flash_burst"))
+				.with_system(audio::footstep_audio.after("footsteps"))
+				.with_system(systems::light_exposure.before("apply_damage"))
+				.with_system(systems::dying_enemies)
+				.with_system(systems::reveal_enemies.label("reveal_enemies").after("aim_flashlight"))
+				// Despues de reveal_enemies, que pisa el color entero
+				.with_system(systems::spawn_anim.label("spawn_anim").after("reveal_enemies"))
+				.with_system(combat::spawn_immunity.after("spawn_anim"))
+				.with_system(fog::reveal_fog.after("aim_flashlight"))
+				.with_system(systems::hit_flash)
+				.with_system(combat::fire_projectiles.after("enemy_vision"))
+				.with_system(combat::home_projectiles)
+				.with_system(combat::projectile_hits.before("apply_damage"))
+				.with_system(combat::damage_on_contact.before("apply_damage"))
+				.with_system(combat::enemy_attack.before("apply_damage"))
+				.with_system(combat::apply_damage.label("apply_damage"))
+				.with_system(combat::tick_invincibility)
+				.with_system(combat::tick_respawn_protection)
+				.with_system(combat::restart_on_death.after("apply_damage"))
+				.with_system(pause::tick_hit_stop.before("time_scale"))
+				.with_system(systems::last_stand.after("apply_damage").before("time_scale"))
+		)
+		.add_system(systems::update_hud)
+		.add_system(systems::update_alert_icons)
+		.add_system(systems::last_stand_vignette.after("camera_controller"))
+		.add_system(systems::update_reticle.after("camera_controller"))
+		.add_system(lights::lights)
+		.add_system(debug::toggle_debug)
+		.add_system(debug::free_look_camera)
+		.add_system(debug::raise_debug_lines)
+		.add_system(debug::debug_enemy_ai.after("enemy_movement"))
+		.add_system(debug::debug_grid)
+		.add_system(debug::debug_heatmap)
+		.add_system(debug::update_diagnostics)
+		.add_system(debug::select_inspected.label("select_inspected").after("camera_controller"))
+		.add_system(debug::update_inspector.after("select_inspected"))
 		.register_ldtk_entity::<components::PlayerBundle>("Player")
 		.register_ldtk_entity::<components::EnemyBundle>("Enemy")
 		.register_ldtk_entity::<components::WinBundle>("Win")
+		.register_ldtk_entity::<components::CollectibleBundle>("Collectible")
+		.register_ldtk_entity::<components::PatrollerBundle>("Patroller")
+		.register_ldtk_entity::<components::LightBundle>("Light")
+		.register_ldtk_entity::<components::SpawnPointBundle>("SpawnPoint")
+		.register_ldtk_entity::<components::SwitchBundle>("Switch")
+		.register_ldtk_entity::<components::GateBundle>("Gate")
+		.register_ldtk_entity::<components::HidingSpotBundle>("HidingSpot")
 		.register_ldtk_int_cell::<components::WallBundle>(1)
+		.register_ldtk_int_cell::<components::SafeZoneBundle>(2)
 		.run();
 }
 
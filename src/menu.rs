@@ -0,0 +1,209 @@
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+
+use crate::{
+	components::*,
+	fog::FogOfWar,
+	pause::set_state,
+	resources::*,
+	systems::spawn_world,
+	ui::{self, UiFont},
+};
+
+#[derive(Component)]
+pub struct MainMenuUi;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Component)]
+pub enum MenuButton {
+	/// Reloads the level the run was quit on
+	Continue,
+	NewGame,
+}
+
+/// Level the last run was quit on, offered by `MenuButton::Continue`
+#[derive(Default)]
+pub struct QuitPoint {
+	pub level_uid: Option<i32>,
+}
+
+/// Q on the pause screen tears the whole run down and goes to the main menu
+///
+/// Everything left in the level goes with the LDtk world, the loose effects are swept up
+/// separately. The HUD, camera and the rest of the startup entities stay for the next run.
+#[allow(clippy::too_many_arguments)]
+pub fn quit_to_menu(
+	mut commands: Commands,
+	input: Res<Input<KeyCode>>,
+	mut state: ResMut<State<GameState>>,
+	score: Res<Score>,
+	mut quit_point: ResMut<QuitPoint>,
+	mut level_transition: ResMut<LevelTransition>,
+	mut grace: ResMut<LevelGrace>,
+	mut damage_queue: ResMut<DamageQueue>,
+	mut hit_stop: ResMut<HitStop>,
+	mut last_stand: ResMut<LastStand>,
+	mut enemy_pool: ResMut<EnemyPool>,
+	mut fog: ResMut<FogOfWar>,
+	world_query: Query<Entity, With<Handle<LdtkAsset>>>,
+	loose_query: Query<Entity, Or<(With<Lifetime>, With<WinUi>, With<GraceUi>, With<Pooled>)>>,
+) {
+	if *state.current() != GameState::Paused || !input.just_pressed(KeyCode::Q) { return }
+	world_query.for_each(|entity| commands.entity(entity).despawn_recursive());
+	loose_query.for_each(|entity| commands.entity(entity).despawn_recursive());
+
+	quit_point.level_uid = Some(score.level_uid);
+	*level_transition = LevelTransition::default();
+	grace.timer = None;
+	damage_queue.0.clear();
+	hit_stop.timer = None;
+	last_stand.timer = None;
+	// Los enemigos del pool ya se fueron con loose_query
+	enemy_pool.inactive.clear();
+	fog.level_uid = None;
+	set_state(&mut state, GameState::MainMenu);
+}
+
+pub fn spawn_main_menu(
+	mut commands: Commands,
+	font: Res<UiFont>,
+	quit_point: Res<QuitPoint>,
+	mut hud_query: Query<&mut Visibility, With<HudText>>,
+) {
+	hud_query.for_each_mut(|mut visibility| visibility.is_visible = false);
+
+	let title = commands.spawn_bundle(TextBundle {
+		text: Text::with_section("Unfair Advantage", font.style(100.0, Color::WHITE), Default::default()),
+		..Default::default()
+	}).id();
+	let mut buttons = vec![(MenuButton::NewGame, "N: New Game")];
+	if quit_point.level_uid.is_some() {
+		buttons.insert(0, (MenuButton::Continue, "C: Continue"));
+	}
+	let buttons: Vec<Entity> = buttons.into_iter().map(|(button, label)| {
+		let entity = ui::button(&mut commands, &font, label, 40.0);
+		commands.entity(entity).insert(button);
+		entity
+	}).collect();
+
+	commands.spawn_bundle(NodeBundle {
+		style: Style {
+			size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+			position_type: PositionType::Absolute,
+			// En bevy 0.6 las columnas van de abajo a arriba
+			flex_direction: FlexDirection::ColumnReverse,
+			justify_content: JustifyContent::Center,
+			align_items: AlignItems::Center,
+			..Default::default()
+		},
+		color: Color::BLACK.into(),
+		..Default::default()
+	}).insert(MainMenuUi).push_children(&[title]).push_children(&buttons);
+}
+
+pub fn despawn_main_menu(
+	mut commands: Commands,
+	query: Query<Entity, With<MainMenuUi>>,
+	mut hud_query: Query<&mut Visibility, With<HudText>>,
+) {
+	query.for_each(|entity| commands.entity(entity).despawn_recursive());
+	hud_query.for_each_mut(|mut visibility| visibility.is_visible = true);
+}
+
+/// Clicking a menu button or pressing its key loads a fresh world and starts playing
+#[allow(clippy::too_many_arguments)]
+pub fn menu_buttons(
+	mut commands: Commands,
+	input: Res<Input<KeyCode>>,
+	asset_server: Res<AssetServer>,
+	mut state: ResMut<State<GameState>>,
+	mut quit_point: ResMut<QuitPoint>,
+	mut level_selection: ResMut<LevelSelection>,
+	mut new_game: EventWriter<NewGame>,
+	button_query: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+) {
+	if *state.current() != GameState::MainMenu { return }
+	let clicked = button_query.iter().find(|(interaction, _)| **interaction == Interaction::Clicked).map(|(_, button)| *button);
+	let pressed = if input.just_pressed(KeyCode::N) {
+		Some(MenuButton::NewGame)
+	} else if input.just_pressed(KeyCode::C) && quit_point.level_uid.is_some() {
+		Some(MenuButton::Continue)
+	} else {
+		None
+	};
+	let button = match clicked.or(pressed) {
+		Some(button) => button,
+		None => return,
+	};
+
+	spawn_world(&mut commands, &asset_server);
+	match (button, quit_point.level_uid.take()) {
+		(MenuButton::Continue, Some(level_uid)) => *level_selection = LevelSelection::Uid(level_uid),
+		// new_game vuelve al primer nivel y limpia lo que quede de la partida anterior
+		_ => new_game.send(NewGame),
+	}
+	set_state(&mut state, GameState::Playing);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util::*;
+
+	fn count<T: Component>(app: &mut App) -> usize {
+		app.world.query_filtered::<(), With<T>>().iter(&app.world).count()
+	}
+
+	#[test]
+	fn quitting_tears_the_run_down() {
+		let mut app = test_app();
+		let mut input = Input::<KeyCode>::default();
+		input.press(KeyCode::Q);
+		app.insert_resource(input)
+			.insert_resource(UiFont(Handle::default()))
+			.insert_resource(Score { level_uid: 7, ..Default::default() })
+			.init_resource::<EnemyPool>()
+			.insert_resource(LevelTransition { timer: Some(Timer::from_seconds(1.0, false)), loading: true })
+			.init_resource::<QuitPoint>()
+			.init_resource::<LastStand>()
+			.init_resource::<FogOfWar>()
+			.add_state(GameState::Paused)
+			.add_system_set(SystemSet::on_enter(GameState::MainMenu).with_system(spawn_main_menu))
+			.add_system(quit_to_menu);
+		app.world.get_resource_mut::<LevelGrace>().unwrap().timer = Some(Timer::from_seconds(3.0, false));
+		app.world.get_resource_mut::<HitStop>().unwrap().start();
+		app.world.get_resource_mut::<FogOfWar>().unwrap().level_uid = Some(7);
+
+		let ldtk_world = app.world.spawn().insert(Handle::<LdtkAsset>::default()).id();
+		let player = spawn_player(&mut app.world, Vec2::ZERO);
+		app.world.entity_mut(ldtk_world).push_children(&[player]);
+		let effect = app.world.spawn().insert(Lifetime { timer: Timer::from_seconds(5.0, false) }).id();
+		let pooled = spawn_enemy(&mut app.world, Vec2::ZERO);
+		app.world.entity_mut(pooled).insert(Pooled);
+		app.world.get_resource_mut::<EnemyPool>().unwrap().inactive.push(pooled);
+		app.world.spawn().insert(HudText).insert(Visibility::default());
+		let target = app.world.spawn().id();
+		app.world.get_resource_mut::<DamageQueue>().unwrap().0.push(Damage { target, amount: 1.0, source: None });
+		app.update();
+
+		assert_eq!(*app.world.get_resource::<State<GameState>>().unwrap().current(), GameState::MainMenu);
+		assert!(app.world.get_entity(ldtk_world).is_none());
+		assert!(app.world.get_entity(player).is_none());
+		assert!(app.world.get_entity(effect).is_none());
+		assert!(app.world.get_entity(pooled).is_none());
+		assert_eq!(count::<Player>(&mut app), 0);
+		assert_eq!(count::<MainMenuUi>(&mut app), 1);
+		// El HUD se queda para la siguiente partida, solo oculto
+		assert_eq!(count::<HudText>(&mut app), 1);
+		assert!(app.world.query_filtered::<&Visibility, With<HudText>>().iter(&app.world).all(|visibility| !visibility.is_visible));
+
+		assert_eq!(app.world.get_resource::<QuitPoint>().unwrap().level_uid, Some(7));
+		let level_transition = app.world.get_resource::<LevelTransition>().unwrap();
+		assert!(level_transition.timer.is_none() && !level_transition.loading);
+		assert!(app.world.get_resource::<LevelGrace>().unwrap().timer.is_none());
+		assert!(app.world.get_resource::<DamageQueue>().unwrap().0.is_empty());
+		assert!(app.world.get_resource::<HitStop>().unwrap().timer.is_none());
+		assert!(app.world.get_resource::<LastStand>().unwrap().timer.is_none());
+		assert!(app.world.get_resource::<EnemyPool>().unwrap().inactive.is_empty());
+		assert!(app.world.get_resource::<FogOfWar>().unwrap().level_uid.is_none());
+	}
+}
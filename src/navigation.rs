@@ -0,0 +1,259 @@
+use std::{
+	cmp::Reverse,
+	collections::{BinaryHeap, HashMap, HashSet},
+};
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+
+// Costes de A* en enteros: recto 10, diagonal 14 (~10 * sqrt(2))
+const STRAIGHT_COST: i32 = 10;
+const DIAGONAL_COST: i32 = 14;
+// Si el objetivo esta tan lejos que hay que expandir mas nodos, es mejor rendirse
+const MAX_EXPANDED_NODES: usize = 4096;
+
+/// Walkable cells of the loaded level, built from the same wall set as the colliders
+///
+/// Empty until `spawn_wall_collision` runs for a level and cleared again on level change,
+/// paths can't be found while it's empty. `safe_zones` is filled by `spawn_safe_zones`.
+#[derive(Default)]
+pub struct NavGrid {
+	pub walls: HashSet<GridCoords>,
+	/// Cells enemies that respect safe zones won't path through or step into
+	pub safe_zones: HashSet<GridCoords>,
+	pub width: i32,
+	pub height: i32,
+	pub grid_size: i32,
+	/// Translation of cell (0, 0)'s corner, non-zero when the IntGrid layer is offset in LDtk
+	pub offset: Vec2,
+	/// Floor of the cells painted with a material, filled by `read_floor_materials`
+	pub materials: HashMap<GridCoords, FloorMaterial>,
+}
+
+/// What a floor cell is made of, painted on the IntGrid layer with the values after the walls
+/// (1) and safe zones (2): 3 stone, 4 ice and 5 mud. Unpainted floor is `Generic`
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub enum FloorMaterial {
+	#[default]
+	Generic,
+	Stone,
+	Ice,
+	Mud,
+}
+
+impl FloorMaterial {
+	/// None for the IntGrid values that aren't floor
+	pub fn from_int_grid(value: i32) -> Option<Self> {
+		match value {
+			0 => Some(FloorMaterial::Generic),
+			3 => Some(FloorMaterial::Stone),
+			4 => Some(FloorMaterial::Ice),
+			5 => Some(FloorMaterial::Mud),
+			_ => None,
+		}
+	}
+}
+
+impl NavGrid {
+	pub fn is_empty(&self) -> bool {
+		self.width == 0 || self.height == 0
+	}
+
+	pub fn material(&self, coords: GridCoords) -> FloorMaterial {
+		self.materials.get(&coords).copied().unwrap_or_default()
+	}
+
+	fn contains(&self, coords: GridCoords) -> bool {
+		coords.x >= 0 && coords.y >= 0 && coords.x < self.width && coords.y < self.height
+	}
+
+	pub fn is_walkable(&self, coords: GridCoords) -> bool {
+		self.contains(coords) && !self.walls.contains(&coords)
+	}
+
+	fn is_open(&self, coords: GridCoords, avoid_safe_zones: bool) -> bool {
+		self.is_walkable(coords) && !(avoid_safe_zones && self.safe_zones.contains(&coords))
+	}
+
+	/// Cell containing a translation relative to the level
+	pub fn to_grid(&self, translation: Vec2) -> GridCoords {
+		let cell = ((translation - self.offset) / self.grid_size as f32).floor();
+		GridCoords { x: cell.x as i32, y: cell.y as i32 }
+	}
+
+	/// Center of a cell relative to the level
+	pub fn to_translation(&self, coords: GridCoords) -> Vec2 {
+		(Vec2::new(coords.x as f32, coords.y as f32) + 0.5) * self.grid_size as f32 + self.offset
+	}
+
+	fn heuristic(a: GridCoords, b: GridCoords) -> i32 {
+		let dx = (a.x - b.x).abs();
+		let dy = (a.y - b.y).abs();
+		STRAIGHT_COST * (dx + dy) + (DIAGONAL_COST - 2 * STRAIGHT_COST) * dx.min(dy)
+	}
+
+	/// A* over the grid with 8-way movement, diagonals can't cut wall corners
+	///
+	/// Returns the cells to walk through after `from`, ending at `to`. With `avoid_safe_zones`
+	/// safe zone cells are treated like walls, so there's no path to a target standing in one.
+	pub fn find_path(&self, from: GridCoords, to: GridCoords, avoid_safe_zones: bool) -> Option<Vec<GridCoords>> {
+		if self.is_empty() || !self.is_open(to, avoid_safe_zones) {
+			return None;
+		}
+
+		// Por celda en vez de HashMaps, y cada celda se busca en walls y safe_zones una sola vez
+		let cells = (self.width * self.height) as usize;
+		let index = |coords: GridCoords| self.contains(coords).then(|| (coords.y * self.width + coords.x) as usize);
+		let mut open_cells: Vec<Option<bool>> = vec![None; cells];
+		let mut is_open = |coords: GridCoords| match index(coords) {
+			Some(i) => *open_cells[i].get_or_insert_with(|| self.is_open(coords, avoid_safe_zones)),
+			None => false,
+		};
+		let mut open = BinaryHeap::new();
+		let mut came_from: Vec<Option<GridCoords>> = vec![None; cells];
+		let mut cost = vec![i32::MAX; cells];
+		let mut expanded = 0;
+
+		// from puede caer fuera de la rejilla, entonces su coste no se guarda
+		if let Some(i) = index(from) { cost[i] = 0 }
+		// Con f empatado sale antes el de mas coste, el que va mas avanzado hacia to, si no con
+		// campo abierto se expande todo el rectangulo entre los dos
+		open.push(Reverse((Self::heuristic(from, to), Reverse(0), from.x, from.y)));
+
+		while let Some(Reverse((_, Reverse(current_cost), x, y))) = open.pop() {
+			let current = GridCoords { x, y };
+			if current == to {
+				let mut path = vec![current];
+				let mut cell = current;
+				while let Some(previous) = index(cell).and_then(|i| came_from[i]) {
+					if previous == from { break }
+					path.push(previous);
+					cell = previous;
+				}
+				path.reverse();
+				return Some(path);
+			}
+			// Entrada vieja de un nodo que ya se alcanzo por un camino mas barato
+			if index(current).is_some_and(|i| current_cost > cost[i]) { continue }
+
+			expanded += 1;
+			if expanded > MAX_EXPANDED_NODES { return None }
+
+			for dx in -1..=1 {
+				for dy in -1..=1 {
+					if dx == 0 && dy == 0 { continue }
+					let next = GridCoords { x: x + dx, y: y + dy };
+					if !is_open(next) { continue }
+					let diagonal = dx != 0 && dy != 0;
+					if diagonal && !(is_open(GridCoords { x: x + dx, y }) && is_open(GridCoords { x, y: y + dy })) {
+						continue;
+					}
+
+					let next_cost = current_cost + if diagonal { DIAGONAL_COST } else { STRAIGHT_COST };
+					let i = (next.y * self.width + next.x) as usize;
+					if next_cost < cost[i] {
+						cost[i] = next_cost;
+						came_from[i] = Some(current);
+						open.push(Reverse((next_cost + Self::heuristic(next, to), Reverse(next_cost), next.x, next.y)));
+					}
+				}
+			}
+		}
+
+		None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::{rngs::StdRng, Rng, SeedableRng};
+
+	use super::*;
+	use crate::resources::EnemyConfig;
+
+	// Capa de 16px desplazada como en LDtk, x a la derecha y la y ya invertida
+	fn offset_grid() -> NavGrid {
+		NavGrid {
+			width: 4,
+			height: 4,
+			grid_size: 16,
+			offset: Vec2::new(8.0, -24.0),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn cells_follow_the_layer_offset() {
+		let nav_grid = offset_grid();
+		assert_eq!(nav_grid.to_grid(Vec2::new(8.0, -24.0)), GridCoords { x: 0, y: 0 });
+		assert_eq!(nav_grid.to_grid(Vec2::new(7.9, -24.1)), GridCoords { x: -1, y: -1 });
+		assert_eq!(nav_grid.to_grid(Vec2::new(40.0, 0.0)), GridCoords { x: 2, y: 1 });
+		assert_eq!(nav_grid.to_translation(GridCoords { x: 0, y: 0 }), Vec2::new(16.0, -16.0));
+		for x in 0..4 {
+			for y in 0..4 {
+				let coords = GridCoords { x, y };
+				assert_eq!(nav_grid.to_grid(nav_grid.to_translation(coords)), coords);
+			}
+		}
+	}
+
+	#[test]
+	fn paths_go_around_offset_walls() {
+		let mut nav_grid = offset_grid();
+		nav_grid.walls.extend([GridCoords { x: 1, y: 0 }, GridCoords { x: 1, y: 1 }, GridCoords { x: 1, y: 2 }]);
+		let from = nav_grid.to_grid(Vec2::new(16.0, -16.0));
+		let to = nav_grid.to_grid(Vec2::new(48.0, -16.0));
+		let path = nav_grid.find_path(from, to, false).unwrap();
+		assert!(path.iter().all(|&coords| nav_grid.is_walkable(coords)));
+		assert!(path.iter().any(|coords| coords.y == 3));
+	}
+
+	// Del tamano del nivel principal, con muros cada 10 columnas que dejan paso arriba o abajo
+	fn level_grid() -> NavGrid {
+		let mut nav_grid = NavGrid { width: 155, height: 124, grid_size: 16, ..Default::default() };
+		for x in (10..155).step_by(10) {
+			let gap = if x % 20 == 0 { 0..4 } else { 120..124 };
+			nav_grid.walls.extend((0..124).filter(|y| !gap.contains(y)).map(|y| GridCoords { x, y }));
+		}
+		nav_grid
+	}
+
+	// Las paredes del nivel de main.ldtk
+	fn main_level_grid() -> NavGrid {
+		let project: ldtk::LdtkJson = serde_json::from_str(include_str!("../assets/tilemap/main.ldtk")).unwrap();
+		let layer = project.levels[0].layer_instances.as_ref().unwrap().iter().find(|layer| !layer.int_grid_csv.is_empty()).unwrap();
+		let walls = layer.int_grid_csv.iter().enumerate()
+			.filter(|(_, value)| **value == 1)
+			.map(|(i, _)| GridCoords { x: i as i32 % layer.c_wid, y: layer.c_hei - 1 - i as i32 / layer.c_wid })
+			.collect();
+		NavGrid { walls, width: layer.c_wid, height: layer.c_hei, grid_size: layer.grid_size, ..Default::default() }
+	}
+
+	// Se mira en release con cargo test --release -- --ignored --nocapture
+	#[test]
+	#[ignore]
+	fn hundred_paths_fit_in_a_frame() {
+		let nav_grid = main_level_grid();
+		let mut rng = StdRng::seed_from_u64(0);
+		let mut cell = || loop {
+			let coords = GridCoords { x: rng.gen_range(0..nav_grid.width), y: rng.gen_range(0..nav_grid.height) };
+			if nav_grid.is_walkable(coords) { return coords }
+		};
+		let queries: Vec<(GridCoords, GridCoords)> = (0..100).map(|_| (cell(), cell())).collect();
+		let start = std::time::Instant::now();
+		let found = queries.iter().filter(|(from, to)| nav_grid.find_path(*from, *to, false).is_some()).count();
+		let elapsed = start.elapsed();
+		// Cada paso de la IA busca como mucho paths_per_frame, eso si que tiene que caber en un frame
+		let per_frame = elapsed / 100 * EnemyConfig::default().paths_per_frame.unwrap() as u32;
+		println!("100 paths on the main level in {:?}, {} found, {:?} per frame", elapsed, found, per_frame);
+		assert!(per_frame.as_secs_f32() < 1.0 / 60.0);
+	}
+
+	#[test]
+	fn far_goals_give_up() {
+		let nav_grid = level_grid();
+		// Al otro lado de todos los muros, mas de MAX_EXPANDED_NODES celdas de camino
+		assert!(nav_grid.find_path(GridCoords { x: 0, y: 60 }, GridCoords { x: 154, y: 60 }, false).is_none());
+		assert!(nav_grid.find_path(GridCoords { x: 0, y: 60 }, GridCoords { x: 9, y: 0 }, false).is_some());
+	}
+}
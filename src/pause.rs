@@ -0,0 +1,277 @@
+use std::time::Duration;
+
+use bevy::{ecs::schedule::ShouldRun, prelude::*, window::WindowFocused};
+use heron::{PhysicsSteps, PhysicsTime};
+
+use crate::{difficulty::ActiveDifficulty, records::BestTimes, resources::*, settings::Settings, ui::UiFont};
+
+#[derive(Component)]
+pub struct PauseUi;
+
+/// Whether the current pause came from losing focus, so regaining it never undoes a manual pause
+#[derive(Default)]
+pub struct AutoPaused(bool);
+
+pub fn set_state(state: &mut State<GameState>, next: GameState) {
+	if *state.current() != next {
+		// Solo falla si ya hay un cambio pendiente en este frame
+		let _ = state.set(next);
+	}
+}
+
+/// Frame time fed to `fixed_step`, tests chain their own to pick the frame rate
+pub fn frame_delta(time: Res<Time>) -> Duration {
+	time.delta()
+}
+
+#[derive(Default)]
+pub struct FixedStepState {
+	accumulator: Duration,
+	looping: bool,
+}
+
+/// Run criteria of the AI, like `FixedTimestep` over `GameConfig.fixed_timestep` but fed the frame
+/// time through `In`
+pub fn fixed_step(
+	In(delta): In<Duration>,
+	game_config: Res<GameConfig>,
+	mut state: Local<FixedStepState>,
+) -> ShouldRun {
+	let step = Duration::from_secs_f32(game_config.fixed_timestep.unwrap_or_default());
+	// Al volver a comprobar en el mismo frame el tiempo ya esta sumado
+	if !state.looping {
+		state.accumulator += delta;
+	}
+	if !step.is_zero() && state.accumulator >= step {
+		state.accumulator -= step;
+		state.looping = true;
+		ShouldRun::YesAndCheckAgain
+	} else {
+		state.looping = false;
+		ShouldRun::No
+	}
+}
+
+/// Runs with the AI, so the physics steps once on the frames that ran a fixed step
+pub fn step_physics(
+	game_config: Res<GameConfig>,
+	mut physics_steps: ResMut<PhysicsSteps>,
+) {
+	let step = Duration::from_secs_f32(game_config.fixed_timestep.unwrap_or_default());
+	*physics_steps = PhysicsSteps::every_frame(step);
+}
+
+/// With a fixed step, stops the physics at the start of every frame until `step_physics` lets it through
+pub fn hold_physics(
+	game_config: Res<GameConfig>,
+	mut physics_steps: ResMut<PhysicsSteps>,
+) {
+	if game_config.fixed_timestep.is_none() {
+		return;
+	}
+	// Un timer que no termina en un frame no deja pasar ningun paso
+	*physics_steps = PhysicsSteps::from_delta_time(Duration::from_secs(3600));
+}
+
+/// Chained after the fixed timestep of the AI, the steps due while paused are used up without running
+pub fn playing_fixed_step(
+	In(should_run): In<ShouldRun>,
+	state: Res<State<GameState>>,
+) -> ShouldRun {
+	match (should_run, state.current()) {
+		(_, GameState::Playing) => should_run,
+		(ShouldRun::YesAndCheckAgain, _) => ShouldRun::NoAndCheckAgain,
+		_ => ShouldRun::No,
+	}
+}
+
+pub fn toggle_pause(
+	input: Res<Input<KeyCode>>,
+	mut state: ResMut<State<GameState>>,
+	mut auto_paused: ResMut<AutoPaused>,
+) {
+	if !input.just_pressed(KeyCode::P) { return }
+	auto_paused.0 = false;
+	let next = match state.current() {
+		GameState::Playing => GameState::Paused,
+		GameState::Paused => GameState::Playing,
+		GameState::MainMenu => return,
+	};
+	set_state(&mut state, next);
+}
+
+/// N on the pause screen starts a new game
+pub fn new_game_from_pause(
+	input: Res<Input<KeyCode>>,
+	mut state: ResMut<State<GameState>>,
+	mut new_game: EventWriter<NewGame>,
+) {
+	if *state.current() != GameState::Paused || !input.just_pressed(KeyCode::N) { return }
+	new_game.send(NewGame);
+	set_state(&mut state, GameState::Playing);
+}
+
+/// D on the pause screen cycles the difficulty the next new game starts with
+pub fn choose_difficulty(
+	input: Res<Input<KeyCode>>,
+	state: Res<State<GameState>>,
+	mut settings: ResMut<Settings>,
+) {
+	if *state.current() != GameState::Paused || !input.just_pressed(KeyCode::D) { return }
+	settings.difficulty = settings.difficulty.next();
+}
+
+/// C on the pause screen cycles the color palette, I toggles the alert icons, M the damage numbers
+/// and F what the player faces
+pub fn choose_accessibility(
+	input: Res<Input<KeyCode>>,
+	state: Res<State<GameState>>,
+	mut settings: ResMut<Settings>,
+) {
+	if *state.current() != GameState::Paused { return }
+	if input.just_pressed(KeyCode::C) { settings.palette = settings.palette.next() }
+	if input.just_pressed(KeyCode::I) { settings.alert_icons = !settings.alert_icons }
+	if input.just_pressed(KeyCode::M) { settings.damage_numbers = !settings.damage_numbers }
+	if input.just_pressed(KeyCode::F) { settings.facing = settings.facing.next() }
+}
+
+/// X on the pause screen forgets every best time and the leaderboard
+pub fn reset_records(
+	input: Res<Input<KeyCode>>,
+	state: Res<State<GameState>>,
+	mut best_times: ResMut<BestTimes>,
+) {
+	if *state.current() != GameState::Paused || !input.just_pressed(KeyCode::X) { return }
+	*best_times = BestTimes::default();
+	info!("Records reset");
+}
+
+pub fn pause_on_focus_loss(
+	settings: Res<Settings>,
+	mut focus_events: EventReader<WindowFocused>,
+	mut state: ResMut<State<GameState>>,
+	mut auto_paused: ResMut<AutoPaused>,
+) {
+	for event in focus_events.iter() {
+		match (event.focused, state.current()) {
+			(false, GameState::Playing) if settings.pause_on_focus_loss => {
+				auto_paused.0 = true;
+				set_state(&mut state, GameState::Paused);
+			}
+			(true, GameState::Paused) if settings.resume_on_focus && auto_paused.0 => {
+				auto_paused.0 = false;
+				set_state(&mut state, GameState::Playing);
+			}
+			// Cualquier otro cambio de foco hace que la pausa ya no sea automatica
+			(true, _) => auto_paused.0 = false,
+			_ => (),
+		}
+	}
+}
+
+pub fn tick_hit_stop(
+	time: Res<Time>,
+	mut hit_stop: ResMut<HitStop>,
+) {
+	if hit_stop.timer.as_mut().is_some_and(|timer| timer.tick(time.delta()).finished()) {
+		hit_stop.timer = None;
+	}
+}
+
+pub fn apply_time_scale(
+	state: Res<State<GameState>>,
+	time_scale: Res<TimeScale>,
+	hit_stop: Res<HitStop>,
+	last_stand: Res<LastStand>,
+	mut physics_time: ResMut<PhysicsTime>,
+) {
+	// La pausa y la carga paran del todo, ganan a cualquier otra escala
+	let scale = if time_scale.loading || *state.current() != GameState::Playing {
+		0.
+	} else if hit_stop.timer.is_some() {
+		hit_stop.scale
+	} else if last_stand.timer.is_some() {
+		last_stand.scale
+	} else {
+		1.
+	};
+	if physics_time.scale() != scale {
+		physics_time.set_scale(scale);
+	}
+}
+
+fn difficulty_text(settings: &Settings, active: &ActiveDifficulty) -> String {
+	if settings.difficulty == active.difficulty {
+		format!("\nDifficulty: {:?}  D: Change", active.difficulty)
+	} else {
+		format!("\nDifficulty: {:?}, {:?} on a new game  D: Change", active.difficulty, settings.difficulty)
+	}
+}
+
+fn accessibility_text(settings: &Settings) -> String {
+	let on_off = |on: bool| if on { "On" } else { "Off" };
+	format!(
+		"\nColors: {:?}  C: Change  Alert Icons: {}  I: Toggle  Damage Numbers: {}  M: Toggle  Facing: {:?}  F: Change",
+		settings.palette, on_off(settings.alert_icons), on_off(settings.damage_numbers), settings.facing,
+	)
+}
+
+pub fn spawn_pause_ui(
+	mut commands: Commands,
+	font: Res<UiFont>,
+	settings: Res<Settings>,
+	active: Res<ActiveDifficulty>,
+) {
+	commands.spawn_bundle(TextBundle {
+		style: Style {
+			margin: Rect::all(Val::Px(5.0)),
+			..Default::default()
+		},
+		text: Text {
+			sections: vec![
+				TextSection {
+					value: "Paused\n".to_string(),
+					style: font.style(100.0, Color::WHITE),
+				},
+				TextSection {
+					value: "P: Resume  N: New Game  Q: Quit to Menu  X: Reset Records  S: Export Save  L: Import Save".to_string(),
+					style: font.style(30.0, Color::WHITE),
+				},
+				TextSection {
+					value: difficulty_text(&settings, &active),
+					style: font.style(30.0, Color::WHITE),
+				},
+				TextSection {
+					value: accessibility_text(&settings),
+					style: font.style(30.0, Color::WHITE),
+				},
+				// Resultado de exportar o importar la partida
+				TextSection {
+					value: String::new(),
+					style: font.style(30.0, Color::WHITE),
+				},
+			],
+			..Default::default()
+		},
+		..Default::default()
+	}).insert(PauseUi);
+}
+
+pub fn update_pause_ui(
+	settings: Res<Settings>,
+	active: Res<ActiveDifficulty>,
+	mut query: Query<&mut Text, With<PauseUi>>,
+) {
+	if !settings.is_changed() && !active.is_changed() { return }
+	for mut text in query.iter_mut() {
+		text.sections[2].value = difficulty_text(&settings, &active);
+		text.sections[3].value = accessibility_text(&settings);
+	}
+}
+
+pub fn despawn_pause_ui(
+	mut commands: Commands,
+	query: Query<Entity, With<PauseUi>>,
+) {
+	query.for_each(|entity| commands.entity(entity).despawn_recursive());
+}
@@ -0,0 +1,109 @@
+use std::{collections::HashMap, fs};
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+
+use crate::resources::{LevelTransition, NewGame, TimeScale};
+
+// Mismo sitio que settings.ron
+const RECORDS_PATH: &str = "records.ron";
+const LEADERBOARD_SIZE: usize = 5;
+
+/// Seconds spent on the current level and on the whole run, stopped on the win screen
+#[derive(Default)]
+pub struct LevelTimer {
+	pub level: f32,
+	pub run: f32,
+}
+
+/// Fastest completion of each level by uid and the fastest full runs, loaded from `records.ron`
+/// at startup and written back whenever they change
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct BestTimes {
+	pub levels: HashMap<i32, f32>,
+	/// Seconds of each completed run, fastest first
+	pub leaderboard: Vec<f32>,
+}
+
+impl BestTimes {
+	pub fn load() -> Self {
+		let contents = match fs::read_to_string(RECORDS_PATH) {
+			Ok(contents) => contents,
+			Err(_) => return Self::default(),
+		};
+		ron::from_str(&contents).unwrap_or_else(|error| {
+			warn!("Ignoring invalid {}: {}", RECORDS_PATH, error);
+			Self::default()
+		})
+	}
+
+	fn save(&self) {
+		let result = ron::ser::to_string_pretty(self, PrettyConfig::default())
+			.map_err(|error| error.to_string())
+			.and_then(|contents| fs::write(RECORDS_PATH, contents).map_err(|error| error.to_string()));
+		if let Err(error) = result {
+			warn!("Couldn't save {}: {}", RECORDS_PATH, error);
+		}
+	}
+
+	/// Keeps `seconds` if it beats the level's best and returns the line for the win screen
+	pub fn record_level(&mut self, level_uid: i32, seconds: f32) -> String {
+		let line = match self.levels.get(&level_uid) {
+			Some(best) if *best <= seconds => format!("Best: {}  This run: {}", format_time(*best), format_time(seconds)),
+			Some(_) => format!("Best: {}  This run: {} (New Record!)", format_time(seconds), format_time(seconds)),
+			None => format!("This run: {} (First Clear!)", format_time(seconds)),
+		};
+		let best = self.levels.entry(level_uid).or_insert(seconds);
+		*best = best.min(seconds);
+		line
+	}
+
+	/// Adds a finished run to the leaderboard, returns its place counting from 1 if it made it in
+	pub fn record_run(&mut self, seconds: f32) -> Option<usize> {
+		let place = self.leaderboard.iter().position(|time| seconds < *time).unwrap_or(self.leaderboard.len());
+		if place >= LEADERBOARD_SIZE { return None }
+		self.leaderboard.insert(place, seconds);
+		self.leaderboard.truncate(LEADERBOARD_SIZE);
+		Some(place + 1)
+	}
+}
+
+pub fn format_time(seconds: f32) -> String {
+	let seconds = seconds as u32;
+	format!("{:02}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// Runs in the Playing set, so the pause doesn't count
+pub fn tick_level_timer(
+	time: Res<Time>,
+	time_scale: Res<TimeScale>,
+	level_transition: Res<LevelTransition>,
+	mut level_timer: ResMut<LevelTimer>,
+) {
+	if time_scale.loading || level_transition.timer.is_some() { return }
+	level_timer.level += time.delta_seconds();
+	level_timer.run += time.delta_seconds();
+}
+
+/// Every load starts the level clock over, also the reload after dying
+pub fn reset_level_timer(
+	mut level_events: EventReader<LevelEvent>,
+	mut new_game_events: EventReader<NewGame>,
+	mut level_timer: ResMut<LevelTimer>,
+) {
+	if new_game_events.iter().count() > 0 {
+		*level_timer = LevelTimer::default();
+	}
+	if level_events.iter().any(|event| matches!(event, LevelEvent::Transformed(_))) {
+		level_timer.level = 0.0;
+	}
+}
+
+pub fn save_records(best_times: Res<BestTimes>) {
+	if best_times.is_changed() && !best_times.is_added() {
+		best_times.save();
+	}
+}
@@ -0,0 +1,678 @@
+use std::{collections::{HashMap, HashSet}, time::Duration};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::components::CollectibleId;
+
+/// Startup options for the game window, applied before `DefaultPlugins` in main.rs
+pub struct GameConfig {
+	pub title: String,
+	/// Initial size in logical pixels, the cursor math uses the live window size so it can change
+	pub width: f32,
+	pub height: f32,
+	pub resizable: bool,
+	/// Seconds per step of physics and enemy AI, so they play out the same at any frame rate.
+	/// None runs them once per rendered frame
+	pub fixed_timestep: Option<f32>,
+	/// Seed for `GameRng`, None picks a new one every run
+	pub seed: Option<u64>,
+	/// Scatters enemies over the floor of every level that loads, None keeps only the placed ones
+	pub procedural_enemies: Option<ProceduralEnemies>,
+}
+
+pub struct ProceduralEnemies {
+	pub count: usize,
+	/// No enemy lands closer than this to the player's start
+	pub min_player_distance: f32,
+	/// Removes the enemies placed in LDtk instead of adding to them
+	pub replace_placed: bool,
+}
+
+impl Default for GameConfig {
+	fn default() -> Self {
+		Self {
+			title: "Unfair Advantage".to_string(),
+			width: 1280.0,
+			height: 720.0,
+			resizable: true,
+			fixed_timestep: Some(1.0 / 60.0),
+			seed: None,
+			procedural_enemies: None,
+		}
+	}
+}
+
+pub struct DashConfig {
+	pub speed: f32,
+	/// Seconds each dash lasts
+	pub duration: f32,
+	/// Seconds from the start of a dash until the next one is allowed
+	pub cooldown: f32,
+	/// Whether the player passes through enemies while dashing, walls always block
+	pub phase_through_enemies: bool,
+	/// Copies of the player spawned evenly along each dash
+	pub afterimages: usize,
+	/// Seconds each afterimage takes to fade out
+	pub afterimage_lifetime: f32,
+	pub afterimage_alpha: f32,
+}
+
+impl Default for DashConfig {
+	fn default() -> Self {
+		Self {
+			speed: 320.0,
+			duration: 0.15,
+			cooldown: 0.8,
+			phase_through_enemies: true,
+			afterimages: 4,
+			afterimage_lifetime: 0.25,
+			afterimage_alpha: 0.5,
+		}
+	}
+}
+
+/// Dust kicked up behind the player, one puff per footstep so it keeps time with the step sounds
+pub struct DustConfig {
+	/// Below this speed the player counts as standing still and no steps are taken
+	pub min_speed: f32,
+	/// World units walked between two footsteps
+	pub stride: f32,
+	pub particles_per_step: usize,
+	/// Particles per step while dashing
+	pub dash_particles: usize,
+	/// Seconds each particle takes to fade out
+	pub lifetime: f32,
+	pub size: f32,
+	pub color: Color,
+	/// How far from the player's feet a particle can land
+	pub spread: f32,
+}
+
+impl Default for DustConfig {
+	fn default() -> Self {
+		Self {
+			min_speed: 20.0,
+			stride: 14.0,
+			particles_per_step: 2,
+			dash_particles: 5,
+			lifetime: 0.4,
+			size: 2.0,
+			color: Color::rgba(0.6, 0.55, 0.5, 0.5),
+			spread: 3.0,
+		}
+	}
+}
+
+pub struct CameraConfig {
+	/// Size in world units of the box around the camera focus the player can move in without scrolling
+	pub dead_zone: Vec2,
+	/// How far ahead of a walking player the camera leans, also the most it can ever lean
+	pub look_ahead_distance: f32,
+	/// Fraction of the way to the new look-ahead covered per second
+	pub look_ahead_speed: f32,
+	/// Keeps the view inside the current level
+	pub clamp_to_level: bool,
+	/// Lets the view spill into neighbouring levels as the camera nears them, only useful with
+	/// `LdtkSettings::use_level_world_translations` and several levels loaded side by side
+	pub blend_neighbours: bool,
+	/// Distance to a neighbouring level at which the view starts spilling into it
+	pub blend_distance: f32,
+}
+
+impl Default for CameraConfig {
+	fn default() -> Self {
+		Self {
+			dead_zone: Vec2::new(64.0, 48.0),
+			look_ahead_distance: 32.0,
+			look_ahead_speed: 3.0,
+			clamp_to_level: true,
+			blend_neighbours: false,
+			blend_distance: 128.0,
+		}
+	}
+}
+
+/// Time covered by each run of the enemy AI, the fixed step or the frame time, see `update_ai_time`
+#[derive(Default)]
+pub struct AiTime {
+	pub delta: Duration,
+}
+
+impl AiTime {
+	pub fn delta(&self) -> Duration {
+		self.delta
+	}
+
+	pub fn delta_seconds(&self) -> f32 {
+		self.delta.as_secs_f32()
+	}
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub enum GameState {
+	Playing,
+	/// Gameplay systems and physics are frozen, entered with P or when the window loses focus
+	Paused,
+	/// No world loaded, reached with Q from the pause screen
+	MainMenu,
+}
+
+/// Everything that wants physics stopped, combined into `PhysicsTime` by `apply_time_scale`
+#[derive(Default)]
+pub struct TimeScale {
+	/// A level is spawning, set by `pause_physics_during_load`
+	pub loading: bool,
+}
+
+pub struct FlashConfig {
+	/// Battery spent by each burst
+	pub cost: f32,
+	pub radius: f32,
+	/// Seconds enemies stay stunned
+	pub stun: f32,
+	pub cooldown: f32,
+	/// Push given to enemies right next to the player, weaker towards the edge of the radius
+	pub knockback: f32,
+}
+
+impl Default for FlashConfig {
+	fn default() -> Self {
+		Self {
+			cost: 0.3,
+			radius: 110.0,
+			stun: 2.0,
+			cooldown: 1.5,
+			knockback: 250.0,
+		}
+	}
+}
+
+pub struct ProjectileConfig {
+	/// Seconds between shots of an enemy that sees the player
+	pub fire_interval: f32,
+	pub damage: f32,
+	pub speed: f32,
+	/// Seconds a straight projectile flies before vanishing, walls stop it sooner
+	pub lifetime: f32,
+	pub homing_speed: f32,
+	pub homing_turn_rate: f32,
+	pub homing_lifetime: f32,
+}
+
+impl Default for ProjectileConfig {
+	fn default() -> Self {
+		Self {
+			fire_interval: 1.5,
+			damage: 1.0,
+			speed: 180.0,
+			lifetime: 3.0,
+			homing_speed: 90.0,
+			homing_turn_rate: 2.0,
+			homing_lifetime: 4.0,
+		}
+	}
+}
+
+/// Sent each time the player fires a flash burst
+pub struct FlashBurst;
+
+/// Sent every `DustConfig.stride` the player walks, for the dust and the step sound
+pub struct Footstep {
+	pub position: Vec3,
+	/// Opposite to where the player is heading
+	pub behind: Vec2,
+	pub dashing: bool,
+}
+
+/// How the player's body and enemy bodies meet, picked with `Settings.enemy_contact`
+#[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum EnemyContact {
+	/// They shove each other. Enemies can pin the player against walls and body-block corridors,
+	/// and the player can push a lone enemy aside, but a pack piling on feels sticky
+	#[default]
+	Solid,
+	/// They overlap and touching only hurts. Nothing blocks the player's escape route, which
+	/// suits stealth, but enemies can't be held back with the body and stack on top of the player.
+	/// Dashing through them still hurts, `DashConfig.phase_through_enemies` only affects the body
+	Sensor,
+}
+
+/// Every enemy hurts on touch with `contact_damage`, kinds where `EnemyKind::attacks` is true
+/// (Brutes and Bosses) also wind up a swing worth `attack_damage`. Either one can be 0.0, so
+/// an enemy can grab and drag without hurting or only hurt when it swings
+pub struct CombatConfig {
+	/// Damage per hit while an enemy touches the player, 0.0 makes touching harmless
+	pub contact_damage: f32,
+	pub enemy_contact: EnemyContact,
+	/// Damage of a wound-up attack that lands, 0.0 makes attacks harmless
+	pub attack_damage: f32,
+	/// How close to the player an attacking enemy starts winding up, and has to still be when it swings
+	pub attack_range: f32,
+	/// Seconds between starting an attack and landing it, the player's window to get away
+	pub attack_windup: f32,
+	/// Seconds after a swing before the next one can start
+	pub attack_cooldown: f32,
+	/// Seconds of i-frames the player gets after taking a hit, from any source
+	pub invincibility: f32,
+	/// Seconds a newly placed or recycled enemy can't hurt the player once it wakes, 0.0 turns it off
+	pub spawn_immunity: f32,
+	/// Seconds the player can't be hurt after respawning from a death, cut short by any input
+	pub respawn_protection: f32,
+}
+
+impl Default for CombatConfig {
+	fn default() -> Self {
+		Self {
+			contact_damage: 1.0,
+			enemy_contact: EnemyContact::default(),
+			attack_damage: 2.0,
+			attack_range: 20.0,
+			attack_windup: 0.4,
+			attack_cooldown: 1.2,
+			invincibility: 1.0,
+			spawn_immunity: 1.0,
+			respawn_protection: 2.0,
+		}
+	}
+}
+
+pub struct Damage {
+	pub target: Entity,
+	pub amount: f32,
+	/// Where the hit came from, an enemy it kills is knocked away from there
+	pub source: Option<Vec3>,
+}
+
+/// Every hit dealt this frame, pushed by the damage sources and drained by `apply_damage`
+#[derive(Default)]
+pub struct DamageQueue(pub Vec<Damage>);
+
+/// Set by `restart_on_death` until the reloaded player shows up, see `protect_respawn`
+#[derive(Default)]
+pub struct PendingRespawn(pub bool);
+
+/// Starts the run over from the first level, see `new_game`
+pub struct NewGame;
+
+/// Sent when the player's health reaches zero
+pub struct PlayerDied;
+
+/// Brief near-freeze of physics that sells an impact, started with `HitStop::start`
+pub struct HitStop {
+	pub timer: Option<Timer>,
+	/// Seconds the freeze lasts
+	pub duration: f32,
+	/// Physics speed while frozen
+	pub scale: f32,
+	/// Smallest hit that freezes, kills freeze whatever they dealt
+	pub min_damage: f32,
+}
+
+impl Default for HitStop {
+	fn default() -> Self {
+		Self {
+			timer: None,
+			duration: 0.08,
+			scale: 0.05,
+			min_damage: 2.0,
+		}
+	}
+}
+
+impl HitStop {
+	pub fn start(&mut self) {
+		self.timer = Some(Timer::from_seconds(self.duration, false));
+	}
+}
+
+/// Slow motion with a red pulse around the screen when the player's health first drops to
+/// `threshold`, started by `last_stand`
+///
+/// Ends after `duration` or as soon as health climbs back over the threshold, and can happen
+/// again once it has. Hit-stop and the pause still win over it, see `apply_time_scale`.
+pub struct LastStand {
+	pub enabled: bool,
+	pub timer: Option<Timer>,
+	/// Whether dropping to the threshold starts it, spent until health recovers
+	pub armed: bool,
+	/// Fraction of max health at or below which it starts
+	pub threshold: f32,
+	/// Physics speed while it lasts
+	pub scale: f32,
+	/// Seconds of slow motion
+	pub duration: f32,
+	pub vignette_alpha: f32,
+	/// Pulses per second of the vignette
+	pub pulse_speed: f32,
+}
+
+impl Default for LastStand {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			timer: None,
+			armed: true,
+			threshold: 0.34,
+			scale: 0.4,
+			duration: 1.5,
+			vignette_alpha: 0.6,
+			pulse_speed: 2.0,
+		}
+	}
+}
+
+/// Aim marker drawn at the cursor, toggled with R
+pub struct ReticleConfig {
+	pub visible: bool,
+	/// Image for the reticle, None draws a plain square
+	pub texture: Option<String>,
+}
+
+impl Default for ReticleConfig {
+	fn default() -> Self {
+		Self {
+			visible: true,
+			texture: None,
+		}
+	}
+}
+
+/// Development overlays, each one toggled on its own key
+pub struct DebugConfig {
+	/// F1: enemy target lines and detection radius, colored by `EnemyState`
+	pub ai: bool,
+	/// F2: camera detached from the player, WASD or arrows pan, scroll zooms, no darkness
+	pub free_look: bool,
+	/// F3: FPS, frame time and entity count
+	pub diagnostics: bool,
+	/// F4: lines along every cell of the loaded level's `NavGrid`
+	pub grid: bool,
+	pub grid_color: Color,
+	/// F5: tints every cell some enemy can see right now, redder the more of them see it
+	pub heatmap: bool,
+	/// Seconds between heat map refreshes, each one casts a ray per cell in sight of each enemy
+	pub heatmap_interval: f32,
+	/// F6: clicking an entity shows its health, physics and AI live, clicking nothing deselects.
+	/// The click doesn't reach the flashlight meanwhile
+	pub inspector: bool,
+}
+
+impl Default for DebugConfig {
+	fn default() -> Self {
+		Self {
+			ai: false,
+			free_look: false,
+			diagnostics: false,
+			grid: false,
+			grid_color: Color::rgba(1.0, 1.0, 1.0, 0.15),
+			heatmap: false,
+			heatmap_interval: 0.5,
+			inspector: false,
+		}
+	}
+}
+
+/// What the player sprite flips to face, picked with `Settings.facing`
+#[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum FacingMode {
+	/// Towards the cursor
+	#[default]
+	Aim,
+	/// Towards where the player is walking
+	Movement,
+}
+
+impl FacingMode {
+	pub fn next(&self) -> Self {
+		match self {
+			FacingMode::Aim => FacingMode::Movement,
+			FacingMode::Movement => FacingMode::Aim,
+		}
+	}
+}
+
+pub struct EnemyConfig {
+	/// Distance at which an idle or returning enemy notices a player in line of sight and starts chasing
+	pub detection_range: f32,
+	/// Smaller than `detection_range`: past it a chasing enemy gives up, once `min_chase_time`
+	/// is over, and a searching one has to get the player this close to chase again. Between the
+	/// two ranges a fresh enemy gives chase and one that already gave up doesn't, so nobody
+	/// flips between chasing and searching at the edge
+	pub lose_range: f32,
+	/// Seconds a chase lasts at least, even if the player breaks line of sight straight away
+	pub min_chase_time: f32,
+	/// Fraction of `speed` used to walk a `PatrolRoute` or wander
+	pub patrol_speed: f32,
+	/// How far from home an enemy without a route wanders, 0.0 keeps it standing still
+	pub wander_radius: f32,
+	/// Seconds a wandering enemy stands still between two points
+	pub wander_rest: f32,
+	pub speed: f32,
+	/// Radians per second an enemy can turn, fast enough that it still runs down a player
+	/// fleeing in a straight line but slow enough that sharp jukes around corners shake it
+	pub turn_rate: f32,
+	/// Distance at which moving enemies start steering around each other, and around the player
+	/// when they aren't after it
+	pub avoidance_radius: f32,
+	/// How hard they steer away compared to following their path, 0.0 turns avoidance off
+	pub avoidance_strength: f32,
+	/// Seconds a chasing enemy waits before asking the `NavGrid` for a new path
+	pub repath_interval: f32,
+	/// Paths searched per run of the AI, the rest of the enemies keep their old one and ask on
+	/// the next run. None searches them all at once
+	pub paths_per_frame: Option<usize>,
+	/// Seconds a `LightVulnerable` enemy spends inside the flashlight for each hit of `light_damage`
+	pub light_threshold: f32,
+	/// Exposure lost per second outside the light
+	pub light_decay: f32,
+	/// Damage dealt each time a `LightVulnerable` enemy's exposure fills up
+	pub light_damage: f32,
+	/// Idle enemies this close to a chasing one start searching where it saw the player
+	pub alert_radius: f32,
+	/// Seconds between alerts sent or received by the same enemy
+	pub alert_cooldown: f32,
+	/// Distance at which the player stops hearing an enemy
+	pub hearing_range: f32,
+	/// Volume multiplier for an enemy heard through a wall, 1.0 turns muffling off
+	pub wall_muffle: f32,
+	/// Enemies woken per frame after a level loads, None wakes them all at once
+	pub activations_per_frame: Option<usize>,
+}
+
+impl Default for EnemyConfig {
+	fn default() -> Self {
+		Self {
+			detection_range: 260.0,
+			lose_range: 200.0,
+			min_chase_time: 1.0,
+			patrol_speed: 0.5,
+			wander_radius: 48.0,
+			wander_rest: 2.0,
+			speed: 90.0,
+			turn_rate: 6.0,
+			avoidance_radius: 20.0,
+			avoidance_strength: 0.8,
+			repath_interval: 0.5,
+			paths_per_frame: Some(8),
+			light_threshold: 1.5,
+			light_decay: 0.5,
+			light_damage: 1.0,
+			alert_radius: 150.0,
+			alert_cooldown: 3.0,
+			hearing_range: 320.0,
+			wall_muffle: 0.35,
+			activations_per_frame: Some(4),
+		}
+	}
+}
+
+/// Multipliers over `EnemyConfig` and `CombatConfig` for the current level, read from the LDtk
+/// level fields `enemy_speed_mult`, `enemy_sight_mult` and `enemy_damage_mult`
+///
+/// Rebuilt from scratch for every level, a level without the fields plays at 1.0.
+pub struct LevelDifficulty {
+	pub enemy_speed_mult: f32,
+	pub enemy_sight_mult: f32,
+	/// Scales contact, attack and projectile damage dealt to the player
+	pub enemy_damage_mult: f32,
+}
+
+impl Default for LevelDifficulty {
+	fn default() -> Self {
+		Self {
+			enemy_speed_mult: 1.0,
+			enemy_sight_mult: 1.0,
+			enemy_damage_mult: 1.0,
+		}
+	}
+}
+
+pub struct DecalConfig {
+	/// Seconds a scorch mark takes to fade away
+	pub lifetime: f32,
+	/// Oldest marks are removed first once there are more than this
+	pub max: usize,
+	pub color: Color,
+}
+
+impl Default for DecalConfig {
+	fn default() -> Self {
+		Self {
+			lifetime: 10.0,
+			max: 32,
+			color: Color::rgba(1.0, 0.45, 0.15, 0.5),
+		}
+	}
+}
+
+/// Picked with `Settings.spawn_effect`
+#[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum SpawnEffect {
+	/// From transparent to opaque
+	Fade,
+	/// From a point to full size
+	Scale,
+}
+
+/// How the player, enemies and goal appear when a level loads, walls always pop in at once
+pub struct SpawnAnimConfig {
+	/// None makes everything appear at once
+	pub effect: Option<SpawnEffect>,
+	/// Seconds the effect lasts
+	pub duration: f32,
+}
+
+impl Default for SpawnAnimConfig {
+	fn default() -> Self {
+		Self {
+			effect: Some(SpawnEffect::Fade),
+			duration: 0.3,
+		}
+	}
+}
+
+/// Dead enemies kept around for reuse, see `acquire_enemy` and `recycle_enemy`
+///
+/// The spawns of each level take from it before spawning anything new, quitting to the menu
+/// despawns what's left.
+#[derive(Default)]
+pub struct EnemyPool {
+	pub inactive: Vec<Entity>,
+}
+
+/// Where the player starts the current level, relative to the level
+///
+/// Taken from the LDtk Player entity when the level loads, or the level center if there's none.
+#[derive(Default)]
+pub struct LevelStart {
+	pub translation: Vec3,
+}
+
+/// Countdown at the start of each level during which enemies ignore the player
+pub struct LevelGrace {
+	pub timer: Option<Timer>,
+	/// Seconds of each countdown, 0.0 turns it off
+	pub seconds: f32,
+}
+
+impl Default for LevelGrace {
+	fn default() -> Self {
+		Self {
+			timer: None,
+			seconds: 3.0,
+		}
+	}
+}
+
+impl LevelGrace {
+	pub fn active(&self) -> bool {
+		self.timer.is_some()
+	}
+}
+
+/// Running while the win screen is up, the next level is loaded when it finishes
+#[derive(Default)]
+pub struct LevelTransition {
+	pub timer: Option<Timer>,
+	/// From picking the next level until it's `Transformed`, contacts with the old goal that
+	/// arrive meanwhile don't count
+	pub loading: bool,
+}
+
+/// Mood of the current level, the clear color and darkness overlay ease towards it
+///
+/// Read on load from the LDtk level fields `clear_color`, `darkness_color` and `darkness_alpha`.
+pub struct AmbientTint {
+	/// None until a level loads, so the project background set by bevy_ecs_ldtk is kept
+	pub clear_color: Option<Color>,
+	pub darkness: Color,
+}
+
+impl Default for AmbientTint {
+	fn default() -> Self {
+		Self {
+			clear_color: None,
+			darkness: Color::NONE,
+		}
+	}
+}
+
+/// Limits every darkness source has to respect, set from `Settings`
+#[derive(Default)]
+pub struct LightingConfig {
+	/// Fraction of the scene that always stays visible, 0.0 lets the darkness reach pure black
+	pub min_ambient: f32,
+}
+
+/// Collectibles picked up in the current level, `total` is counted when the level loads
+#[derive(Default)]
+pub struct Score {
+	pub level_uid: i32,
+	pub collected: usize,
+	pub total: usize,
+}
+
+/// Every collectible picked up this run by level uid and `CollectibleId`, so leaving and coming
+/// back to a level doesn't bring them back. Cleared on a new game
+#[derive(Default)]
+pub struct CollectedEntities(pub HashSet<(i32, CollectibleId)>);
+
+/// Records kept across levels for the whole session
+#[derive(Default)]
+pub struct Stats {
+	/// Collectibles picked up on the best completion of each level, by level uid
+	pub best_collected: HashMap<i32, usize>,
+}
+
+/// A background image that scrolls at `factor` times the camera movement
+///
+/// 0.0 stays glued to the level, 1.0 follows the camera and looks infinitely far away.
+pub struct ParallaxLayer {
+	pub texture: String,
+	pub factor: f32,
+}
+
+/// Background layers spawned behind the level, listed from nearest to farthest
+#[derive(Default)]
+pub struct ParallaxLayers(pub Vec<ParallaxLayer>);
@@ -0,0 +1,133 @@
+use std::{collections::HashMap, fs};
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	components::CollectibleId,
+	pause::PauseUi,
+	records::BestTimes,
+	resources::*,
+	settings::Settings,
+};
+
+// Mismo sitio que settings.ron y records.ron
+const SAVE_PATH: &str = "save.ron";
+/// Bumped whenever `SaveFile` changes in a way older builds can't read
+const SAVE_VERSION: u32 = 1;
+
+/// Everything kept between sessions in one file, to back it up, attach it to a bug report or
+/// carry it to another machine
+#[derive(Serialize, Deserialize)]
+pub struct SaveFile {
+	pub version: u32,
+	pub settings: Settings,
+	pub best_times: BestTimes,
+	/// `Stats.best_collected`
+	pub best_collected: HashMap<i32, usize>,
+	pub collected: Vec<(i32, CollectibleId)>,
+	/// Level the player was on, loaded again on import
+	pub level_uid: Option<i32>,
+}
+
+// Se lee primero solo la version, asi un archivo de otra version no falla por sus campos
+#[derive(Deserialize)]
+struct SaveVersion {
+	version: u32,
+}
+
+pub fn export_save(save: &SaveFile) -> Result<(), String> {
+	let contents = ron::ser::to_string_pretty(save, PrettyConfig::default()).map_err(|error| error.to_string())?;
+	fs::write(SAVE_PATH, contents).map_err(|error| error.to_string())
+}
+
+/// Reads `save.ron` back, any file that isn't a save of this version is an error, never a panic
+pub fn import_save() -> Result<SaveFile, String> {
+	let contents = fs::read_to_string(SAVE_PATH).map_err(|error| error.to_string())?;
+	let SaveVersion { version } = ron::from_str(&contents).map_err(|error| format!("not a save file ({})", error))?;
+	if version != SAVE_VERSION {
+		return Err(format!("save version {} can't be read, this build reads version {}", version, SAVE_VERSION));
+	}
+	ron::from_str(&contents).map_err(|error| format!("corrupt save file ({})", error))
+}
+
+fn show_status(query: &mut Query<&mut Text, With<PauseUi>>, status: String) {
+	for mut text in query.iter_mut() {
+		text.sections[4].value = format!("\n{}", status);
+	}
+}
+
+/// S on the pause screen writes `save.ron`
+#[allow(clippy::too_many_arguments)]
+pub fn export_save_key(
+	input: Res<Input<KeyCode>>,
+	state: Res<State<GameState>>,
+	settings: Res<Settings>,
+	best_times: Res<BestTimes>,
+	stats: Res<Stats>,
+	collected: Res<CollectedEntities>,
+	score: Res<Score>,
+	mut text_query: Query<&mut Text, With<PauseUi>>,
+) {
+	if *state.current() != GameState::Paused || !input.just_pressed(KeyCode::S) { return }
+	let save = SaveFile {
+		version: SAVE_VERSION,
+		settings: settings.clone(),
+		best_times: best_times.clone(),
+		best_collected: stats.best_collected.clone(),
+		collected: collected.0.iter().copied().collect(),
+		level_uid: Some(score.level_uid),
+	};
+	let status = match export_save(&save) {
+		Ok(()) => format!("Saved to {}", SAVE_PATH),
+		Err(error) => {
+			warn!("Couldn't export {}: {}", SAVE_PATH, error);
+			format!("Couldn't save: {}", error)
+		}
+	};
+	show_status(&mut text_query, status);
+}
+
+/// L on the pause screen replaces the progress with `save.ron`, a bad file leaves it untouched
+#[allow(clippy::too_many_arguments)]
+pub fn import_save_key(
+	input: Res<Input<KeyCode>>,
+	state: Res<State<GameState>>,
+	mut settings: ResMut<Settings>,
+	mut best_times: ResMut<BestTimes>,
+	mut stats: ResMut<Stats>,
+	mut collected: ResMut<CollectedEntities>,
+	mut level_selection: ResMut<LevelSelection>,
+	ldtk_assets: Res<Assets<LdtkAsset>>,
+	ldtk_query: Query<&Handle<LdtkAsset>>,
+	mut text_query: Query<&mut Text, With<PauseUi>>,
+) {
+	if *state.current() != GameState::Paused || !input.just_pressed(KeyCode::L) { return }
+	let ldtk_asset = ldtk_query.get_single().ok().and_then(|handle| ldtk_assets.get(handle));
+	// Un nivel que ya no existe dejaria el mundo vacio
+	let save = import_save().and_then(|save| match (save.level_uid, ldtk_asset) {
+		(Some(level_uid), Some(ldtk_asset)) if ldtk_asset.get_level(&LevelSelection::Uid(level_uid)).is_none() => {
+			Err(format!("level {} isn't in this build", level_uid))
+		}
+		_ => Ok(save),
+	});
+	let save = match save {
+		Ok(save) => save,
+		Err(error) => {
+			warn!("Ignoring {}: {}", SAVE_PATH, error);
+			show_status(&mut text_query, format!("Couldn't load: {}", error));
+			return;
+		}
+	};
+	// settings.ron y records.ron se reescriben solos al cambiar
+	*settings = save.settings;
+	*best_times = save.best_times;
+	stats.best_collected = save.best_collected;
+	collected.0 = save.collected.into_iter().collect();
+	if let Some(level_uid) = save.level_uid {
+		*level_selection = LevelSelection::Uid(level_uid);
+	}
+	show_status(&mut text_query, format!("Loaded {}", SAVE_PATH));
+}
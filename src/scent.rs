@@ -0,0 +1,111 @@
+use bevy::prelude::*;
+
+use crate::{components::*, resources::LevelGrace};
+
+/// Fake trails dropped by `Mimic` enemies
+pub struct ScentConfig {
+	/// Seconds between samples of the player's position, and between dropped markers
+	pub interval: f32,
+	/// How many samples make up the replayed trail
+	pub history: usize,
+	/// Seconds each marker lasts
+	pub lifetime: f32,
+	/// Markers alive at once across every mimic
+	pub max: usize,
+	/// Distance at which enemies pick up a marker, 0.0 turns the lure off
+	pub radius: f32,
+}
+
+impl Default for ScentConfig {
+	fn default() -> Self {
+		Self {
+			interval: 0.5,
+			history: 8,
+			lifetime: 4.0,
+			max: 24,
+			radius: 160.0,
+		}
+	}
+}
+
+/// Mimics record where the player walks and lay the same path down, starting from themselves
+pub fn drop_scent(
+	mut commands: Commands,
+	time: Res<Time>,
+	scent_config: Res<ScentConfig>,
+	// Escondido no deja rastro
+	player_query: Query<&Transform, (With<Player>, Without<Hidden>)>,
+	marker_query: Query<(), With<ScentMarker>>,
+	mut mimic_query: Query<(&mut ScentTrail, &EnemyKind, &Transform), (With<Enemy>, Without<Dormant>, Without<DyingEnemy>)>,
+) {
+	let player_translation = match player_query.get_single() {
+		Ok(transform) => transform.translation.truncate(),
+		Err(_) => return,
+	};
+	let mut markers = marker_query.iter().count();
+	for (mut trail, kind, transform) in mimic_query.iter_mut() {
+		if *kind != EnemyKind::Mimic { continue }
+		if !trail.timer.tick(time.delta()).finished() { continue }
+		trail.timer = Timer::from_seconds(scent_config.interval, false);
+
+		trail.history.push_back(player_translation);
+		// Con history a 0 se guarda igualmente la ultima posicion
+		let history = scent_config.history.max(1);
+		while trail.history.len() > history { trail.history.pop_front(); }
+		if markers >= scent_config.max { continue }
+
+		// El rastro arranca en el imitador con la misma forma que el camino del jugador
+		let origin = trail.history[0];
+		let sample = trail.history[trail.replay % trail.history.len()];
+		trail.replay = (trail.replay + 1) % history;
+		let position = transform.translation.truncate() + sample - origin;
+		commands.spawn()
+			.insert(ScentMarker)
+			.insert(Transform::from_translation(position.extend(0.0)))
+			.insert(Lifetime { timer: Timer::from_seconds(scent_config.lifetime, false) });
+		markers += 1;
+	}
+}
+
+/// Enemies that can't see the player search the nearest scent marker in range instead
+pub fn follow_scent(
+	scent_config: Res<ScentConfig>,
+	grace: Res<LevelGrace>,
+	marker_query: Query<&Transform, With<ScentMarker>>,
+	mut enemy_query: Query<(&mut EnemyState, &mut Alert, &EnemyKind, &Vision, &Transform), (With<Enemy>, Without<Dormant>, Without<Stunned>, Without<DyingEnemy>)>,
+) {
+	if scent_config.radius <= 0.0 || marker_query.is_empty() || grace.active() { return }
+	for (mut state, mut alert, kind, vision, transform) in enemy_query.iter_mut() {
+		if *kind == EnemyKind::Mimic || vision.can_see_player || matches!(*state, EnemyState::Chase | EnemyState::Return) { continue }
+		let translation = transform.translation.truncate();
+		let nearest = marker_query.iter()
+			.map(|marker| marker.translation.truncate())
+			.filter(|marker| marker.distance(translation) < scent_config.radius)
+			.min_by(|a, b| a.distance(translation).total_cmp(&b.distance(translation)));
+		if let Some(marker) = nearest {
+			*state = EnemyState::Search;
+			alert.target = marker;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util::*;
+
+	#[test]
+	fn zero_history_still_drops_markers() {
+		let mut app = test_app();
+		app.insert_resource(ScentConfig { history: 0, ..Default::default() })
+			.add_system(drop_scent);
+		spawn_player(&mut app.world, Vec2::new(40.0, 0.0));
+		let mimic = spawn_enemy(&mut app.world, Vec2::new(-20.0, 5.0));
+		app.world.entity_mut(mimic).insert(EnemyKind::Mimic);
+		app.update();
+
+		assert_eq!(app.world.get::<ScentTrail>(mimic).unwrap().history.len(), 1);
+		let markers: Vec<Vec2> = app.world.query_filtered::<&Transform, With<ScentMarker>>().iter(&app.world).map(|transform| transform.translation.truncate()).collect();
+		assert_eq!(markers, vec![Vec2::new(-20.0, 5.0)]);
+	}
+}
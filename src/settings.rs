@@ -0,0 +1,152 @@
+use std::fs;
+
+use bevy::prelude::*;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+
+use crate::{components::EnemyState, difficulty::Difficulty, resources::{CombatConfig, EnemyContact, FacingMode, LightingConfig, SpawnAnimConfig, SpawnEffect}};
+
+// Junto al ejecutable cuando se lanza desde su carpeta, con cargo run queda en la raiz del repo
+const SETTINGS_PATH: &str = "settings.ron";
+// Con reduced_darkness siempre se ve al menos un tercio de la escena
+const REDUCED_DARKNESS_MIN_AMBIENT: f32 = 0.35;
+
+/// Player preferences, loaded from `settings.ron` at startup and written back whenever they change
+///
+/// Missing fields fall back to their defaults, so older files keep working as options are added.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct Settings {
+	/// High-contrast mode: the darkness overlay never covers more than `1 - min_ambient` of the scene
+	pub reduced_darkness: bool,
+	/// Master volume for sound effects, from 0.0 (muted) to 1.0
+	pub sfx_volume: f32,
+	/// Master volume for the music, every layer is scaled by it
+	pub music_volume: f32,
+	pub pause_on_focus_loss: bool,
+	/// Only resumes games that were paused by losing focus, never a manual pause
+	pub resume_on_focus: bool,
+	/// Picked on the pause screen, used from the next new game on
+	pub difficulty: Difficulty,
+	/// Colors for the flashlight reveal and the enemy state tints
+	pub palette: Palette,
+	/// "!" over chasing enemies and "?" over searching ones, readable without telling colors apart
+	pub alert_icons: bool,
+	/// Numbers floating up from whoever takes damage
+	pub damage_numbers: bool,
+	/// Whether the player sprite faces the cursor or where it walks
+	pub facing: FacingMode,
+	/// Whether enemies block the player or only hurt on touch, only set in the file. Takes effect
+	/// the next time the player spawns
+	pub enemy_contact: EnemyContact,
+	/// How the player, enemies and goal appear when a level loads, None for at once
+	pub spawn_effect: Option<SpawnEffect>,
+}
+
+/// Enemy tints remapped for the common kinds of color blindness
+#[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum Palette {
+	#[default]
+	Standard,
+	/// Deuteranopia and protanopia, no red against green or orange
+	RedGreen,
+	/// Tritanopia, no blue against yellow
+	BlueYellow,
+}
+
+impl Palette {
+	pub fn next(&self) -> Self {
+		match self {
+			Palette::Standard => Palette::RedGreen,
+			Palette::RedGreen => Palette::BlueYellow,
+			Palette::BlueYellow => Palette::Standard,
+		}
+	}
+
+	pub fn state_color(&self, state: EnemyState) -> Color {
+		let idle = Color::rgba(0.5, 0.5, 0.5, 0.5);
+		// Los de RedGreen salen de la paleta de Okabe-Ito
+		match (self, state) {
+			(_, EnemyState::Idle) => idle,
+			(Palette::Standard, EnemyState::Chase) => Color::RED,
+			(Palette::Standard, EnemyState::Search) => Color::ORANGE,
+			(Palette::Standard, EnemyState::Return) => Color::BLUE,
+			(Palette::RedGreen, EnemyState::Chase) => Color::rgb(0.9, 0.6, 0.0),
+			(Palette::RedGreen, EnemyState::Search) => Color::rgb(0.35, 0.7, 0.9),
+			(Palette::RedGreen, EnemyState::Return) => Color::rgb(0.8, 0.6, 0.7),
+			(Palette::BlueYellow, EnemyState::Chase) => Color::rgb(0.85, 0.15, 0.15),
+			(Palette::BlueYellow, EnemyState::Search) => Color::rgb(0.0, 0.6, 0.5),
+			(Palette::BlueYellow, EnemyState::Return) => Color::rgb(1.0, 0.6, 0.8),
+		}
+	}
+
+	/// Tint of enemies caught in the flashlight, past 1.0 so it brightens the sprite
+	pub fn reveal_color(&self) -> Color {
+		match self {
+			// Un toque calido de la linterna
+			Palette::Standard => Color::rgb(2.0, 2.0, 1.6),
+			Palette::RedGreen => Color::rgb(1.6, 1.8, 2.2),
+			Palette::BlueYellow => Color::rgb(2.2, 1.7, 1.7),
+		}
+	}
+}
+
+impl Default for Settings {
+	fn default() -> Self {
+		Self {
+			reduced_darkness: false,
+			sfx_volume: 1.0,
+			music_volume: 0.6,
+			pause_on_focus_loss: true,
+			resume_on_focus: false,
+			difficulty: Difficulty::default(),
+			palette: Palette::default(),
+			alert_icons: false,
+			damage_numbers: true,
+			facing: FacingMode::default(),
+			enemy_contact: EnemyContact::default(),
+			spawn_effect: Some(SpawnEffect::Fade),
+		}
+	}
+}
+
+impl Settings {
+	pub fn load() -> Self {
+		let contents = match fs::read_to_string(SETTINGS_PATH) {
+			Ok(contents) => contents,
+			Err(_) => return Self::default(),
+		};
+		ron::from_str(&contents).unwrap_or_else(|error| {
+			warn!("Ignoring invalid {}: {}", SETTINGS_PATH, error);
+			Self::default()
+		})
+	}
+
+	fn save(&self) {
+		let result = ron::ser::to_string_pretty(self, PrettyConfig::default())
+			.map_err(|error| error.to_string())
+			.and_then(|contents| fs::write(SETTINGS_PATH, contents).map_err(|error| error.to_string()));
+		if let Err(error) = result {
+			warn!("Couldn't save {}: {}", SETTINGS_PATH, error);
+		}
+	}
+}
+
+pub fn apply_settings(
+	settings: Res<Settings>,
+	mut lighting_config: ResMut<LightingConfig>,
+	mut combat_config: ResMut<CombatConfig>,
+	mut spawn_anim_config: ResMut<SpawnAnimConfig>,
+) {
+	if !settings.is_changed() { return }
+	lighting_config.min_ambient = if settings.reduced_darkness { REDUCED_DARKNESS_MIN_AMBIENT } else { 0.0 };
+	combat_config.enemy_contact = settings.enemy_contact;
+	spawn_anim_config.effect = settings.spawn_effect;
+}
+
+pub fn save_settings(settings: Res<Settings>) {
+	// Recien cargadas no hay nada nuevo que escribir
+	if settings.is_changed() && !settings.is_added() {
+		settings.save();
+	}
+}
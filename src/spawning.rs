@@ -0,0 +1,256 @@
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use heron::CollisionLayers;
+use rand::{distributions::WeightedIndex, prelude::*, rngs::StdRng, seq::SliceRandom};
+
+use crate::{
+	components::*,
+	navigation::FloorMaterial,
+	resources::{EnemyPool, GameConfig, LevelStart},
+	systems::{acquire_enemy, int_grid_layer, layer_offset, level_field},
+};
+
+/// Every random roll of the game goes through here, so a fixed seed replays the same run
+pub struct GameRng(pub StdRng);
+
+impl Default for GameRng {
+	fn default() -> Self {
+		Self(StdRng::from_entropy())
+	}
+}
+
+impl GameRng {
+	/// The same seed rolls the same run, see `GameConfig.seed`
+	pub fn new(seed: Option<u64>) -> Self {
+		match seed {
+			Some(seed) => Self(StdRng::seed_from_u64(seed)),
+			None => Self::default(),
+		}
+	}
+}
+
+/// Chance of each `EnemyKind` when a `SpawnPoint` fires, as relative weights
+///
+/// The resource is used by levels without a `spawn_table` field. The field is a string like
+/// `"Stalker: 3, Brute: 1"`, kinds left out never spawn.
+#[derive(Clone, Debug)]
+pub struct SpawnTable {
+	pub weights: Vec<(EnemyKind, f32)>,
+}
+
+impl Default for SpawnTable {
+	fn default() -> Self {
+		Self { weights: vec![(EnemyKind::Stalker, 1.0)] }
+	}
+}
+
+impl SpawnTable {
+	pub fn parse(table: &str) -> Result<Self, String> {
+		let weights = table.split(',').map(str::trim).filter(|entry| !entry.is_empty()).map(|entry| {
+			let (name, weight) = entry.split_once(':').ok_or_else(|| format!("\"{}\" is not kind: weight", entry))?;
+			let kind = EnemyKind::from_name(name.trim()).ok_or_else(|| format!("unknown enemy kind \"{}\"", name.trim()))?;
+			let weight = weight.trim().parse::<f32>().map_err(|error| format!("bad weight for {}: {}", name.trim(), error))?;
+			Ok((kind, weight))
+		}).collect::<Result<Vec<_>, String>>()?;
+		// WeightedIndex ya rechaza una tabla vacia o con todo a cero
+		WeightedIndex::new(weights.iter().map(|(_, weight)| *weight)).map_err(|error| error.to_string())?;
+		Ok(Self { weights })
+	}
+
+	pub fn roll(&self, rng: &mut impl Rng) -> EnemyKind {
+		match WeightedIndex::new(self.weights.iter().map(|(_, weight)| *weight)) {
+			Ok(index) => self.weights[index.sample(rng)].0,
+			Err(_) => EnemyKind::default(),
+		}
+	}
+}
+
+fn level_table(level: &ldtk::Level, default_table: &SpawnTable) -> SpawnTable {
+	match level_field(level, "spawn_table") {
+		Some(FieldValue::String(Some(table))) => SpawnTable::parse(table).unwrap_or_else(|error| {
+			warn!("Ignoring spawn_table of level \"{}\": {}", level.identifier, error);
+			default_table.clone()
+		}),
+		_ => default_table.clone(),
+	}
+}
+
+/// Takes an enemy from the pool if there's one, or spawns a new one
+#[allow(clippy::too_many_arguments)]
+fn spawn_enemy(
+	commands: &mut Commands,
+	pool: &mut EnemyPool,
+	alive: &Query<(), With<Enemy>>,
+	instance: &EntityInstance,
+	kind: EnemyKind,
+	layer: &LayerInstance,
+	asset_server: &AssetServer,
+	texture_atlases: &mut Assets<TextureAtlas>,
+	transform: Transform,
+	parent: Entity,
+) {
+	let health = Health::new(Health::max_field(instance).unwrap_or_else(|| kind.max_health()));
+	let acquired = acquire_enemy(commands, pool, alive, transform.translation);
+	let mut enemy = match acquired {
+		Some(entity) => commands.entity(entity),
+		None => commands.spawn(),
+	};
+	enemy.insert_bundle(EnemyBundle::bundle_entity(instance, layer, None, None, asset_server, texture_atlases))
+		.insert(kind)
+		.insert(health)
+		.insert(transform)
+		.insert(GlobalTransform::default())
+		.insert(Parent(parent));
+	// El bundle le devuelve las capas, pero sigue dormido hasta que activate_enemies lo despierte
+	if acquired.is_some() {
+		enemy.insert(CollisionLayers::none());
+	}
+}
+
+/// Replaces every `SpawnPoint` of the level that just loaded with an enemy of a rolled kind
+#[allow(clippy::too_many_arguments)]
+pub fn fire_spawn_points(
+	mut commands: Commands,
+	mut level_events: EventReader<LevelEvent>,
+	asset_server: Res<AssetServer>,
+	levels: Res<Assets<LdtkLevel>>,
+	mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+	default_table: Res<SpawnTable>,
+	mut rng: ResMut<GameRng>,
+	mut pool: ResMut<EnemyPool>,
+	level_query: Query<&Handle<LdtkLevel>>,
+	point_query: Query<(Entity, &EntityInstance, &Transform, &Parent), With<SpawnPoint>>,
+	alive_query: Query<(), With<Enemy>>,
+) {
+
+	for event in level_events.iter() {
+		let uid = match event {
+			LevelEvent::Transformed(uid) => *uid,
+			_ => continue,
+		};
+		let level = match level_query.iter().find_map(|handle| levels.get(handle).filter(|level| level.level.uid == uid)) {
+			Some(level) => &level.level,
+			None => continue,
+		};
+		let table = level_table(level, &default_table);
+		// AnimatedSpriteBundle no mira la capa, cualquiera sirve
+		let layer = match int_grid_layer(level).or_else(|| level.layer_instances.as_ref().and_then(|layers| layers.first())) {
+			Some(layer) => layer,
+			None => continue,
+		};
+
+		for (entity, instance, transform, parent) in point_query.iter() {
+			let instance = EntityInstance { identifier: "Enemy".to_string(), ..instance.clone() };
+			spawn_enemy(&mut commands, &mut pool, &alive_query, &instance, table.roll(&mut rng.0), layer, &asset_server, &mut texture_atlases, *transform, parent.0);
+			commands.entity(entity).despawn();
+		}
+	}
+}
+
+/// Scatters `GameConfig.procedural_enemies` over the free floor of the level that just loaded
+///
+/// Only floor cells are used, never walls or safe zones, and none within
+/// `min_player_distance` of the player's start.
+#[allow(clippy::too_many_arguments)]
+pub fn procedural_spawn(
+	mut commands: Commands,
+	mut level_events: EventReader<LevelEvent>,
+	game_config: Res<GameConfig>,
+	asset_server: Res<AssetServer>,
+	levels: Res<Assets<LdtkLevel>>,
+	mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+	default_table: Res<SpawnTable>,
+	level_start: Res<LevelStart>,
+	mut rng: ResMut<GameRng>,
+	mut pool: ResMut<EnemyPool>,
+	level_query: Query<(Entity, &Handle<LdtkLevel>)>,
+	placed_query: Query<Entity, (With<Enemy>, Without<Pooled>)>,
+	alive_query: Query<(), With<Enemy>>,
+) {
+	let procedural = match &game_config.procedural_enemies {
+		Some(procedural) => procedural,
+		None => return,
+	};
+	for event in level_events.iter() {
+		let uid = match event {
+			LevelEvent::Transformed(uid) => *uid,
+			_ => continue,
+		};
+		let (level_entity, level) = match level_query.iter().find_map(|(entity, handle)| levels.get(handle).filter(|level| level.level.uid == uid).map(|level| (entity, &level.level))) {
+			Some(level) => level,
+			None => continue,
+		};
+		let layer = match int_grid_layer(level) {
+			Some(layer) => layer,
+			None => continue,
+		};
+		if procedural.replace_placed {
+			placed_query.for_each(|entity| commands.entity(entity).despawn_recursive());
+		}
+
+		let size = layer.grid_size as f32;
+		let offset = layer_offset(layer);
+		let start = level_start.translation;
+		// El csv empieza por la fila de arriba, las GridCoords por la de abajo
+		let floor: Vec<Vec2> = layer.int_grid_csv.iter().enumerate()
+			.filter(|(_, value)| FloorMaterial::from_int_grid(**value).is_some())
+			.map(|(i, _)| {
+				let (x, row) = (i as i32 % layer.c_wid, i as i32 / layer.c_wid);
+				(Vec2::new(x as f32, (layer.c_hei - 1 - row) as f32) + 0.5) * size + offset
+			})
+			.filter(|center| center.distance(start.truncate()) >= procedural.min_player_distance)
+			.collect();
+		if floor.len() < procedural.count {
+			warn!("Level \"{}\" only has room for {} of {} procedural enemies", level.identifier, floor.len(), procedural.count);
+		}
+
+		let table = level_table(level, &default_table);
+		let instance = EntityInstance { identifier: "Enemy".to_string(), ..Default::default() };
+	
+		for center in floor.choose_multiple(&mut rng.0, procedural.count) {
+			let kind = table.roll(&mut rng.0);
+			let transform = Transform::from_translation(center.extend(start.z));
+			spawn_enemy(&mut commands, &mut pool, &alive_query, &instance, kind, layer, &asset_server, &mut texture_atlases, transform, level_entity);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const ROLLS: usize = 10000;
+
+	#[test]
+	fn parses_and_rejects_tables() {
+		let table = SpawnTable::parse("Stalker: 3, Brute:1,").unwrap();
+		assert_eq!(table.weights, vec![(EnemyKind::Stalker, 3.0), (EnemyKind::Brute, 1.0)]);
+		assert!(SpawnTable::parse("").is_err());
+		assert!(SpawnTable::parse("Stalker: 0").is_err());
+		assert!(SpawnTable::parse("Ghost: 1").is_err());
+		assert!(SpawnTable::parse("Stalker").is_err());
+	}
+
+	#[test]
+	fn same_seed_rolls_the_same_kinds() {
+		let table = SpawnTable::parse("Stalker: 1, Brute: 1, Mimic: 1").unwrap();
+		let roll = |seed| {
+			let mut rng = GameRng::new(Some(seed));
+			(0..100).map(|_| table.roll(&mut rng.0)).collect::<Vec<_>>()
+		};
+		assert_eq!(roll(7), roll(7));
+		assert_ne!(roll(7), roll(8));
+	}
+
+	#[test]
+	fn rolls_follow_the_weights() {
+		let table = SpawnTable::parse("Stalker: 6, Brute: 3, Boss: 1").unwrap();
+		let mut rng = GameRng::new(Some(42));
+		let rolls: Vec<EnemyKind> = (0..ROLLS).map(|_| table.roll(&mut rng.0)).collect();
+		for (kind, weight) in &table.weights {
+			let share = rolls.iter().filter(|roll| *roll == kind).count() as f32 / ROLLS as f32;
+			assert!((share - weight / 10.0).abs() < 0.02, "{:?} rolled {}", kind, share);
+		}
+		assert!(!rolls.contains(&EnemyKind::Mimic));
+	}
+}
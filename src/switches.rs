@@ -0,0 +1,68 @@
+use bevy::prelude::*;
+use heron::prelude::*;
+
+use crate::{components::*, hiding::HIDE_REACH};
+
+// Distancia desde el centro del jugador, algo mas que medio tile
+const SWITCH_REACH: f32 = 20.0;
+const SWITCH_ON_COLOR: Color = Color::rgb(0.5, 1.0, 0.5);
+const SWITCH_OFF_COLOR: Color = Color::rgb(1.0, 0.5, 0.5);
+
+/// E flips the nearest switch in reach, unless the player is next to a hiding spot or in one
+pub fn toggle_switches(
+	input: Res<Input<KeyCode>>,
+	player_query: Query<&Transform, (With<Player>, Without<Hidden>)>,
+	spot_query: Query<&Transform, With<HidingSpot>>,
+	mut switch_query: Query<(&Transform, &mut Switch)>,
+) {
+	if !input.just_pressed(KeyCode::E) { return }
+	let player_translation = match player_query.get_single() {
+		Ok(transform) => transform.translation.truncate(),
+		Err(_) => return,
+	};
+	if spot_query.iter().any(|spot| spot.translation.truncate().distance(player_translation) <= HIDE_REACH) { return }
+	let nearest = switch_query.iter_mut()
+		.map(|(transform, switch)| (transform.translation.truncate().distance(player_translation), switch))
+		.filter(|(distance, _)| *distance <= SWITCH_REACH)
+		.min_by(|a, b| a.0.total_cmp(&b.0));
+	if let Some((_, mut switch)) = nearest {
+		switch.on = !switch.on;
+	}
+}
+
+/// Works out which `Linked` entities their switches power and tints each switch by its state
+pub fn switches(
+	mut switch_query: Query<(&Switch, &mut Sprite, ChangeTrackers<Switch>)>,
+	mut linked_query: Query<&mut Linked>,
+) {
+	if !switch_query.iter().any(|(.., tracker)| tracker.is_changed()) { return }
+	for (switch, mut sprite, _) in switch_query.iter_mut() {
+		sprite.color = if switch.on { SWITCH_ON_COLOR } else { SWITCH_OFF_COLOR };
+	}
+	for mut linked in linked_query.iter_mut() {
+		let id = match &linked.id {
+			Some(id) => id,
+			None => continue,
+		};
+		let states: Vec<bool> = switch_query.iter().filter(|(switch, ..)| &switch.target == id).map(|(switch, ..)| switch.on).collect();
+		let powered = match linked.logic {
+			SwitchLogic::Any => states.contains(&true),
+			// Sin interruptores no se puede encender
+			SwitchLogic::All => !states.is_empty() && !states.contains(&false),
+		};
+		if linked.powered != powered {
+			linked.powered = powered;
+		}
+	}
+}
+
+/// Opens powered gates and shuts the rest, an open gate is neither seen nor collided with
+pub fn apply_gates(
+	mut query: Query<(&Linked, &mut CollisionLayers, &mut Visibility), (With<Gate>, Changed<Linked>)>,
+) {
+	for (linked, mut layers, mut visibility) in query.iter_mut() {
+		let open = linked.id.is_some() && linked.powered;
+		*layers = if open { CollisionLayers::none() } else { layers_for("Gate") };
+		visibility.is_visible = !open;
+	}
+}
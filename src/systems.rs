@@ -1,14 +1,63 @@
-use std::collections::{HashMap, HashSet};
+use std::{collections::{HashMap, HashSet}, f32::consts::TAU, time::Duration};
 
-use bevy::prelude::*;
-use bevy_ecs_ldtk::prelude::*;
-use heron::prelude::*;
+use bevy::{
+	input::mouse::MouseWheel,
+	math::Mat2,
+	prelude::*,
+	render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use bevy_ecs_ldtk::{ldtk::Type, prelude::*};
+use heron::{prelude::*, rapier_plugin::PhysicsWorld};
 
-use crate::components::*;
+use crate::{components::*, difficulty::ActiveDifficulty, fog::FogOfWar, navigation::{FloorMaterial, NavGrid}, records::{format_time, BestTimes, LevelTimer}, resources::*, settings::Settings, spawning::GameRng, ui::{self, UiFont}};
+use rand::Rng;
+
+const WIN_SCREEN_SECONDS: f32 = 3.0;
+const MAX_RADIAL_IMPULSE: f32 = 400.0;
+// Lo que frena por segundo un enemigo aturdido que ha salido despedido
+const STUN_FRICTION: f32 = 6.0;
+// Lo que se queda el "Run!" en pantalla al acabar la cuenta atras
+const GRACE_RUN_SECONDS: f32 = 0.6;
+
+// Por encima de la oscuridad (998.9) y del destello (999.4), por debajo de la camara
+const RETICLE_Z: f32 = 999.6;
+const RETICLE_SIZE: f32 = 4.0;
+const RETICLE_COLOR: Color = Color::rgba(1.0, 1.0, 0.8, 0.8);
+
+const FLASH_OVERLAY_ALPHA: f32 = 0.8;
+// Alfa perdido por segundo, el destello dura unas decimas
+const FLASH_OVERLAY_FADE: f32 = 3.0;
+
+// Carga por segundo con la linterna apagada, la bateria entera tarda 20 segundos
+const BATTERY_RECHARGE: f32 = 0.05;
+
+// Un enemigo buscando se rinde al llegar a menos de esto del punto de alerta
+const SEARCH_REACHED_DISTANCE: f32 = 8.0;
+
+// Delante del overlay de oscuridad (z 998.9 en el mundo), las zonas seguras siempre se ven
+const SAFE_ZONE_Z: f32 = 999.0;
+const SAFE_ZONE_COLOR: Color = Color::rgba(1.0, 0.9, 0.6, 0.2);
+
+// Lo bastante grande para tapar la vista con cualquier resolucion y zoom
+const DARKNESS_SIZE: f32 = 10000.0;
+// Fraccion del camino hacia el tinte nuevo que se recorre por segundo
+const AMBIENT_FADE_SPEED: f32 = 2.0;
+
+// The 2d camera only renders z greater than -0.1, the tilemap layers start at 0
+const PARALLAX_Z: f32 = -0.01;
+
+/// The LDtk project every level comes from, despawn it with `despawn_recursive` to tear the run down
+pub fn spawn_world(commands: &mut Commands, asset_server: &AssetServer) {
+	commands.spawn_bundle(LdtkWorldBundle {
+		ldtk_handle: asset_server.load("tilemap/main.ldtk"),
+		..Default::default()
+	});
+}
 
 pub fn setup(
 	mut commands: Commands,
 	asset_server: Res<AssetServer>,
+	parallax_layers: Res<ParallaxLayers>,
 ) {
 	commands.spawn_bundle(OrthographicCameraBundle {
 		orthographic_projection: OrthographicProjection {
@@ -16,27 +65,277 @@ pub fn setup(
 			..Default::default()
 		},
 		..OrthographicCameraBundle::new_2d()
-	}).insert(MainCamera::default());
-	commands.spawn_bundle(UiCameraBundle::default());
-	commands.spawn_bundle(LdtkWorldBundle {
-		ldtk_handle: asset_server.load("tilemap/main.ldtk"),
-		..Default::default()
+	}).insert(MainCamera::default()).with_children(|parent| {
+		parent.spawn_bundle(SpriteBundle {
+			sprite: Sprite {
+				color: Color::NONE,
+				custom_size: Some(Vec2::splat(DARKNESS_SIZE)),
+				..Default::default()
+			},
+			// Justo delante de todo lo que ve la camara
+			transform: Transform::from_xyz(0., 0., -1.),
+			..Default::default()
+		}).insert(Darkness);
+		parent.spawn_bundle(SpriteBundle {
+			sprite: Sprite {
+				color: Color::rgba(1., 1., 1., 0.),
+				custom_size: Some(Vec2::splat(DARKNESS_SIZE)),
+				..Default::default()
+			},
+			// Por delante de la oscuridad
+			transform: Transform::from_xyz(0., 0., -0.5),
+			..Default::default()
+		}).insert(FlashOverlay);
 	});
+	commands.spawn_bundle(UiCameraBundle::default());
+	spawn_world(&mut commands, &asset_server);
+	for (i, layer) in parallax_layers.0.iter().enumerate() {
+		commands.spawn_bundle(SpriteBundle {
+			texture: asset_server.load(layer.texture.as_str()),
+			transform: Transform::from_xyz(0., 0., PARALLAX_Z - i as f32 * 0.001),
+			..Default::default()
+		}).insert(Parallax { factor: layer.factor });
+	}
+}
+
+/// Resets the score and counts the collectibles placed in the level that just loaded
+pub fn count_collectibles(
+	mut level_events: EventReader<LevelEvent>,
+	mut score: ResMut<Score>,
+	collectible_query: Query<(), With<Collectible>>,
+) {
+	for event in level_events.iter() {
+		if let LevelEvent::Transformed(uid) = event {
+			*score = Score {
+				level_uid: *uid,
+				collected: 0,
+				total: collectible_query.iter().count(),
+			};
+		}
+	}
+}
+
+/// Takes out the collectibles already picked up on an earlier visit, they still count as collected
+pub fn skip_collected(
+	mut commands: Commands,
+	mut level_events: EventReader<LevelEvent>,
+	collected: Res<CollectedEntities>,
+	mut score: ResMut<Score>,
+	collectible_query: Query<(Entity, &CollectibleId), With<Collectible>>,
+) {
+	for event in level_events.iter() {
+		if let LevelEvent::Transformed(uid) = event {
+			for (entity, id) in collectible_query.iter() {
+				if collected.0.contains(&(*uid, *id)) {
+					commands.entity(entity).despawn();
+					score.collected += 1;
+				}
+			}
+		}
+	}
+}
+
+pub fn level_field<'a>(level: &'a ldtk::Level, identifier: &str) -> Option<&'a FieldValue> {
+	level.field_instances.iter()
+		.find(|field| field.identifier == identifier)
+		.map(|field| &field.value)
+}
+
+pub fn read_ambient_tint(
+	mut level_events: EventReader<LevelEvent>,
+	ldtk_assets: Res<Assets<LdtkAsset>>,
+	ldtk_query: Query<&Handle<LdtkAsset>>,
+	mut ambient_tint: ResMut<AmbientTint>,
+) {
+	for event in level_events.iter() {
+		if let LevelEvent::Transformed(uid) = event {
+			let ldtk_asset = match ldtk_query.get_single().ok().and_then(|handle| ldtk_assets.get(handle)) {
+				Some(ldtk_asset) => ldtk_asset,
+				None => continue,
+			};
+			if let Some(level) = ldtk_asset.project.levels.iter().find(|level| level.uid == *uid) {
+				let color = |identifier| match level_field(level, identifier) {
+					Some(FieldValue::Color(color)) => Some(*color),
+					_ => None,
+				};
+				ambient_tint.clear_color = Some(color("clear_color").unwrap_or(ldtk_asset.project.bg_color));
+
+				let mut darkness = color("darkness_color").unwrap_or(Color::BLACK);
+				darkness.set_a(match level_field(level, "darkness_alpha") {
+					Some(FieldValue::Float(Some(alpha))) => *alpha,
+					_ => 0.0,
+				});
+				ambient_tint.darkness = darkness;
+			}
+		}
+	}
+}
+
+// Mas o menos el z que le da bevy_ecs_ldtk a la capa de entidades
+const FALLBACK_PLAYER_Z: f32 = 2.0;
+
+/// Records the player's start for the level that just loaded, spawning one at the center if the
+/// level has no Player entity
+#[allow(clippy::too_many_arguments)]
+pub fn record_level_start(
+	mut commands: Commands,
+	mut level_events: EventReader<LevelEvent>,
+	asset_server: Res<AssetServer>,
+	levels: Res<Assets<LdtkLevel>>,
+	mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+	mut level_start: ResMut<LevelStart>,
+	player_query: Query<&Transform, With<Player>>,
+	level_query: Query<(Entity, &Handle<LdtkLevel>)>,
+) {
+	for event in level_events.iter() {
+		let uid = match event {
+			LevelEvent::Transformed(uid) => *uid,
+			_ => continue,
+		};
+		if let Ok(transform) = player_query.get_single() {
+			*level_start = LevelStart { translation: transform.translation };
+			continue;
+		}
+
+		let level = level_query.iter().find_map(|(entity, handle)| levels.get(handle).filter(|level| level.level.uid == uid).map(|level| (entity, level)));
+		let (level_entity, level) = match level {
+			Some(level) => level,
+			None => continue,
+		};
+		error!("Level \"{}\" has no Player entity, spawning the player at its center", level.level.identifier);
+		let center = Vec2::new(level.level.px_wid as f32, level.level.px_hei as f32) / 2.0;
+		*level_start = LevelStart { translation: center.extend(FALLBACK_PLAYER_Z) };
+
+		let layer = match int_grid_layer(&level.level).or_else(|| level.level.layer_instances.as_ref().and_then(|layers| layers.first())) {
+			Some(layer) => layer,
+			None => continue,
+		};
+		let instance = EntityInstance { identifier: "Player".to_string(), ..Default::default() };
+		commands.spawn_bundle(PlayerBundle::bundle_entity(&instance, layer, None, None, &asset_server, &mut texture_atlases))
+			.insert(Transform::from_translation(level_start.translation))
+			.insert(GlobalTransform::default())
+			.insert(Parent(level_entity));
+	}
+}
+
+pub fn read_level_difficulty(
+	mut level_events: EventReader<LevelEvent>,
+	ldtk_assets: Res<Assets<LdtkAsset>>,
+	ldtk_query: Query<&Handle<LdtkAsset>>,
+	mut difficulty: ResMut<LevelDifficulty>,
+) {
+	for event in level_events.iter() {
+		match event {
+			// Nada del nivel anterior sobrevive mientras carga el siguiente
+			LevelEvent::SpawnTriggered(_) => *difficulty = LevelDifficulty::default(),
+			LevelEvent::Transformed(uid) => {
+				let level = ldtk_query.get_single().ok()
+					.and_then(|handle| ldtk_assets.get(handle))
+					.and_then(|ldtk_asset| ldtk_asset.project.levels.iter().find(|level| level.uid == *uid));
+				if let Some(level) = level {
+					let mult = |identifier| match level_field(level, identifier) {
+						Some(FieldValue::Float(Some(mult))) => *mult,
+						_ => 1.0,
+					};
+					*difficulty = LevelDifficulty {
+						enemy_speed_mult: mult("enemy_speed_mult"),
+						enemy_sight_mult: mult("enemy_sight_mult"),
+						enemy_damage_mult: mult("enemy_damage_mult"),
+					};
+				}
+			}
+			_ => (),
+		}
+	}
+}
+
+fn lerp_color(from: Color, to: Color, t: f32) -> Color {
+	let from = Vec4::from(from.as_rgba_f32());
+	let to = Vec4::from(to.as_rgba_f32());
+	from.lerp(to, t).into()
+}
+
+pub fn apply_ambient_tint(
+	time: Res<Time>,
+	ambient_tint: Res<AmbientTint>,
+	lighting_config: Res<LightingConfig>,
+	mut clear_color: ResMut<ClearColor>,
+	mut darkness_query: Query<&mut Sprite, With<Darkness>>,
+) {
+	let t = (AMBIENT_FADE_SPEED * time.delta_seconds()).min(1.0);
+	if let Some(target) = ambient_tint.clear_color {
+		clear_color.0 = lerp_color(clear_color.0, target, t);
+	}
+	for mut sprite in darkness_query.iter_mut() {
+		sprite.color = lerp_color(sprite.color, ambient_tint.darkness, t);
+		let alpha = sprite.color.a().min(1.0 - lighting_config.min_ambient);
+		sprite.color.set_a(alpha);
+	}
+}
+
+pub fn invalidate_nav_grid(
+	mut level_events: EventReader<LevelEvent>,
+	mut nav_grid: ResMut<NavGrid>,
+) {
+	for event in level_events.iter() {
+		if let LevelEvent::SpawnTriggered(_) = event {
+			*nav_grid = NavGrid::default();
+		}
+	}
+}
+
+/// Reads the floor materials off the IntGrid layer of the level that just loaded
+pub fn read_floor_materials(
+	mut level_events: EventReader<LevelEvent>,
+	levels: Res<Assets<LdtkLevel>>,
+	level_query: Query<&Handle<LdtkLevel>>,
+	mut nav_grid: ResMut<NavGrid>,
+) {
+	for event in level_events.iter() {
+		let uid = match event {
+			LevelEvent::Transformed(uid) => *uid,
+			_ => continue,
+		};
+		let layer = level_query.iter()
+			.find_map(|handle| levels.get(handle).filter(|level| level.level.uid == uid))
+			.and_then(|level| int_grid_layer(&level.level));
+		let layer = match layer {
+			Some(layer) => layer,
+			None => continue,
+		};
+		// El csv empieza por la fila de arriba, las GridCoords por la de abajo
+		nav_grid.materials = layer.int_grid_csv.iter().enumerate()
+			.filter_map(|(i, value)| FloorMaterial::from_int_grid(*value).filter(|material| *material != FloorMaterial::Generic).map(|material| (i as i32, material)))
+			.map(|(i, material)| (GridCoords { x: i % layer.c_wid, y: layer.c_hei - 1 - i / layer.c_wid }, material))
+			.collect();
+	}
 }
 
 pub fn pause_physics_during_load(
 	mut level_events: EventReader<LevelEvent>,
-	mut physics_time: ResMut<PhysicsTime>,
+	mut time_scale: ResMut<TimeScale>,
 ) {
 	for event in level_events.iter() {
 		match event {
-			LevelEvent::SpawnTriggered(_) => physics_time.set_scale(0.),
-			LevelEvent::Transformed(_) => physics_time.set_scale(1.),
+			LevelEvent::SpawnTriggered(_) => time_scale.loading = true,
+			LevelEvent::Transformed(_) => time_scale.loading = false,
 			_ => (),
 		}
 	}
 }
 
+/// Layer the walls and safe zones are painted on, the first layer may be an entity layer
+pub fn int_grid_layer(level: &ldtk::Level) -> Option<&LayerInstance> {
+	level.layer_instances.as_ref()?
+		.iter()
+		.find(|layer| layer.layer_instance_type == Type::IntGrid)
+}
+
+/// Where bevy_ecs_ldtk places the layer relative to its level, LDtk measures y downwards
+pub fn layer_offset(layer: &LayerInstance) -> Vec2 {
+	Vec2::new(layer.px_total_offset_x as f32, -layer.px_total_offset_y as f32)
+}
+
 /// Spawns heron collisions for the walls of a level
 ///
 /// You could just insert a ColliderBundle in to the WallBundle,
@@ -59,6 +358,7 @@ pub fn spawn_wall_collision(
 	parent_query: Query<&Parent, Without<Wall>>,
 	level_query: Query<(Entity, &Handle<LdtkLevel>)>,
 	levels: Res<Assets<LdtkLevel>>,
+	mut nav_grid: ResMut<NavGrid>,
 ) {
 	/// Represents a wide wall that is 1 tile tall
 	/// Used to spawn wall collisions
@@ -78,7 +378,7 @@ pub fn spawn_wall_collision(
 		if let Ok(&Parent(level_entity)) = parent_query.get(parent) {
 			level_to_wall_locations
 				.entry(level_entity)
-				.or_insert(HashSet::new())
+				.or_default()
 				.insert(grid_coords);
 		}
 	});
@@ -86,20 +386,29 @@ pub fn spawn_wall_collision(
 	if !wall_query.is_empty() {
 		level_query.for_each(|(level_entity, level_handle)| {
 			if let Some(level_walls) = level_to_wall_locations.get(&level_entity) {
-				let level = levels
-					.get(level_handle)
-					.expect("Level should be loaded by this point");
-
-				let LayerInstance {
-					c_wid: width,
-					c_hei: height,
-					grid_size,
-					..
-				} = level
-					.level
-					.layer_instances
-					.clone()
-					.expect("Level asset should have layers")[0];
+				let level = match levels.get(level_handle) {
+					Some(level) => level,
+					None => {
+						warn!("Level asset not loaded yet, skipping its wall collisions");
+						return;
+					}
+				};
+
+				let (width, height, grid_size, offset) = match int_grid_layer(&level.level) {
+					Some(layer) => (layer.c_wid, layer.c_hei, layer.grid_size, layer_offset(layer)),
+					None => {
+						warn!("Level \"{}\" has no IntGrid layer, skipping its wall collisions", level.level.identifier);
+						return;
+					}
+				};
+
+				// enemies path around the same walls the colliders are built from
+				// campo a campo para no pisar las zonas seguras de spawn_safe_zones
+				nav_grid.walls = level_walls.clone();
+				nav_grid.width = width;
+				nav_grid.height = height;
+				nav_grid.grid_size = grid_size;
+				nav_grid.offset = offset;
 
 				// combine wall tiles into flat "plates" in each individual row
 				let mut plate_stack: Vec<Vec<Plate>> = Vec::new();
@@ -138,7 +447,7 @@ pub fn spawn_wall_collision(
 				for (y, row) in plate_stack.iter().enumerate() {
 					let mut current_rects: HashMap<Plate, Rect<i32>> = HashMap::new();
 					for plate in row {
-						if let Some(previous_rect) = previous_rects.remove(&plate) {
+						if let Some(previous_rect) = previous_rects.remove(plate) {
 							current_rects.insert(
 								*plate,
 								Rect {
@@ -181,13 +490,14 @@ pub fn spawn_wall_collision(
 							border_radius: None,
 						})
 						.insert(RigidBody::Static)
+						.insert(layers_for("Wall"))
 						.insert(PhysicMaterial {
 							friction: 0.1,
 							..Default::default()
 						})
 						.insert(Transform::from_xyz(
-							(wall_rect.left + wall_rect.right + 1) as f32 * grid_size as f32 / 2.,
-							(wall_rect.bottom + wall_rect.top + 1) as f32 * grid_size as f32 / 2.,
+							(wall_rect.left + wall_rect.right + 1) as f32 * grid_size as f32 / 2. + offset.x,
+							(wall_rect.bottom + wall_rect.top + 1) as f32 * grid_size as f32 / 2. + offset.y,
 							0.,
 						))
 						.insert(GlobalTransform::default())
@@ -201,118 +511,2158 @@ pub fn spawn_wall_collision(
 	}
 }
 
+/// Registers safe zone cells in the `NavGrid` and lights each one with a sprite above the darkness
+pub fn spawn_safe_zones(
+	mut commands: Commands,
+	safe_zone_query: Query<(&GridCoords, &Parent), Added<SafeZone>>,
+	parent_query: Query<&Parent, Without<SafeZone>>,
+	level_query: Query<&Handle<LdtkLevel>>,
+	levels: Res<Assets<LdtkLevel>>,
+	mut nav_grid: ResMut<NavGrid>,
+) {
+	safe_zone_query.for_each(|(&grid_coords, &Parent(parent))| {
+		// Igual que las paredes, el abuelo de la celda es el nivel
+		let level_entity = match parent_query.get(parent) {
+			Ok(&Parent(level_entity)) => level_entity,
+			Err(_) => return,
+		};
+		let (grid_size, offset) = match level_query.get(level_entity).ok().and_then(|handle| levels.get(handle)).and_then(|level| int_grid_layer(&level.level)) {
+			Some(layer) => (layer.grid_size, layer_offset(layer)),
+			None => return,
+		};
+
+		nav_grid.safe_zones.insert(grid_coords);
+		let center = (Vec2::new(grid_coords.x as f32, grid_coords.y as f32) + 0.5) * grid_size as f32 + offset;
+		commands.spawn_bundle(SpriteBundle {
+			sprite: Sprite {
+				color: SAFE_ZONE_COLOR,
+				custom_size: Some(Vec2::splat(grid_size as f32)),
+				..Default::default()
+			},
+			transform: Transform::from_translation(center.extend(SAFE_ZONE_Z)),
+			..Default::default()
+		}).insert(Parent(level_entity));
+	});
+}
+
+pub fn update_ai_time(
+	time: Res<Time>,
+	game_config: Res<GameConfig>,
+	mut ai_time: ResMut<AiTime>,
+) {
+	ai_time.delta = game_config.fixed_timestep.map_or(time.delta(), Duration::from_secs_f32);
+}
+
+/// Returns true if nothing but the entities at either end lies between `from` and `to`
+///
+/// The ray only interacts with `Layer::Wall`, so the player's and enemy's own colliders,
+/// every other actor and the win sensor are ignored while the static wall colliders stop it.
+pub fn line_of_sight(from: Vec3, to: Vec3, physics_world: &PhysicsWorld) -> bool {
+	physics_world.ray_cast_with_filter(
+		from,
+		to - from,
+		true,
+		CollisionLayers::new(Layer::Wall, Layer::Wall),
+		|_| true,
+	).is_none()
+}
+
+/// Shoves a body at `translation` away from `center`, with `force` at the center fading to
+/// nothing at `radius`
+///
+/// heron has no impulses, so the push goes straight into the velocity as if every body weighed
+/// the same. Capped at `MAX_RADIAL_IMPULSE` so nothing gets flung across the level.
+pub fn apply_radial_impulse(center: Vec3, radius: f32, force: f32, translation: Vec3, velocity: &mut Velocity) {
+	let offset = (translation - center).truncate();
+	let distance = offset.length();
+	if distance >= radius { return }
+	let strength = (force * (1.0 - distance / radius)).min(MAX_RADIAL_IMPULSE);
+	// Justo en el centro no hay direccion, se empuja hacia arriba
+	let direction = if distance > 0.0 { offset / distance } else { Vec2::Y };
+	velocity.linear += (direction * strength).extend(0.0);
+}
+
+/// How far an enemy in `state` sees the player, before difficulty, see `EnemyConfig.lose_range`
+pub fn vision_range(state: EnemyState, enemy_config: &EnemyConfig) -> f32 {
+	match state {
+		EnemyState::Chase | EnemyState::Search => enemy_config.lose_range,
+		EnemyState::Idle | EnemyState::Return => enemy_config.detection_range,
+	}
+}
+
+// Un solo raycast por enemigo cada frame, el resto de sistemas leen el resultado de Vision
+pub fn enemy_vision(
+	physics_world: PhysicsWorld,
+	enemy_config: Res<EnemyConfig>,
+	difficulty: Res<LevelDifficulty>,
+	grace: Res<LevelGrace>,
+	player_query: Query<(&Transform, Option<&Hidden>), With<Player>>,
+	mut enemy_query: Query<(&mut Vision, &EnemyState, &Transform), (With<Enemy>, Without<Dormant>, Without<Stunned>, Without<DyingEnemy>)>,
+) {
+	if let Ok((Transform { translation: player_translation, .. }, hidden)) = player_query.get_single() {
+		for (mut vision, state, Transform { translation: enemy_translation, .. }) in enemy_query.iter_mut() {
+			let range = vision_range(*state, &enemy_config);
+			// Durante la cuenta atras nadie ve al jugador, asi que nadie lo persigue ni avisa
+			vision.can_see_player = !grace.active()
+				&& hidden.is_none()
+				&& enemy_translation.distance(*player_translation) < range * difficulty.enemy_sight_mult
+				&& line_of_sight(*enemy_translation, *player_translation, &physics_world);
+		}
+	}
+}
+
+/// Keeps each chasing or searching enemy's path to its goal fresh, at most once every `repath_interval`
+/// and `paths_per_frame` searches between all of them
+pub fn enemy_pathfinding(
+	time: Res<AiTime>,
+	nav_grid: Res<NavGrid>,
+	enemy_config: Res<EnemyConfig>,
+	player_query: Query<&Transform, With<Player>>,
+	mut enemy_query: Query<(&mut NavPath, &EnemyState, &EnemyKind, &Alert, &Leash, &Transform), With<Enemy>>,
+) {
+	let player_translation = match player_query.get_single() {
+		Ok(transform) => transform.translation.truncate(),
+		Err(_) => return,
+	};
+
+	let mut searches = enemy_config.paths_per_frame.unwrap_or(usize::MAX);
+	for (mut path, state, kind, alert, leash, transform) in enemy_query.iter_mut() {
+		let translation = transform.translation.truncate();
+
+		// Avanza al siguiente punto cuando llega al centro de la celda actual
+		if let Some(waypoint) = path.waypoints.last() {
+			if waypoint.distance(translation) < nav_grid.grid_size as f32 / 4.0 {
+				path.waypoints.pop();
+			}
+		}
+
+		let goal = match state {
+			EnemyState::Chase => nav_grid.to_grid(player_translation),
+			EnemyState::Search => nav_grid.to_grid(alert.target),
+			EnemyState::Return => nav_grid.to_grid(leash.home),
+			EnemyState::Idle => {
+				path.waypoints.clear();
+				continue;
+			}
+		};
+		// El timer se queda terminado, sin busquedas libres lo intenta en la siguiente
+		if !path.repath.tick(time.delta()).finished() || searches == 0 { continue }
+		searches -= 1;
+
+		// Sin camino el enemigo va directo hacia el jugador y no lo vuelve a pedir hasta el proximo intervalo
+		path.waypoints = nav_grid.find_path(nav_grid.to_grid(translation), goal, kind.respects_safe_zones())
+			.map(|cells| cells.into_iter().rev().map(|cell| nav_grid.to_translation(cell)).collect())
+			.unwrap_or_default();
+		path.repath = Timer::from_seconds(enemy_config.repath_interval, false);
+	}
+}
+
+/// Rotates `heading` towards `desired` by at most `max_angle` radians, a stopped heading snaps straight to it
+fn turn_towards(heading: Vec2, desired: Vec2, max_angle: f32) -> Vec2 {
+	let heading = heading.normalize_or_zero();
+	if heading == Vec2::ZERO || desired == Vec2::ZERO { return desired }
+	let angle = heading.angle_between(desired);
+	Mat2::from_angle(angle.clamp(-max_angle, max_angle)) * heading
+}
+
+/// Push away from everyone within `radius`, stronger the closer they are. `buckets` are the
+/// enemies by cells of `radius`, so only the nine cells around `position` are looked at
+fn avoidance(entity: Entity, position: Vec2, radius: f32, buckets: &HashMap<IVec2, Vec<(Entity, Vec2)>>, obstacle: Option<Vec2>) -> Vec2 {
+	let cell = (position / radius).floor().as_ivec2();
+	let neighbours = (-1..=1).flat_map(|x| (-1..=1).map(move |y| cell + IVec2::new(x, y)))
+		.filter_map(|cell| buckets.get(&cell))
+		.flatten()
+		.filter(|(other, _)| *other != entity)
+		.map(|(_, other)| *other);
+	neighbours.chain(obstacle).fold(Vec2::ZERO, |push, other| {
+		let offset = position - other;
+		let distance = offset.length();
+		if distance >= radius || distance == 0.0 { return push }
+		push + offset / distance * (1.0 - distance / radius)
+	})
+}
+
 pub fn enemy_movement(
+	time: Res<AiTime>,
+	enemy_config: Res<EnemyConfig>,
+	difficulty: Res<LevelDifficulty>,
+	nav_grid: Res<NavGrid>,
 	player_query: Query<&Transform, With<Player>>,
-	mut enemy_query: Query<(&mut Velocity, &Transform), With<Enemy>>
+	mut enemy_query: Query<(Entity, &mut Velocity, &mut EnemyState, &mut Health, &mut Vision, &EnemyKind, &Transform, &NavPath, &mut Alert, &Leash), (With<Enemy>, Without<Dormant>, Without<Stunned>, Without<DyingEnemy>)>
 ) {
+	let radius = enemy_config.avoidance_radius;
+	let avoiding = enemy_config.avoidance_strength > 0.0 && radius > 0.0;
+	let mut buckets: HashMap<IVec2, Vec<(Entity, Vec2)>> = HashMap::new();
+	if avoiding {
+		for (entity, .., transform, _, _, _) in enemy_query.iter() {
+			let position = transform.translation.truncate();
+			buckets.entry((position / radius).floor().as_ivec2()).or_default().push((entity, position));
+		}
+	}
+
 	if let Ok(Transform { translation: player_translation, .. }) = player_query.get_single() {
-		for (mut enemy_velocity, Transform { translation: enemy_translation, .. }) in enemy_query.iter_mut() {
-			let delta = *player_translation - *enemy_translation;
-			if delta.length() < 200.0 {
-				enemy_velocity.linear = delta.normalize_or_zero() * 90.0;
+		for (entity, mut enemy_velocity, mut state, mut health, mut vision, kind, Transform { translation: enemy_translation, .. }, path, mut alert, leash) in enemy_query.iter_mut() {
+			if matches!(*state, EnemyState::Chase | EnemyState::Search) && kind.can_be_leashed() && leash.strayed(enemy_translation.truncate()) {
+				*state = EnemyState::Return;
+			}
+
+			let goal = if *state == EnemyState::Return {
+				if leash.home.distance(enemy_translation.truncate()) > SEARCH_REACHED_DISTANCE {
+					Some(leash.home.extend(0.0))
+				} else {
+					// De vuelta en casa como nuevo
+					*state = EnemyState::Idle;
+					health.current = health.max;
+					enemy_velocity.linear = Vec3::ZERO;
+					None
+				}
+			} else if vision.can_see_player || (*state == EnemyState::Chase && vision.chase_time < enemy_config.min_chase_time) {
+				if *state != EnemyState::Chase { vision.chase_time = 0.0 }
+				vision.chase_time += time.delta_seconds();
+				*state = EnemyState::Chase;
+				Some(*player_translation)
+			} else if *state == EnemyState::Chase {
+				// Busca donde lo perdio, y con lose_range no lo vuelve a ver enseguida
+				*state = EnemyState::Search;
+				alert.target = player_translation.truncate();
+				Some(*player_translation)
+			} else if *state == EnemyState::Search && alert.target.distance(enemy_translation.truncate()) > SEARCH_REACHED_DISTANCE {
+				Some(alert.target.extend(0.0))
+			} else {
+				*state = EnemyState::Idle;
+				None
+			};
+
+			if let Some(goal) = goal {
+				let target = path.waypoints.last().map_or(goal, |waypoint| waypoint.extend(0.0));
+				let mut desired = (target - *enemy_translation).truncate().normalize_or_zero();
+				if avoiding && desired != Vec2::ZERO {
+					// Solo esquiva al jugador si no va a por el
+					let obstacle = (*state != EnemyState::Chase).then(|| player_translation.truncate());
+					let mut steer = avoidance(entity, enemy_translation.truncate(), radius, &buckets, obstacle) * enemy_config.avoidance_strength;
+					// Nunca frena ni retrocede, solo aparta de lado, asi en una puerta siempre acaba pasando
+					let back = steer.dot(desired);
+					if back < 0.0 { steer -= desired * back }
+					desired = (desired + steer).normalize_or_zero();
+				}
+				let direction = turn_towards(enemy_velocity.linear.truncate(), desired, enemy_config.turn_rate * time.delta_seconds()).extend(0.0);
+				enemy_velocity.linear = direction * enemy_config.speed * difficulty.enemy_speed_mult;
+
+				// Se queda en el borde en vez de meterse detras del jugador
+				if kind.respects_safe_zones() {
+					let here = nav_grid.to_grid(enemy_translation.truncate());
+					let ahead = nav_grid.to_grid((*enemy_translation + direction * nav_grid.grid_size as f32 / 2.0).truncate());
+					if ahead != here && nav_grid.safe_zones.contains(&ahead) && !nav_grid.safe_zones.contains(&here) {
+						enemy_velocity.linear = Vec3::ZERO;
+					}
+				}
 			}
 		}
 	}
 }
 
-pub fn movement(
-	input: Res<Input<KeyCode>>,
-	mut query: Query<&mut Velocity, With<Player>>,
+// Puntos al azar alrededor de casa que se prueban hasta dar con uno a la vista
+const WANDER_TRIES: usize = 8;
+
+fn wander_target(home: Vec2, from: Vec3, radius: f32, nav_grid: &NavGrid, physics_world: &PhysicsWorld, rng: &mut GameRng) -> Option<Vec2> {
+	(0..WANDER_TRIES).map(|_| home + Vec2::new(rng.0.gen_range(-1.0..1.0), rng.0.gen_range(-1.0..1.0)) * radius)
+		.find(|target| nav_grid.is_walkable(nav_grid.to_grid(*target)) && line_of_sight(from, target.extend(from.z), physics_world))
+}
+
+/// Idle enemies walk their `PatrolRoute`, or wander around home without one, and pick it back
+/// up where they left it once a chase or search is over
+#[allow(clippy::too_many_arguments)]
+pub fn patrol_movement(
+	time: Res<AiTime>,
+	physics_world: PhysicsWorld,
+	enemy_config: Res<EnemyConfig>,
+	difficulty: Res<LevelDifficulty>,
+	nav_grid: Res<NavGrid>,
+	mut rng: ResMut<GameRng>,
+	mut query: Query<(&mut Velocity, &mut PatrolRoute, &EnemyState, &Leash, &Transform), (With<Enemy>, Without<Dormant>, Without<Stunned>, Without<DyingEnemy>)>,
 ) {
-	if let Ok(mut velocity) = query.get_single_mut() {
-		let mut delta = Vec2::ZERO;
-		if input.pressed(KeyCode::W) { delta.y += 1.0 }
-		if input.pressed(KeyCode::A) { delta.x -= 1.0 }
-		if input.pressed(KeyCode::S) { delta.y -= 1.0 }
-		if input.pressed(KeyCode::D) { delta.x += 1.0 }
-		delta = delta.normalize_or_zero();
+	let speed = enemy_config.speed * enemy_config.patrol_speed * difficulty.enemy_speed_mult;
+	for (mut velocity, mut route, state, leash, transform) in query.iter_mut() {
+		if *state != EnemyState::Idle { continue }
+		let translation = transform.translation.truncate();
 
-		velocity.linear = delta.extend(0.0) * 100.;
+		let target = if route.points.is_empty() {
+			if enemy_config.wander_radius <= 0.0 { continue }
+			if !route.rest.tick(time.delta()).finished() {
+				velocity.linear = Vec3::ZERO;
+				continue;
+			}
+			if route.wander.is_none() {
+				route.wander = wander_target(leash.home, transform.translation, enemy_config.wander_radius, &nav_grid, &physics_world, &mut rng);
+			}
+			match route.wander {
+				Some(target) if target.distance(translation) > SEARCH_REACHED_DISTANCE => target,
+				// Llego o no encontro adonde ir, descansa y lo vuelve a intentar
+				_ => {
+					route.wander = None;
+					route.rest = Timer::from_seconds(enemy_config.wander_rest, false);
+					velocity.linear = Vec3::ZERO;
+					continue;
+				}
+			}
+		} else {
+			if route.points[route.next].distance(translation) <= SEARCH_REACHED_DISTANCE {
+				route.advance();
+			}
+			route.points[route.next]
+		};
+		velocity.linear = (target - translation).normalize_or_zero().extend(0.0) * speed;
 	}
 }
 
-pub fn animation(
-	time: Res<Time>,
-	texture_atlases: Res<Assets<TextureAtlas>>,
-	mut query: Query<(&Velocity, &mut Timer, &mut TextureAtlasSprite, &Handle<TextureAtlas>)>,
+/// Idle enemies near a chasing one start searching where the player was seen
+///
+/// Only `Chase` broadcasts, and both ends go on cooldown, so two enemies can't keep re-alerting each other.
+pub fn propagate_alert(
+	time: Res<AiTime>,
+	enemy_config: Res<EnemyConfig>,
+	player_query: Query<&Transform, With<Player>>,
+	mut enemy_query: Query<(Entity, &mut EnemyState, &mut Alert, &EnemyKind, &Transform), (With<Enemy>, Without<Dormant>, Without<Stunned>, Without<DyingEnemy>)>,
 ) {
-	for (velocity, mut timer, mut sprite, texture_atlas_handle) in query.iter_mut() {
-		timer.tick(time.delta());
-		if timer.finished() && velocity.linear != Vec3::ZERO {
-			let texture_atlas = texture_atlases.get(texture_atlas_handle).unwrap();
-			sprite.index = (sprite.index + 1) % texture_atlas.textures.len();
-		} else if velocity.linear == Vec3::ZERO {
-			sprite.index = 0;
+	let player_translation = match player_query.get_single() {
+		Ok(transform) => transform.translation.truncate(),
+		Err(_) => return,
+	};
+
+	let mut callers = Vec::new();
+	for (entity, state, mut alert, kind, transform) in enemy_query.iter_mut() {
+		alert.cooldown.tick(time.delta());
+		if *state == EnemyState::Chase && kind.shares_alerts() && alert.cooldown.finished() {
+			callers.push((entity, transform.translation.truncate()));
 		}
 	}
-}
 
-pub fn win(
-	mut commands: Commands,
-	asset_server: Res<AssetServer>,
-	mut physic_event: EventReader<CollisionEvent>,
-) {
-	physic_event.iter().filter(|e| e.is_started()).filter_map(|event| {
-		let (e1, e2) = event.rigid_body_entities();
-		let (l1, l2) = event.collision_layers();
+	// Pocos enemigos por nivel, comparar todos con todos sale barato
+	for (caller, caller_translation) in callers {
+		let mut alerted = false;
+		for (entity, mut state, mut alert, kind, transform) in enemy_query.iter_mut() {
+			if entity == caller || *state != EnemyState::Idle || !kind.shares_alerts() || !alert.cooldown.finished() { continue }
+			if transform.translation.truncate().distance(caller_translation) > enemy_config.alert_radius { continue }
 
-		if l1.contains_group(Layer::Player) && !l1.contains_group(Layer::Win) && l2.contains_group(Layer::Win) && !l2.contains_group(Layer::Player) {
-			Some(e2)
-		} else if l2.contains_group(Layer::Win) && !l2.contains_group(Layer::Player) && l1.contains_group(Layer::Player) && !l1.contains_group(Layer::Win) {
-			Some(e1)
-		} else {
-			None
+			*state = EnemyState::Search;
+			alert.target = player_translation;
+			alert.cooldown = Timer::from_seconds(enemy_config.alert_cooldown, false);
+			alerted = true;
 		}
-	}).for_each(|entity_win| {
-		commands.entity(entity_win).despawn();
-		commands.spawn_bundle(TextBundle {
-				style: Style {
-					margin: Rect::all(Val::Px(5.0)),
-					..Default::default()
-				},
-				text: Text::with_section(
-					"You Win!!!",
-					TextStyle {
-						font: asset_server.load("fonts/FiraSans-Bold.ttf"),
-						font_size: 100.0,
-						color: Color::WHITE,
-					},
-				Default::default(),
-			),
+		if alerted {
+			if let Ok((_, _, mut alert, _, _)) = enemy_query.get_mut(caller) {
+				alert.cooldown = Timer::from_seconds(enemy_config.alert_cooldown, false);
+			}
+		}
+	}
+}
+
+// Debajo del enemigo que murio y de todo lo que se mueve encima del nivel
+const DECAL_Z_OFFSET: f32 = -0.5;
+const DECAL_SIZE: f32 = 16.0;
+
+/// Leaves a fading scorch mark where an enemy died
+pub fn spawn_decal(commands: &mut Commands, decal_config: &DecalConfig, translation: Vec3) {
+	commands.spawn_bundle(SpriteBundle {
+		sprite: Sprite {
+			color: decal_config.color,
+			custom_size: Some(Vec2::splat(DECAL_SIZE)),
 			..Default::default()
-		});
+		},
+		transform: Transform::from_translation(translation + Vec3::Z * DECAL_Z_OFFSET),
+		..Default::default()
 	})
+	.insert(Decal)
+	.insert(Fade { alpha: decal_config.color.a() })
+	.insert(Lifetime { timer: Timer::from_seconds(decal_config.lifetime, false) });
 }
 
-// Memoriza la ultima posicion del mouse
-pub fn camera_cursor_position(
-	wnds: Res<Windows>,
-	mut cursor_moved: EventReader<CursorMoved>,
-	mut mc_query: Query<(&mut MainCamera, &OrthographicProjection)>,
+/// Removes the oldest scorch marks past `DecalConfig.max`
+pub fn cap_decals(
+	mut commands: Commands,
+	decal_config: Res<DecalConfig>,
+	query: Query<(Entity, &Lifetime), With<Decal>>,
 ) {
-	let wnd = wnds.get_primary().unwrap();
-	let (mut mc, op) = mc_query.single_mut();
-	if let Some(cm) = cursor_moved.iter().last() {
-		let size = Vec2::new(wnd.width() as f32, wnd.height() as f32);
-		let p = cm.position - size / 2.0;
-		mc.last_cursor_position = p * op.scale;
+	let mut decals: Vec<(Entity, f32)> = query.iter().map(|(entity, lifetime)| (entity, lifetime.timer.elapsed_secs())).collect();
+	if decals.len() <= decal_config.max { return }
+	decals.sort_by(|a, b| b.1.total_cmp(&a.1));
+	for (entity, _) in decals.iter().take(decals.len() - decal_config.max) {
+		commands.entity(*entity).despawn();
 	}
 }
 
-// Camara fachera
-pub fn camera_controller(
-	mut player_query: Query<(&Transform, &mut TextureAtlasSprite), With<Player>>,
-	mut camera_query: Query<(&mut Transform, &MainCamera), Without<Player>>
+// Empujon al morir por la luz y lo que tarda en desaparecer
+const DEATH_KNOCKBACK: f32 = 200.0;
+const DEATH_SECONDS: f32 = 0.5;
+// Fraccion de la velocidad perdida por segundo mientras sale despedido
+const DEATH_FRICTION: f32 = 4.0;
+
+// Lejos de cualquier nivel, ni se ven ni se oyen
+const POOLED_ENEMY_OFFSET: f32 = -100000.0;
+
+/// Takes an enemy out of play and keeps it in the pool instead of despawning it
+///
+/// It leaves its level, so it outlives it and the spawns of the next level can reuse it.
+pub fn recycle_enemy(commands: &mut Commands, pool: &mut EnemyPool, entity: Entity) {
+	commands.entity(entity)
+		.remove::<Parent>()
+		// Fuera de la fisica, aparcados en el mismo punto se solaparian todos en cada paso
+		.remove::<RigidBody>()
+		.insert(Pooled)
+		.insert(Dormant { layers: layers_for("Enemy") })
+		.insert(CollisionLayers::none())
+		.insert(Velocity::default())
+		.insert(Transform::from_xyz(POOLED_ENEMY_OFFSET, POOLED_ENEMY_OFFSET, 0.0))
+		.insert(Visibility { is_visible: false })
+		.remove::<HitFlash>()
+		.remove::<Stunned>()
+		.remove::<DyingEnemy>()
+		.remove::<Invincible>()
+		.remove::<SpawnImmunity>()
+		.remove::<LightVulnerable>();
+	pool.inactive.push(entity);
+}
+
+/// Puts a pooled enemy back into play at `translation`, with every bit of AI state reset
+///
+/// It comes back `Dormant` and `activate_enemies` wakes it, `insert_light_vulnerable`
+/// gives it a fresh `LightVulnerable` if its kind needs one. None when the pool is empty.
+pub fn acquire_enemy(commands: &mut Commands, pool: &mut EnemyPool, alive: &Query<(), With<Enemy>>, translation: Vec3) -> Option<Entity> {
+	// Se salta los que se fueron con un nivel antes de llegar a salir de el
+	let entity = std::iter::from_fn(|| pool.inactive.pop()).find(|&entity| alive.get(entity).is_ok())?;
+	commands.entity(entity)
+		.remove::<Pooled>()
+		// El mismo que le da ColliderEntityBundle a "Enemy"
+		.insert(RigidBody::Dynamic)
+		.insert(Transform::from_translation(translation))
+		.insert(Visibility { is_visible: true })
+		.insert(EnemyState::default())
+		.insert(Alert::default())
+		.insert(Attack::default())
+		.insert(Vision::default())
+		.insert(NavPath::default());
+	Some(entity)
+}
+
+/// Freshly spawned enemies start without AI or contacts until `activate_enemies` wakes them
+pub fn make_enemies_dormant(
+	mut commands: Commands,
+	mut query: Query<(Entity, &mut CollisionLayers), Added<Enemy>>,
+) {
+	for (entity, mut layers) in query.iter_mut() {
+		commands.entity(entity).insert(Dormant { layers: *layers });
+		*layers = CollisionLayers::none();
+	}
+}
+
+/// Wakes a few dormant enemies per frame once the level has loaded, spreading the physics spike
+pub fn activate_enemies(
+	mut commands: Commands,
+	time_scale: Res<TimeScale>,
+	enemy_config: Res<EnemyConfig>,
+	mut query: Query<(Entity, &Dormant, &mut CollisionLayers), Without<Pooled>>,
+) {
+	if time_scale.loading { return }
+	let count = enemy_config.activations_per_frame.unwrap_or(usize::MAX);
+	for (entity, dormant, mut layers) in query.iter_mut().take(count) {
+		*layers = dormant.layers;
+		commands.entity(entity).remove::<Dormant>();
+	}
+}
+
+/// Leashes are measured from wherever the enemy was placed, also when it comes back out of the pool
+pub fn set_leash_home(
+	recycled: RemovedComponents<Pooled>,
+	mut query: Query<(Entity, &mut Leash, &Transform), Without<Pooled>>,
 ) {
-	if let Ok((Transform { translation: player_translation, .. }, mut sprite)) = player_query.get_single_mut() {
-		let (mut camera_trans, mc) = camera_query.single_mut();
-		camera_trans.translation = *player_translation + mc.last_cursor_position.extend(0.0) / 2.0;
+	let recycled: HashSet<Entity> = recycled.iter().collect();
+	for (entity, mut leash, transform) in query.iter_mut() {
+		if leash.is_added() || recycled.contains(&entity) {
+			leash.home = transform.translation.truncate();
+		}
+	}
+}
+
+pub fn insert_light_vulnerable(
+	mut commands: Commands,
+	enemy_config: Res<EnemyConfig>,
+	query: Query<(Entity, &EnemyKind), Added<EnemyKind>>,
+	recycled: RemovedComponents<Pooled>,
+	kind_query: Query<&EnemyKind>,
+) {
+	let acquired = recycled.iter().filter_map(|entity| kind_query.get(entity).ok().map(|kind| (entity, kind)));
+	for (entity, kind) in query.iter().chain(acquired) {
+		if kind.light_vulnerable() {
+			commands.entity(entity).insert(LightVulnerable { exposure: 0.0, threshold: enemy_config.light_threshold });
+		}
+	}
+}
 
-		if mc.last_cursor_position.x >= 0.0 {
-			sprite.flip_x = false;
+/// Hurts enemies that stay inside the lit flashlight cone, or the radius of a burning
+/// `LightSource`, for too long, once every `light_threshold` seconds of exposure
+pub fn light_exposure(
+	time: Res<Time>,
+	physics_world: PhysicsWorld,
+	enemy_config: Res<EnemyConfig>,
+	mut damage_queue: ResMut<DamageQueue>,
+	player_query: Query<(&Transform, &Flashlight), With<Player>>,
+	light_query: Query<(&Transform, &LightSource, &Linked)>,
+	mut enemy_query: Query<(Entity, &mut LightVulnerable, &Transform), (With<Enemy>, Without<HitFlash>, Without<Dormant>, Without<DyingEnemy>)>,
+) {
+	let (Transform { translation: player_translation, .. }, flashlight) = match player_query.get_single() {
+		Ok(player) => player,
+		Err(_) => return,
+	};
+	for (entity, mut light_vulnerable, Transform { translation: enemy_translation, .. }) in enemy_query.iter_mut() {
+		let lit = flashlight.on
+			&& flashlight.contains(player_translation.truncate(), enemy_translation.truncate())
+			&& line_of_sight(*player_translation, *enemy_translation, &physics_world);
+		// Las luces fijas queman al ritmo del modo ancho, y sale despedido lejos de la que lo quema
+		let burning_light = light_query.iter().find(|(Transform { translation, .. }, light, linked)| {
+			light.burns
+				&& linked.powered
+				&& translation.truncate().distance(enemy_translation.truncate()) <= light.radius
+				&& line_of_sight(*translation, *enemy_translation, &physics_world)
+		}).map(|(transform, ..)| transform.translation);
+		if lit {
+			light_vulnerable.exposure += flashlight.mode.exposure_rate() * time.delta_seconds();
+		} else if burning_light.is_some() {
+			light_vulnerable.exposure += FlashlightMode::Wide.exposure_rate() * time.delta_seconds();
 		} else {
-			sprite.flip_x = true;
+			light_vulnerable.exposure = (light_vulnerable.exposure - enemy_config.light_decay * time.delta_seconds()).max(0.0);
+		}
+		if light_vulnerable.exposure >= light_vulnerable.threshold {
+			light_vulnerable.exposure = 0.0;
+			let source = if lit { *player_translation } else { burning_light.unwrap_or(*player_translation) };
+			damage_queue.0.push(Damage { target: entity, amount: enemy_config.light_damage, source: Some(source) });
+		}
+	}
+}
+
+/// Knocks a dead enemy back along `away` and leaves it to `dying_enemies`
+pub fn kill_enemy(commands: &mut Commands, entity: Entity, velocity: &mut Velocity, away: Vec2, flash_seconds: f32) {
+	velocity.linear = away.extend(0.0) * DEATH_KNOCKBACK;
+	commands.entity(entity)
+		.insert(HitFlash { timer: Timer::from_seconds(flash_seconds, false) })
+		.insert(DyingEnemy { timer: Timer::from_seconds(DEATH_SECONDS, false) })
+		// Solo choca con las paredes mientras sale despedido
+		.insert(CollisionLayers::none().with_group(Layer::Enemy).with_mask(Layer::Wall));
+}
+
+/// Plays the death row and fades dying enemies out, then sends them to the pool
+pub fn dying_enemies(
+	mut commands: Commands,
+	time: Res<Time>,
+	decal_config: Res<DecalConfig>,
+	mut pool: ResMut<EnemyPool>,
+	mut query: Query<(Entity, &mut DyingEnemy, &mut Velocity, &mut TextureAtlasSprite, &AnimationDef, &Transform), Without<HitFlash>>,
+) {
+	for (entity, mut dying, mut velocity, mut sprite, animation_def, transform) in query.iter_mut() {
+		if dying.timer.tick(time.delta()).finished() {
+			spawn_decal(&mut commands, &decal_config, transform.translation);
+			commands.entity(entity).remove::<DyingEnemy>();
+			recycle_enemy(&mut commands, &mut pool, entity);
+			continue;
+		}
+		if let Some((first, last)) = animation_def.death {
+			sprite.index = first + ((dying.timer.percent() * (last - first + 1) as f32) as usize).min(last - first);
 		}
+		sprite.color = Color::rgba(1.0, 1.0, 1.0, dying.timer.percent_left());
+		velocity.linear *= (1.0 - DEATH_FRICTION * time.delta_seconds()).max(0.0);
 	}
 }
 
+/// Tints enemies caught in the lit beam so sweeping the light finds them, walls still hide them
+pub fn reveal_enemies(
+	physics_world: PhysicsWorld,
+	settings: Res<Settings>,
+	player_query: Query<(&Transform, &Flashlight), With<Player>>,
+	mut enemy_query: Query<(&Transform, &mut TextureAtlasSprite), (With<Enemy>, Without<HitFlash>, Without<Pooled>, Without<DyingEnemy>)>,
+) {
+	let (Transform { translation: player_translation, .. }, flashlight) = match player_query.get_single() {
+		Ok(player) => player,
+		Err(_) => return,
+	};
+	for (Transform { translation: enemy_translation, .. }, mut sprite) in enemy_query.iter_mut() {
+		let revealed = flashlight.on
+			&& flashlight.contains(player_translation.truncate(), enemy_translation.truncate())
+			&& line_of_sight(*player_translation, *enemy_translation, &physics_world);
+		sprite.color = if revealed { settings.palette.reveal_color() } else { Color::WHITE };
+	}
+}
+
+// Por encima de la cabeza, un poco delante del sprite
+const ALERT_ICON_HEIGHT: f32 = 14.0;
+const ALERT_ICON_SIZE: f32 = 12.0;
+
+pub fn spawn_alert_icons(
+	mut commands: Commands,
+	font: Res<UiFont>,
+	query: Query<Entity, Added<Enemy>>,
+) {
+	for entity in query.iter() {
+		let icon = ui::world_text(&mut commands, &font, "!", ALERT_ICON_SIZE, Color::WHITE, Vec3::new(0.0, ALERT_ICON_HEIGHT, 0.1));
+		commands.entity(icon).insert(AlertIcon).insert(Visibility { is_visible: false });
+		commands.entity(entity).add_child(icon);
+	}
+}
+
+/// Shows the icon of every chasing or searching enemy, the glyph tells the state apart without
+/// relying on its color
+pub fn update_alert_icons(
+	settings: Res<Settings>,
+	enemy_query: Query<(&EnemyState, &Visibility, Option<&DyingEnemy>), (With<Enemy>, Without<AlertIcon>)>,
+	mut icon_query: Query<(&Parent, &mut Text, &mut Visibility), With<AlertIcon>>,
+) {
+	for (parent, mut text, mut visibility) in icon_query.iter_mut() {
+		let (state, enemy_visibility, dying) = match enemy_query.get(parent.0) {
+			Ok(enemy) => enemy,
+			Err(_) => continue,
+		};
+		let glyph = match state {
+			EnemyState::Chase => "!",
+			EnemyState::Search => "?",
+			_ => "",
+		};
+		// Los del pool estan ocultos y la visibilidad no pasa a los hijos
+		visibility.is_visible = settings.alert_icons && !glyph.is_empty() && enemy_visibility.is_visible && dying.is_none();
+		if !visibility.is_visible { continue }
+		if text.sections[0].value != glyph {
+			text.sections[0].value = glyph.to_string();
+		}
+		text.sections[0].style.color = settings.palette.state_color(*state);
+	}
+}
+
+pub fn move_patroller(
+	time: Res<AiTime>,
+	mut query: Query<(&mut Velocity, &mut Patroller, &Transform)>,
+) {
+	for (mut velocity, mut patroller, transform) in query.iter_mut() {
+		let target = if patroller.towards_end { patroller.end } else { patroller.start };
+		let delta = target - transform.translation.truncate();
+		if delta.length() <= patroller.speed * time.delta_seconds() {
+			patroller.towards_end = !patroller.towards_end;
+		}
+		velocity.linear = delta.normalize_or_zero().extend(0.0) * patroller.speed;
+	}
+}
+
+pub fn track_riding(
+	mut physic_event: EventReader<CollisionEvent>,
+	mut player_query: Query<&mut Riding, With<Player>>,
+) {
+	for event in physic_event.iter() {
+		if let Some((entity_player, entity_platform)) = layer_contact(event, Layer::Player, Layer::Platform) {
+			if let Ok(mut riding) = player_query.get_mut(entity_player) {
+				if event.is_started() {
+					riding.platform = Some(entity_platform);
+				} else if riding.platform == Some(entity_platform) {
+					riding.platform = None;
+				}
+			}
+		}
+	}
+}
+
+/// Adds the velocity of the platform under the player on top of their own movement
+pub fn carry_rider(
+	mut player_query: Query<(&mut Velocity, &Riding), With<Player>>,
+	platform_query: Query<&Velocity, (With<Patroller>, Without<Player>)>,
+) {
+	if let Ok((mut velocity, riding)) = player_query.get_single_mut() {
+		if let Some(platform_velocity) = riding.platform.and_then(|entity| platform_query.get(entity).ok()) {
+			velocity.linear += platform_velocity.linear;
+		}
+	}
+}
+
+pub fn movement(
+	input: Res<Input<KeyCode>>,
+	debug_config: Res<DebugConfig>,
+	mut query: Query<&mut Velocity, (With<Player>, Without<Hidden>)>,
+) {
+	if let Ok(mut velocity) = query.get_single_mut() {
+		// WASD mueve la camara libre, el jugador se queda quieto
+		if debug_config.free_look {
+			velocity.linear = Vec3::ZERO;
+			return;
+		}
+		let mut delta = Vec2::ZERO;
+		if input.pressed(KeyCode::W) { delta.y += 1.0 }
+		if input.pressed(KeyCode::A) { delta.x -= 1.0 }
+		if input.pressed(KeyCode::S) { delta.y -= 1.0 }
+		if input.pressed(KeyCode::D) { delta.x += 1.0 }
+		delta = delta.normalize_or_zero();
+
+		velocity.linear = delta.extend(0.0) * 100.;
+	}
+}
+
+/// Overrides the walking velocity while a dash runs, optionally dropping `Layer::Enemy` from the mask
+pub fn dash(
+	time: Res<Time>,
+	input: Res<Input<KeyCode>>,
+	debug_config: Res<DebugConfig>,
+	dash_config: Res<DashConfig>,
+	mut query: Query<(&mut Dash, &mut Velocity, &mut CollisionLayers, &Flashlight), (With<Player>, Without<Hidden>)>,
+) {
+	let (mut dash, mut velocity, mut layers, flashlight) = match query.get_single_mut() {
+		Ok(player) => player,
+		Err(_) => return,
+	};
+	dash.cooldown.tick(time.delta());
+
+	if dash.active.is_none() && dash.cooldown.finished() && input.just_pressed(KeyCode::Space) && !debug_config.free_look {
+		// Quieto sale disparado hacia donde apunta
+		let walking = velocity.linear.truncate().normalize_or_zero();
+		dash.direction = if walking == Vec2::ZERO { flashlight.direction } else { walking };
+		dash.active = Some(Timer::from_seconds(dash_config.duration, false));
+		dash.cooldown = Timer::from_seconds(dash_config.cooldown, false);
+		if dash_config.phase_through_enemies {
+			dash.saved_layers = Some(*layers);
+			*layers = layers.without_mask(Layer::Enemy);
+		}
+	}
+
+	let finished = match dash.active.as_mut() {
+		Some(timer) => timer.tick(time.delta()).finished(),
+		None => return,
+	};
+	if finished {
+		dash.active = None;
+		if let Some(saved_layers) = dash.saved_layers.take() {
+			*layers = saved_layers;
+		}
+	} else {
+		velocity.linear = dash.direction.extend(0.0) * dash_config.speed;
+	}
+}
+
+pub fn spawn_afterimages(
+	mut commands: Commands,
+	dash_config: Res<DashConfig>,
+	mut spawned: Local<usize>,
+	query: Query<(&Dash, &Transform, &TextureAtlasSprite, &Handle<TextureAtlas>), With<Player>>,
+) {
+	let (dash, transform, sprite, texture_atlas) = match query.get_single() {
+		Ok(player) => player,
+		Err(_) => return,
+	};
+	let timer = match &dash.active {
+		Some(timer) => timer,
+		None => {
+			*spawned = 0;
+			return;
+		}
+	};
+
+	// Repartidas a lo largo del dash, la primera sale nada mas empezar
+	let due = ((timer.percent() * dash_config.afterimages as f32) as usize + 1).min(dash_config.afterimages);
+	while *spawned < due {
+		*spawned += 1;
+		let mut afterimage_sprite = TextureAtlasSprite::new(sprite.index);
+		afterimage_sprite.flip_x = sprite.flip_x;
+		afterimage_sprite.color.set_a(dash_config.afterimage_alpha);
+		commands.spawn_bundle(SpriteSheetBundle {
+			sprite: afterimage_sprite,
+			texture_atlas: texture_atlas.clone(),
+			// Justo detras del jugador
+			transform: Transform::from_translation(transform.translation - Vec3::Z * 0.01),
+			..Default::default()
+		})
+		.insert(Fade { alpha: dash_config.afterimage_alpha })
+		.insert(Lifetime { timer: Timer::from_seconds(dash_config.afterimage_lifetime, false) });
+	}
+}
+
+/// Sends a `Footstep` each `DustConfig.stride` the player covers, none while standing still
+pub fn footsteps(
+	time: Res<Time>,
+	dust_config: Res<DustConfig>,
+	mut walked: Local<f32>,
+	mut footstep_events: EventWriter<Footstep>,
+	query: Query<(&Transform, &Velocity, &Dash), (With<Player>, Without<Hidden>)>,
+) {
+	let (transform, velocity, dash) = match query.get_single() {
+		Ok(player) => player,
+		Err(_) => return,
+	};
+	let speed = velocity.linear.truncate().length();
+	if speed < dust_config.min_speed {
+		// El primer paso sale nada mas arrancar
+		*walked = dust_config.stride;
+		return;
+	}
+	*walked += speed * time.delta_seconds();
+	if *walked < dust_config.stride { return }
+	*walked %= dust_config.stride;
+	footstep_events.send(Footstep {
+		position: transform.translation,
+		behind: -velocity.linear.truncate() / speed,
+		dashing: dash.active.is_some(),
+	});
+}
+
+pub fn spawn_dust(
+	mut commands: Commands,
+	dust_config: Res<DustConfig>,
+	mut rng: ResMut<GameRng>,
+	mut footstep_events: EventReader<Footstep>,
+) {
+	for footstep in footstep_events.iter() {
+		let count = if footstep.dashing { dust_config.dash_particles } else { dust_config.particles_per_step };
+		for _ in 0..count {
+			let scatter = Vec2::new(rng.0.gen_range(-1.0..1.0), rng.0.gen_range(-1.0..1.0)) * dust_config.spread;
+			let position = footstep.position.truncate() + footstep.behind * dust_config.spread + scatter;
+			commands.spawn_bundle(SpriteBundle {
+				sprite: Sprite {
+					color: dust_config.color,
+					custom_size: Some(Vec2::splat(dust_config.size)),
+					..Default::default()
+				},
+				// Por debajo del jugador, como las estelas del dash
+				transform: Transform::from_translation(position.extend(footstep.position.z - 0.01)),
+				..Default::default()
+			})
+			.insert(Fade { alpha: dust_config.color.a() })
+			.insert(Lifetime { timer: Timer::from_seconds(dust_config.lifetime, false) });
+		}
+	}
+}
+
+/// Fades `Fade` sprites and text out over their `Lifetime`, `tick_lifetimes` despawns them
+pub fn fade_out(
+	mut atlas_query: Query<(&Lifetime, &Fade, &mut TextureAtlasSprite)>,
+	mut sprite_query: Query<(&Lifetime, &Fade, &mut Sprite)>,
+	mut text_query: Query<(&Lifetime, &Fade, &mut Text)>,
+) {
+	for (lifetime, fade, mut text) in text_query.iter_mut() {
+		for section in text.sections.iter_mut() {
+			section.style.color.set_a(fade.alpha * lifetime.timer.percent_left());
+		}
+	}
+	for (lifetime, fade, mut sprite) in atlas_query.iter_mut() {
+		sprite.color.set_a(fade.alpha * lifetime.timer.percent_left());
+	}
+	for (lifetime, fade, mut sprite) in sprite_query.iter_mut() {
+		sprite.color.set_a(fade.alpha * lifetime.timer.percent_left());
+	}
+}
+
+pub fn drift(
+	time: Res<Time>,
+	mut query: Query<(&Drift, &mut Transform)>,
+) {
+	for (drift, mut transform) in query.iter_mut() {
+		transform.translation += (drift.velocity * time.delta_seconds()).extend(0.0);
+	}
+}
+
+fn apply_spawn_effect(effect: SpawnEffect, progress: f32, transform: &mut Transform, color: &mut Color) {
+	match effect {
+		SpawnEffect::Fade => { color.set_a(progress); }
+		SpawnEffect::Scale => transform.scale = Vec3::splat(progress),
+	}
+}
+
+pub fn insert_spawn_anim(
+	mut commands: Commands,
+	spawn_anim_config: Res<SpawnAnimConfig>,
+	// Sin el Without bevy no sabe que las dos queries no comparten Transform
+	mut atlas_query: Query<(Entity, &mut Transform, &mut TextureAtlasSprite), (Or<(Added<Player>, Added<Enemy>)>, Without<Sprite>)>,
+	mut sprite_query: Query<(Entity, &mut Transform, &mut Sprite), Added<Win>>,
+) {
+	let effect = match spawn_anim_config.effect {
+		Some(effect) => effect,
+		None => return,
+	};
+	let timer = || SpawnAnim { timer: Timer::from_seconds(spawn_anim_config.duration, false) };
+	// Empieza ya invisible para que no se vea un frame entero
+	for (entity, mut transform, mut sprite) in atlas_query.iter_mut() {
+		apply_spawn_effect(effect, 0.0, &mut transform, &mut sprite.color);
+		commands.entity(entity).insert(timer());
+	}
+	for (entity, mut transform, mut sprite) in sprite_query.iter_mut() {
+		apply_spawn_effect(effect, 0.0, &mut transform, &mut sprite.color);
+		commands.entity(entity).insert(timer());
+	}
+}
+
+pub fn spawn_anim(
+	mut commands: Commands,
+	time: Res<Time>,
+	spawn_anim_config: Res<SpawnAnimConfig>,
+	mut atlas_query: Query<(Entity, &mut SpawnAnim, &mut Transform, &mut TextureAtlasSprite), Without<Sprite>>,
+	mut sprite_query: Query<(Entity, &mut SpawnAnim, &mut Transform, &mut Sprite)>,
+) {
+	let effect = match spawn_anim_config.effect {
+		Some(effect) => effect,
+		None => return,
+	};
+	let step = |commands: &mut Commands, entity, spawn_anim: &mut SpawnAnim, transform: &mut Transform, color: &mut Color| {
+		if spawn_anim.timer.tick(time.delta()).finished() {
+			commands.entity(entity).remove::<SpawnAnim>();
+		}
+		apply_spawn_effect(effect, spawn_anim.timer.percent(), transform, color);
+	};
+	for (entity, mut spawn_anim, mut transform, mut sprite) in atlas_query.iter_mut() {
+		step(&mut commands, entity, &mut spawn_anim, &mut transform, &mut sprite.color);
+	}
+	for (entity, mut spawn_anim, mut transform, mut sprite) in sprite_query.iter_mut() {
+		step(&mut commands, entity, &mut spawn_anim, &mut transform, &mut sprite.color);
+	}
+}
+
+pub fn tick_lifetimes(
+	mut commands: Commands,
+	time: Res<Time>,
+	mut query: Query<(Entity, &mut Lifetime)>,
+) {
+	for (entity, mut lifetime) in query.iter_mut() {
+		if lifetime.timer.tick(time.delta()).finished() {
+			commands.entity(entity).despawn_recursive();
+		}
+	}
+}
+
+pub fn animation(
+	time: Res<Time>,
+	mut query: Query<(&Velocity, &mut Timer, &mut TextureAtlasSprite, &AnimationDef), Without<DyingEnemy>>,
+) {
+	for (velocity, mut timer, mut sprite, animation_def) in query.iter_mut() {
+		timer.tick(time.delta());
+		if timer.finished() && velocity.linear != Vec3::ZERO {
+			sprite.index = (sprite.index + 1) % animation_def.frames;
+		} else if velocity.linear == Vec3::ZERO {
+			sprite.index = 0;
+		}
+	}
+}
+
+// Los sprites multiplican su textura por el color, pasarse de 1 lo aclara hacia el blanco
+const HIT_FLASH_COLOR: Color = Color::rgb(8.0, 8.0, 8.0);
+
+pub fn hit_flash(
+	mut commands: Commands,
+	time: Res<Time>,
+	mut query: Query<(Entity, &mut HitFlash, &mut TextureAtlasSprite)>,
+) {
+	for (entity, mut flash, mut sprite) in query.iter_mut() {
+		if flash.timer.tick(time.delta()).finished() {
+			sprite.color = Color::WHITE;
+			commands.entity(entity).remove::<HitFlash>();
+		} else {
+			sprite.color = HIT_FLASH_COLOR;
+		}
+	}
+}
+
+fn spawn_win_ui(commands: &mut Commands, font: &UiFont, text: &str) {
+	let entity = ui::centered_text(commands, font, text, 100.0, Color::WHITE);
+	commands.entity(entity).insert(WinUi);
+}
+
+/// Returns the rigid body entities in layers `a` and `b` if the event is a contact between them
+pub fn layer_contact(event: &CollisionEvent, a: Layer, b: Layer) -> Option<(Entity, Entity)> {
+	let (e1, e2) = event.rigid_body_entities();
+	let (l1, l2) = event.collision_layers();
+
+	if l1.contains_group(a) && l2.contains_group(b) {
+		Some((e1, e2))
+	} else if l2.contains_group(a) && l1.contains_group(b) {
+		Some((e2, e1))
+	} else {
+		None
+	}
+}
+
+/// Starts the countdown every time a level loads, restarts after dying included
+pub fn start_level_grace(
+	mut commands: Commands,
+	mut level_events: EventReader<LevelEvent>,
+	font: Res<UiFont>,
+	mut grace: ResMut<LevelGrace>,
+	grace_ui_query: Query<Entity, With<GraceUi>>,
+) {
+	if !level_events.iter().any(|event| matches!(event, LevelEvent::Transformed(_))) { return }
+	grace_ui_query.for_each(|entity| commands.entity(entity).despawn_recursive());
+	if grace.seconds <= 0.0 {
+		grace.timer = None;
+		return;
+	}
+	grace.timer = Some(Timer::from_seconds(grace.seconds, false));
+	let entity = ui::centered_text(&mut commands, &font, &format!("{}", grace.seconds.ceil()), 100.0, Color::WHITE);
+	commands.entity(entity).insert(GraceUi);
+}
+
+/// Counts the grace down on screen and lets the hunt begin when it runs out
+pub fn tick_level_grace(
+	mut commands: Commands,
+	time: Res<Time>,
+	mut grace: ResMut<LevelGrace>,
+	grace_ui_query: Query<(Entity, &Children), With<GraceUi>>,
+	mut text_query: Query<&mut Text>,
+) {
+	let timer = match &mut grace.timer {
+		Some(timer) => timer,
+		None => return,
+	};
+	let finished = timer.tick(time.delta()).finished();
+	let remaining = timer.duration().as_secs_f32() - timer.elapsed_secs();
+	let value = if finished { "Run!".to_string() } else { format!("{}", remaining.ceil()) };
+	for (entity, children) in grace_ui_query.iter() {
+		for &child in children.iter() {
+			if let Ok(mut text) = text_query.get_mut(child) {
+				text.sections[0].value = value.clone();
+			}
+		}
+		if finished {
+			commands.entity(entity).insert(Lifetime { timer: Timer::from_seconds(GRACE_RUN_SECONDS, false) });
+		}
+	}
+	if finished {
+		grace.timer = None;
+	}
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn win(
+	mut commands: Commands,
+	font: Res<UiFont>,
+	score: Res<Score>,
+	level_timer: Res<LevelTimer>,
+	mut best_times: ResMut<BestTimes>,
+	mut stats: ResMut<Stats>,
+	mut level_transition: ResMut<LevelTransition>,
+	mut level_events: EventReader<LevelEvent>,
+	mut physic_event: EventReader<CollisionEvent>,
+	win_query: Query<(), With<Win>>,
+) {
+	if level_events.iter().any(|event| matches!(event, LevelEvent::Transformed(_))) {
+		level_transition.loading = false;
+	}
+	physic_event.iter().filter(|e| e.is_started()).filter_map(|event| {
+		layer_contact(event, Layer::Player, Layer::Win).map(|(_, entity_win)| entity_win)
+	}).for_each(|entity_win| {
+		// Varios contactos pueden llegar en el mismo frame, solo el primero cuenta, y los de una
+		// meta ya despawneada no cuentan nunca
+		if level_transition.timer.is_none() && !level_transition.loading && win_query.get(entity_win).is_ok() {
+			commands.entity(entity_win).despawn();
+
+			let mut text = String::from("You Win!!!");
+			if score.total > 0 {
+				text += &format!("\n{} / {} collected", score.collected, score.total);
+				let best = stats.best_collected.entry(score.level_uid).or_default();
+				*best = (*best).max(score.collected);
+			}
+			text += "\n";
+			text += &best_times.record_level(score.level_uid, level_timer.level);
+			spawn_win_ui(&mut commands, &font, &text);
+			level_transition.timer = Some(Timer::from_seconds(WIN_SCREEN_SECONDS, false));
+		}
+	})
+}
+
+pub fn collect(
+	mut commands: Commands,
+	mut score: ResMut<Score>,
+	mut collected: ResMut<CollectedEntities>,
+	mut physic_event: EventReader<CollisionEvent>,
+	pickup_query: Query<Option<&CollectibleId>, With<Collectible>>,
+) {
+	// El despawn espera al final del frame, un segundo contacto con la misma no cuenta otra vez
+	let mut picked = HashSet::new();
+	physic_event.iter().filter(|e| e.is_started()).filter_map(|event| {
+		layer_contact(event, Layer::Player, Layer::Pickup).map(|(_, entity_pickup)| entity_pickup)
+	}).filter(|entity_pickup| pickup_query.get(*entity_pickup).is_ok() && picked.insert(*entity_pickup)).for_each(|entity_pickup| {
+		commands.entity(entity_pickup).despawn();
+		score.collected += 1;
+		if let Ok(Some(id)) = pickup_query.get(entity_pickup) {
+			collected.0.insert((score.level_uid, *id));
+		}
+	})
+}
+
+/// Dismisses the win screen once its timer runs out and moves on to the next level,
+/// or leaves an end screen up if this was the last one
+#[allow(clippy::too_many_arguments)]
+pub fn advance_level(
+	mut commands: Commands,
+	time: Res<Time>,
+	font: Res<UiFont>,
+	level_timer: Res<LevelTimer>,
+	mut best_times: ResMut<BestTimes>,
+	ldtk_assets: Res<Assets<LdtkAsset>>,
+	ldtk_query: Query<&Handle<LdtkAsset>>,
+	win_ui_query: Query<Entity, With<WinUi>>,
+	mut level_transition: ResMut<LevelTransition>,
+	mut level_selection: ResMut<LevelSelection>,
+) {
+	let timer = match &mut level_transition.timer {
+		Some(timer) => timer,
+		None => return,
+	};
+	if !timer.tick(time.delta()).just_finished() {
+		return;
+	}
+
+	win_ui_query.for_each(|entity| commands.entity(entity).despawn_recursive());
+
+	let next_level = ldtk_query.get_single().ok()
+		.and_then(|handle| ldtk_assets.get(handle))
+		.and_then(|ldtk_asset| {
+			let levels = &ldtk_asset.project.levels;
+			levels.iter().enumerate()
+				.position(|(i, level)| level_selection.is_match(&i, level))
+				.map(|i| i + 1)
+				.filter(|next| *next < levels.len())
+		});
+
+	match next_level {
+		Some(index) => {
+			*level_selection = LevelSelection::Index(index);
+			level_transition.timer = None;
+			level_transition.loading = true;
+		}
+		// The finished timer stays in place so the goal can't be triggered again
+		None => {
+			let mut text = format!("The End\nRun: {}", format_time(level_timer.run));
+			if let Some(place) = best_times.record_run(level_timer.run) {
+				text += &format!(" (#{} best)", place);
+			}
+			spawn_win_ui(&mut commands, &font, &text);
+		}
+	}
+}
+
+/// Throws away everything from the current run and respawns the world at the first level
+///
+/// `Settings` and the records in `Stats` survive, they aren't part of a run.
+#[allow(clippy::too_many_arguments)]
+pub fn new_game(
+	mut commands: Commands,
+	mut new_game_events: EventReader<NewGame>,
+	mut level_selection: ResMut<LevelSelection>,
+	mut score: ResMut<Score>,
+	mut collected: ResMut<CollectedEntities>,
+	mut level_transition: ResMut<LevelTransition>,
+	mut damage_queue: ResMut<DamageQueue>,
+	mut hit_stop: ResMut<HitStop>,
+	mut fog: ResMut<FogOfWar>,
+	ldtk_query: Query<(Entity, &Handle<LdtkAsset>)>,
+	win_ui_query: Query<Entity, With<WinUi>>,
+) {
+	if new_game_events.iter().count() == 0 { return }
+
+	*level_selection = LevelSelection::Index(0);
+	*score = Score::default();
+	collected.0.clear();
+	// Siempre se recarga el mundo, asi que siempre llega el Transformed que lo limpia
+	*level_transition = LevelTransition { loading: true, ..Default::default() };
+	damage_queue.0.clear();
+	hit_stop.timer = None;
+	// El pool no cuelga del mundo, sus enemigos sirven para la partida nueva
+	fog.level_uid = None;
+	win_ui_query.for_each(|entity| commands.entity(entity).despawn_recursive());
+	// Igual que restart_on_death, el jugador y todo el nivel vuelven como nuevos
+	for (entity, handle) in ldtk_query.iter() {
+		commands.entity(entity).remove::<Handle<LdtkAsset>>().insert(handle.clone());
+	}
+}
+
+/// Cursor offset from the camera center in world units, through the camera's own projection so
+/// it stays right at any aspect ratio, scaling mode or DPI
+pub fn camera_cursor_position(
+	wnds: Res<Windows>,
+	mut mc_query: Query<(&mut MainCamera, &Camera)>,
+) {
+	let wnd = match wnds.get_primary() {
+		Some(wnd) => wnd,
+		None => return,
+	};
+	let (mut mc, camera) = mc_query.single_mut();
+	// Fuera de la ventana se queda la ultima
+	if let Some(cursor) = wnd.cursor_position() {
+		mc.last_cursor_position = unproject_cursor(cursor, Vec2::new(wnd.width(), wnd.height()), camera.projection_matrix);
+	}
+}
+
+fn unproject_cursor(cursor: Vec2, window_size: Vec2, projection: Mat4) -> Vec2 {
+	let ndc = cursor / window_size * 2.0 - Vec2::ONE;
+	projection.inverse().project_point3(ndc.extend(0.0)).truncate()
+}
+
+/// Cursor offset from the player in world units, the camera isn't always centered on the player
+fn aim(camera_translation: Vec3, main_camera: &MainCamera, player_translation: Vec3) -> Vec2 {
+	(camera_translation - player_translation).truncate() + main_camera.last_cursor_position
+}
+
+pub fn setup_reticle(
+	mut commands: Commands,
+	asset_server: Res<AssetServer>,
+	reticle_config: Res<ReticleConfig>,
+) {
+	let (texture, color) = match &reticle_config.texture {
+		Some(texture) => (asset_server.load(texture.as_str()), Color::WHITE),
+		None => (Handle::default(), RETICLE_COLOR),
+	};
+	commands.spawn_bundle(SpriteBundle {
+		sprite: Sprite {
+			color,
+			custom_size: Some(Vec2::splat(RETICLE_SIZE)),
+			..Default::default()
+		},
+		texture,
+		..Default::default()
+	}).insert(Reticle);
+}
+
+/// Puts the reticle where the flashlight aims, hidden while paused, in free look or when turned off
+pub fn update_reticle(
+	input: Res<Input<KeyCode>>,
+	state: Res<State<GameState>>,
+	debug_config: Res<DebugConfig>,
+	mut reticle_config: ResMut<ReticleConfig>,
+	camera_query: Query<(&Transform, &MainCamera)>,
+	player_query: Query<&Transform, With<Player>>,
+	mut reticle_query: Query<(&mut Transform, &mut Visibility), (With<Reticle>, Without<MainCamera>, Without<Player>)>,
+) {
+	if input.just_pressed(KeyCode::R) { reticle_config.visible = !reticle_config.visible }
+	let (mut transform, mut visibility) = match reticle_query.get_single_mut() {
+		Ok(reticle) => reticle,
+		Err(_) => return,
+	};
+	let player_translation = match player_query.get_single() {
+		Ok(player) => player.translation,
+		Err(_) => {
+			visibility.is_visible = false;
+			return;
+		}
+	};
+	visibility.is_visible = reticle_config.visible && *state.current() == GameState::Playing && !debug_config.free_look;
+
+	let (camera_transform, main_camera) = camera_query.single();
+	let target = player_translation.truncate() + aim(camera_transform.translation, main_camera, player_translation);
+	transform.translation = target.extend(RETICLE_Z);
+}
+
+pub fn aim_flashlight(
+	input: Res<Input<KeyCode>>,
+	mouse_input: Res<Input<MouseButton>>,
+	mut mouse_wheel: EventReader<MouseWheel>,
+	debug_config: Res<DebugConfig>,
+	camera_query: Query<(&Transform, &MainCamera)>,
+	mut flashlight_query: Query<(&mut Flashlight, &Transform), With<Player>>,
+) {
+	if let Ok((mut flashlight, Transform { translation: player_translation, .. })) = flashlight_query.get_single_mut() {
+		if debug_config.free_look { return }
+		// En modo Flash el click dispara la rafaga, ver flash_burst
+		if mouse_input.just_pressed(MouseButton::Left) && flashlight.mode != FlashlightMode::Flash && !debug_config.inspector {
+			flashlight.on = !flashlight.on;
+		}
+		let scrolled = mouse_wheel.iter().filter(|event| event.y != 0.0).count() > 0;
+		if input.just_pressed(KeyCode::Q) || scrolled {
+			flashlight.mode = flashlight.mode.next();
+			if flashlight.mode == FlashlightMode::Flash { flashlight.on = false }
+		}
+		let (Transform { translation: camera_translation, .. }, mc) = camera_query.single();
+		let aim = aim(*camera_translation, mc, *player_translation);
+		if aim != Vec2::ZERO {
+			flashlight.direction = aim.normalize();
+		}
+	}
+}
+
+/// Stuns and shoves back every enemy in line of sight within `FlashConfig.radius` when clicking in flash mode
+#[allow(clippy::too_many_arguments)]
+pub fn flash_burst(
+	mut commands: Commands,
+	time: Res<Time>,
+	mouse_input: Res<Input<MouseButton>>,
+	debug_config: Res<DebugConfig>,
+	flash_config: Res<FlashConfig>,
+	physics_world: PhysicsWorld,
+	mut bursts: EventWriter<FlashBurst>,
+	mut player_query: Query<(&mut Flashlight, &mut Battery, &Transform), With<Player>>,
+	mut enemy_query: Query<(Entity, &Transform, &mut Velocity), (With<Enemy>, Without<Dormant>)>,
+	mut overlay_query: Query<&mut Sprite, With<FlashOverlay>>,
+) {
+	let (mut flashlight, mut battery, Transform { translation: player_translation, .. }) = match player_query.get_single_mut() {
+		Ok(player) => player,
+		Err(_) => return,
+	};
+	flashlight.flash_cooldown.tick(time.delta());
+	if flashlight.mode != FlashlightMode::Flash || !mouse_input.just_pressed(MouseButton::Left) || debug_config.free_look || debug_config.inspector { return }
+	if !flashlight.flash_cooldown.finished() || battery.charge < flash_config.cost { return }
+
+	battery.charge -= flash_config.cost;
+	flashlight.flash_cooldown = Timer::from_seconds(flash_config.cooldown, false);
+	bursts.send(FlashBurst);
+	for mut sprite in overlay_query.iter_mut() {
+		sprite.color.set_a(FLASH_OVERLAY_ALPHA);
+	}
+
+	for (entity, Transform { translation: enemy_translation, .. }, mut velocity) in enemy_query.iter_mut() {
+		if enemy_translation.distance(*player_translation) > flash_config.radius { continue }
+		if !line_of_sight(*player_translation, *enemy_translation, &physics_world) { continue }
+		velocity.linear = Vec3::ZERO;
+		apply_radial_impulse(*player_translation, flash_config.radius, flash_config.knockback, *enemy_translation, &mut velocity);
+		commands.entity(entity).insert(Stunned { timer: Timer::from_seconds(flash_config.stun, false) });
+	}
+}
+
+pub fn tick_stun(
+	mut commands: Commands,
+	time: Res<Time>,
+	mut query: Query<(Entity, &mut Stunned, &mut Velocity)>,
+) {
+	for (entity, mut stunned, mut velocity) in query.iter_mut() {
+		// Sin IA que lo mueva, solo se desliza hasta pararse si el destello lo empujo
+		velocity.linear *= (1.0 - STUN_FRICTION * time.delta_seconds()).max(0.0);
+		if stunned.timer.tick(time.delta()).finished() {
+			commands.entity(entity).remove::<Stunned>();
+		}
+	}
+}
+
+// Delante del destello (999.4), detras de la mira
+const VIGNETTE_Z: f32 = 999.45;
+const VIGNETTE_TEXTURE_SIZE: u32 = 64;
+
+// Transparente en el centro, rojo hacia los bordes
+fn vignette_image() -> Image {
+	let half = VIGNETTE_TEXTURE_SIZE as f32 / 2.0;
+	let data = (0..VIGNETTE_TEXTURE_SIZE * VIGNETTE_TEXTURE_SIZE).flat_map(|i| {
+		let offset = Vec2::new((i % VIGNETTE_TEXTURE_SIZE) as f32 + 0.5, (i / VIGNETTE_TEXTURE_SIZE) as f32 + 0.5) - half;
+		let t = ((offset.length() / half - 0.5) * 2.0).clamp(0.0, 1.0);
+		[255, 0, 0, (t * t * 255.0) as u8]
+	}).collect();
+	Image::new(
+		Extent3d { width: VIGNETTE_TEXTURE_SIZE, height: VIGNETTE_TEXTURE_SIZE, depth_or_array_layers: 1 },
+		TextureDimension::D2,
+		data,
+		TextureFormat::Rgba8UnormSrgb,
+	)
+}
+
+pub fn setup_vignette(
+	mut commands: Commands,
+	mut images: ResMut<Assets<Image>>,
+) {
+	commands.spawn_bundle(SpriteBundle {
+		texture: images.add(vignette_image()),
+		visibility: Visibility { is_visible: false },
+		..Default::default()
+	}).insert(Vignette);
+}
+
+/// Starts the `LastStand` slow motion when the player's health drops to its threshold
+pub fn last_stand(
+	time: Res<Time>,
+	mut last_stand: ResMut<LastStand>,
+	player_query: Query<&Health, With<Player>>,
+) {
+	let health = match player_query.get_single() {
+		Ok(health) => health,
+		// Sin jugador, por ejemplo al morir, no hay nada que ralentizar
+		Err(_) => {
+			last_stand.timer = None;
+			return;
+		}
+	};
+	let critical = health.current > 0.0 && health.current <= health.max * last_stand.threshold;
+	if !critical {
+		last_stand.timer = None;
+		last_stand.armed = true;
+		return;
+	}
+	if last_stand.enabled && last_stand.armed {
+		last_stand.armed = false;
+		last_stand.timer = Some(Timer::from_seconds(last_stand.duration, false));
+	}
+	if last_stand.timer.as_mut().is_some_and(|timer| timer.tick(time.delta()).finished()) {
+		last_stand.timer = None;
+	}
+}
+
+/// Keeps the vignette over the whole view and pulses it while the `LastStand` lasts
+pub fn last_stand_vignette(
+	time: Res<Time>,
+	windows: Res<Windows>,
+	last_stand: Res<LastStand>,
+	camera_query: Query<(&Transform, &OrthographicProjection), With<MainCamera>>,
+	mut vignette_query: Query<(&mut Transform, &mut Sprite, &mut Visibility), (With<Vignette>, Without<MainCamera>)>,
+) {
+	let (mut transform, mut sprite, mut visibility) = match vignette_query.get_single_mut() {
+		Ok(vignette) => vignette,
+		Err(_) => return,
+	};
+	visibility.is_visible = last_stand.timer.is_some();
+	if !visibility.is_visible { return }
+	let (camera_transform, projection) = match camera_query.get_single() {
+		Ok(camera) => camera,
+		Err(_) => return,
+	};
+	if let Some(window) = windows.get_primary() {
+		sprite.custom_size = Some(Vec2::new(window.width(), window.height()) * projection.scale);
+	}
+	transform.translation = camera_transform.translation.truncate().extend(VIGNETTE_Z);
+	let pulse = ((time.seconds_since_startup() as f32 * last_stand.pulse_speed * TAU).sin() * 0.5 + 0.5) * 0.4 + 0.6;
+	sprite.color.set_a(last_stand.vignette_alpha * pulse);
+}
+
+pub fn fade_flash_overlay(
+	time: Res<Time>,
+	mut query: Query<&mut Sprite, With<FlashOverlay>>,
+) {
+	for mut sprite in query.iter_mut() {
+		let alpha = (sprite.color.a() - FLASH_OVERLAY_FADE * time.delta_seconds()).max(0.0);
+		sprite.color.set_a(alpha);
+	}
+}
+
+pub fn drain_battery(
+	time: Res<Time>,
+	difficulty: Res<ActiveDifficulty>,
+	mut query: Query<(&mut Flashlight, &mut Battery), With<Player>>,
+) {
+	for (mut flashlight, mut battery) in query.iter_mut() {
+		if flashlight.on {
+			battery.charge -= flashlight.mode.drain() * difficulty.preset.battery_drain * time.delta_seconds();
+			// Se apaga sola, hay que esperar a que recargue algo para volver a encenderla
+			if battery.charge <= 0.0 { flashlight.on = false }
+		} else {
+			battery.charge += BATTERY_RECHARGE * time.delta_seconds();
+		}
+		battery.charge = battery.charge.clamp(0.0, 1.0);
+	}
+}
+
+pub fn setup_hud(
+	mut commands: Commands,
+	font: Res<UiFont>,
+) {
+	commands.spawn_bundle(TextBundle {
+		style: Style {
+			position_type: PositionType::Absolute,
+			position: Rect {
+				bottom: Val::Px(5.0),
+				left: Val::Px(5.0),
+				..Default::default()
+			},
+			..Default::default()
+		},
+		text: Text::with_section("", font.style(24.0, Color::WHITE), Default::default()),
+		..Default::default()
+	}).insert(HudText);
+}
+
+pub fn update_hud(
+	player_query: Query<(&Flashlight, &Battery, &Health), With<Player>>,
+	mut hud_query: Query<&mut Text, With<HudText>>,
+) {
+	let mut text = hud_query.single_mut();
+	text.sections[0].value = match player_query.get_single() {
+		Ok((flashlight, battery, health)) => format!("Health {:.0}/{:.0}  {:?} {:.0}%", health.current, health.max, flashlight.mode, battery.charge * 100.0),
+		Err(_) => String::new(),
+	};
+}
+
+pub fn parallax_scroll(
+	camera_query: Query<&Transform, With<MainCamera>>,
+	mut parallax_query: Query<(&mut Transform, &Parallax), Without<MainCamera>>,
+) {
+	if let Ok(Transform { translation: camera_translation, .. }) = camera_query.get_single() {
+		for (mut transform, parallax) in parallax_query.iter_mut() {
+			let offset = camera_translation.truncate() * parallax.factor;
+			transform.translation = offset.extend(transform.translation.z);
+		}
+	}
+}
+
+/// Corners of a level in world space, where bevy_ecs_ldtk puts it
+fn level_rect(level: &ldtk::Level, ldtk_asset: &LdtkAsset, ldtk_settings: &LdtkSettings) -> (Vec2, Vec2) {
+	let min = if ldtk_settings.use_level_world_translations {
+		// LDtk mide y hacia abajo desde la esquina de arriba del mundo
+		Vec2::new(level.world_x as f32, (ldtk_asset.world_height() - level.world_y - level.px_hei) as f32)
+	} else {
+		Vec2::ZERO
+	};
+	(min, min + Vec2::new(level.px_wid as f32, level.px_hei as f32))
+}
+
+/// Area the camera view has to stay inside, the current level grown towards any neighbour
+/// closer than `blend_distance` so crossing between levels doesn't snap the camera
+fn camera_bounds(center: Vec2, camera_config: &CameraConfig, level: &ldtk::Level, ldtk_asset: &LdtkAsset, ldtk_settings: &LdtkSettings) -> (Vec2, Vec2) {
+	let (mut min, mut max) = level_rect(level, ldtk_asset, ldtk_settings);
+	if !camera_config.blend_neighbours { return (min, max) }
+
+	let (level_min, level_max) = (min, max);
+	for neighbour in level.neighbours.iter() {
+		let neighbour = match ldtk_asset.project.levels.iter().find(|l| l.uid == neighbour.level_uid) {
+			Some(neighbour) => neighbour,
+			None => continue,
+		};
+		let (neighbour_min, neighbour_max) = level_rect(neighbour, ldtk_asset, ldtk_settings);
+		let distance = (neighbour_min - center).max(center - neighbour_max).max(Vec2::ZERO).length();
+		let weight = (1.0 - distance / camera_config.blend_distance).clamp(0.0, 1.0);
+		min = min.min(level_min.lerp(level_min.min(neighbour_min), weight));
+		max = max.max(level_max.lerp(level_max.max(neighbour_max), weight));
+	}
+	(min, max)
+}
+
+/// Keeps a view of `half_size` inside the bounds, centering it on any axis where the bounds are smaller
+fn clamp_to_bounds(center: Vec2, half_size: Vec2, (min, max): (Vec2, Vec2)) -> Vec2 {
+	let axis = |center: f32, half: f32, min: f32, max: f32| {
+		if max - min < half * 2.0 { (min + max) / 2.0 } else { center.clamp(min + half, max - half) }
+	};
+	Vec2::new(axis(center.x, half_size.x, min.x, max.x), axis(center.y, half_size.y, min.y, max.y))
+}
+
+/// Puts the camera right on a freshly spawned player instead of dragging it over from wherever
+/// the last one was
+pub fn recenter_camera(
+	player_query: Query<&Transform, Added<Player>>,
+	mut camera_query: Query<(&mut Transform, &mut MainCamera), Without<Player>>,
+) {
+	let player_translation = match player_query.iter().next() {
+		Some(transform) => transform.translation,
+		None => return,
+	};
+	let (mut camera_trans, mut mc) = camera_query.single_mut();
+	mc.focus = player_translation.truncate();
+	mc.look_ahead = Vec2::ZERO;
+	camera_trans.translation = player_translation.truncate().extend(camera_trans.translation.z);
+}
+
+// Camara fachera
+#[allow(clippy::too_many_arguments)]
+pub fn camera_controller(
+	time: Res<Time>,
+	settings: Res<Settings>,
+	camera_config: Res<CameraConfig>,
+	debug_config: Res<DebugConfig>,
+	ldtk_settings: Res<LdtkSettings>,
+	level_selection: Res<LevelSelection>,
+	ldtk_assets: Res<Assets<LdtkAsset>>,
+	ldtk_query: Query<&Handle<LdtkAsset>>,
+	mut player_query: Query<(&Transform, &Velocity, &mut TextureAtlasSprite), With<Player>>,
+	mut camera_query: Query<(&mut Transform, &mut MainCamera, &OrthographicProjection), Without<Player>>
+) {
+	if debug_config.free_look { return }
+	if let Ok((Transform { translation: player_translation, .. }, velocity, mut sprite)) = player_query.get_single_mut() {
+		let (mut camera_trans, mut mc, projection) = camera_query.single_mut();
+		// Solo se arrastra el foco lo que el jugador se sale de la caja
+		let offset = player_translation.truncate() - mc.focus;
+		let half_dead_zone = camera_config.dead_zone / 2.0;
+		mc.focus += offset - offset.clamp(-half_dead_zone, half_dead_zone);
+
+		let look_ahead = velocity.linear.truncate().normalize_or_zero() * camera_config.look_ahead_distance;
+		let t = (camera_config.look_ahead_speed * time.delta_seconds()).min(1.0);
+		mc.look_ahead = mc.look_ahead.lerp(look_ahead, t).clamp_length_max(camera_config.look_ahead_distance);
+
+		// La camara conserva su z, con la del jugador se recortaba todo lo que esta por encima
+		let mut target = mc.focus + mc.look_ahead + mc.last_cursor_position / 2.0;
+		let ldtk_asset = ldtk_query.get_single().ok().and_then(|handle| ldtk_assets.get(handle));
+		let level = ldtk_asset.and_then(|ldtk_asset| ldtk_asset.get_level(&level_selection).map(|level| (ldtk_asset, level)));
+		if let (true, Some((ldtk_asset, level))) = (camera_config.clamp_to_level, level) {
+			let half_size = Vec2::new(projection.right, projection.top) * projection.scale;
+			target = clamp_to_bounds(target, half_size, camera_bounds(target, &camera_config, level, ldtk_asset, &ldtk_settings));
+		}
+		camera_trans.translation = target.extend(camera_trans.translation.z);
+
+		match settings.facing {
+			FacingMode::Aim => sprite.flip_x = aim(camera_trans.translation, &mc, *player_translation).x < 0.0,
+			// Sin movimiento horizontal se queda mirando hacia donde iba
+			FacingMode::Movement => if velocity.linear.x != 0.0 {
+				sprite.flip_x = velocity.linear.x < 0.0;
+			},
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use bevy::{ecs::system::SystemState, render::camera::{CameraProjection, ScalingMode}};
+
+	use super::*;
+	use crate::{combat::restore_enemy_health, settings::apply_settings, test_util::*};
+
+	fn with_commands<R>(app: &mut App, f: impl FnOnce(&mut Commands, &mut EnemyPool, &Query<(), With<Enemy>>) -> R) -> R {
+		app.world.get_resource_or_insert_with(EnemyPool::default);
+		let mut state = SystemState::<(Commands, ResMut<EnemyPool>, Query<(), With<Enemy>>)>::new(&mut app.world);
+		let (mut commands, mut pool, alive) = state.get_mut(&mut app.world);
+		let result = f(&mut commands, &mut pool, &alive);
+		state.apply(&mut app.world);
+		result
+	}
+
+	#[test]
+	fn acquired_enemy_comes_back_fresh() {
+		let mut app = test_app();
+		app.add_system(restore_enemy_health);
+		let enemy = spawn_enemy(&mut app.world, Vec2::ZERO);
+		{
+			let mut entity = app.world.entity_mut(enemy);
+			*entity.get_mut::<EnemyState>().unwrap() = EnemyState::Chase;
+			entity.get_mut::<Health>().unwrap().current = 0.5;
+			entity.get_mut::<Alert>().unwrap().target = Vec2::new(40.0, 40.0);
+			entity.get_mut::<Attack>().unwrap().windup = Some(Timer::from_seconds(1.0, false));
+			entity.get_mut::<Vision>().unwrap().can_see_player = true;
+			entity.get_mut::<NavPath>().unwrap().waypoints.push(Vec2::ONE);
+		}
+		with_commands(&mut app, |commands, pool, _| recycle_enemy(commands, pool, enemy));
+		assert!(app.world.get::<Pooled>(enemy).is_some());
+		assert!(app.world.get::<RigidBody>(enemy).is_none());
+
+		with_commands(&mut app, |commands, pool, alive| {
+			assert_eq!(acquire_enemy(commands, pool, alive, Vec3::new(10.0, 20.0, 0.0)), Some(enemy));
+			assert_eq!(acquire_enemy(commands, pool, alive, Vec3::ZERO), None);
+		});
+		app.update();
+
+		let entity = app.world.entity(enemy);
+		assert!(entity.get::<Pooled>().is_none());
+		assert!(entity.get::<Dormant>().is_some());
+		assert_eq!(entity.get::<RigidBody>(), Some(&RigidBody::Dynamic));
+		assert_eq!(entity.get::<Transform>().unwrap().translation, Vec3::new(10.0, 20.0, 0.0));
+		assert_eq!(*entity.get::<EnemyState>().unwrap(), EnemyState::Idle);
+		let health = entity.get::<Health>().unwrap();
+		assert_eq!(health.current, health.max);
+		assert_eq!(entity.get::<Alert>().unwrap().target, Vec2::ZERO);
+		assert!(entity.get::<Attack>().unwrap().windup.is_none());
+		assert!(!entity.get::<Vision>().unwrap().can_see_player);
+		assert!(entity.get::<NavPath>().unwrap().waypoints.is_empty());
+	}
+
+	#[test]
+	fn pooled_enemies_outlive_their_level() {
+		let mut app = test_app();
+		app.add_plugin(TransformPlugin);
+		let level = app.world.spawn().insert_bundle((Transform::default(), GlobalTransform::default())).id();
+		let kept = spawn_enemy(&mut app.world, Vec2::ZERO);
+		let lost = spawn_enemy(&mut app.world, Vec2::ZERO);
+		app.world.entity_mut(level).push_children(&[kept, lost]);
+		with_commands(&mut app, |commands, pool, _| recycle_enemy(commands, pool, kept));
+		app.update();
+		// Este muere en el mismo frame que se va el nivel, antes de salir de el
+		with_commands(&mut app, |commands, pool, _| {
+			recycle_enemy(commands, pool, lost);
+			commands.entity(level).despawn_recursive();
+		});
+		app.update();
+
+		assert!(app.world.get_entity(kept).is_some());
+		assert!(app.world.get::<Parent>(kept).is_none());
+		with_commands(&mut app, |commands, pool, alive| {
+			assert_eq!(acquire_enemy(commands, pool, alive, Vec3::ZERO), Some(kept));
+			assert!(pool.inactive.is_empty());
+		});
+	}
+
+	// Sin ignore tarda demasiado para cada cargo test, se mira con cargo test -- --ignored --nocapture
+	#[test]
+	#[ignore]
+	fn pool_stress() {
+		const ENEMIES: usize = 200;
+		const LEVELS: usize = 20;
+		let run = |pooled: bool| {
+			let mut app = physics_app();
+			let start = std::time::Instant::now();
+			for _ in 0..LEVELS {
+				let positions: Vec<Vec2> = (0..ENEMIES).map(|i| Vec2::new((i % 20) as f32 * 16.0, (i / 20) as f32 * 16.0)).collect();
+				let acquired: Vec<Option<Entity>> = with_commands(&mut app, |commands, pool, alive| {
+					positions.iter().map(|position| acquire_enemy(commands, pool, alive, position.extend(0.0)).filter(|_| pooled)).collect()
+				});
+				let enemies: Vec<Entity> = acquired.into_iter().zip(&positions)
+					.map(|(acquired, &position)| acquired.unwrap_or_else(|| spawn_enemy(&mut app.world, position)))
+					.collect();
+				app.update();
+				with_commands(&mut app, |commands, pool, _| for &enemy in &enemies {
+					if pooled { recycle_enemy(commands, pool, enemy) } else { commands.entity(enemy).despawn() }
+				});
+				app.update();
+			}
+			start.elapsed()
+		};
+		let (fresh, pooled) = (run(false), run(true));
+		println!("{} levels of {} enemies: spawn and despawn {:?}, pool {:?}", LEVELS, ENEMIES, fresh, pooled);
+	}
+
+	// Jugador en (100, 0) y enemigo en el origen, con paredes y actores en medio
+	fn sees_player(walls: &[(Vec2, Vec2)], actors: &[Vec2]) -> bool {
+		let mut app = physics_app();
+		app.add_system(enemy_vision);
+		spawn_player(&mut app.world, Vec2::new(100.0, 0.0));
+		let enemy = spawn_enemy(&mut app.world, Vec2::ZERO);
+		for &(center, half_size) in walls {
+			spawn_wall(&mut app.world, center, half_size);
+		}
+		for &position in actors {
+			spawn_enemy(&mut app.world, position);
+		}
+		for _ in 0..3 { app.update() }
+		app.world.get::<Vision>(enemy).unwrap().can_see_player
+	}
+
+	#[test]
+	fn pillar_blocks_line_of_sight() {
+		assert!(sees_player(&[], &[]));
+		assert!(!sees_player(&[(Vec2::new(50.0, 0.0), Vec2::new(8.0, 8.0))], &[]));
+	}
+
+	#[test]
+	fn corridor_walls_beside_the_ray_dont_block() {
+		let corridor = [(Vec2::new(50.0, 24.0), Vec2::new(60.0, 8.0)), (Vec2::new(50.0, -24.0), Vec2::new(60.0, 8.0))];
+		assert!(sees_player(&corridor, &[]));
+	}
+
+	#[test]
+	fn actors_in_between_dont_block() {
+		assert!(sees_player(&[], &[Vec2::new(50.0, 0.0)]));
+	}
+
+	// Dash hacia la derecha contra un enemigo en el origen, devuelve jugador y enemigo al final
+	fn dash_into_enemy(dash_config: DashConfig, updates: usize) -> (App, Entity, Entity) {
+		let mut app = physics_app();
+		let mut input = Input::<KeyCode>::default();
+		input.press(KeyCode::Space);
+		app.insert_resource(input)
+			.insert_resource(dash_config)
+			.add_system(dash);
+		let player = spawn_player(&mut app.world, Vec2::new(-40.0, 0.0));
+		app.world.get_mut::<Flashlight>(player).unwrap().direction = Vec2::X;
+		let enemy = spawn_enemy(&mut app.world, Vec2::ZERO);
+		app.update();
+		app.world.get_resource_mut::<Input<KeyCode>>().unwrap().clear();
+		for _ in 1..updates { app.update() }
+		(app, player, enemy)
+	}
+
+	#[test]
+	fn dash_phases_through_enemies() {
+		let (app, player, enemy) = dash_into_enemy(DashConfig { duration: 10.0, ..Default::default() }, 20);
+		assert!(app.world.get::<Dash>(player).unwrap().active.is_some());
+		assert!(!app.world.get::<CollisionLayers>(player).unwrap().contains_mask(Layer::Enemy));
+		assert!(position(&app, player).x > 40.0);
+		assert!(position(&app, enemy).length() < 1.0);
+	}
+
+	#[test]
+	fn dash_without_phasing_hits_enemies() {
+		let config = DashConfig { duration: 10.0, phase_through_enemies: false, ..Default::default() };
+		let (app, player, enemy) = dash_into_enemy(config, 20);
+		assert!(app.world.get::<CollisionLayers>(player).unwrap().contains_mask(Layer::Enemy));
+		assert!(position(&app, player).x < position(&app, enemy).x);
+	}
+
+	#[test]
+	fn dash_gives_the_layers_back() {
+		let (mut app, player, _) = dash_into_enemy(DashConfig { duration: 0.02, ..Default::default() }, 1);
+		assert_ne!(*app.world.get::<CollisionLayers>(player).unwrap(), layers_for("Player"));
+		// Se agota a mano para no depender del reloj, cualquier tick lo termina
+		let mut dash = app.world.get_mut::<Dash>(player).unwrap();
+		dash.active.as_mut().unwrap().set_elapsed(Duration::from_secs_f32(0.02));
+		app.update();
+		assert!(app.world.get::<Dash>(player).unwrap().active.is_none());
+		assert_eq!(*app.world.get::<CollisionLayers>(player).unwrap(), layers_for("Player"));
+	}
+
+	fn walk_right(mut query: Query<&mut Velocity, With<Player>>) {
+		for mut velocity in query.iter_mut() {
+			velocity.linear = Vec3::X * 100.0;
+		}
+	}
+
+	// Camina a la derecha rozando por abajo la esquina de una pared, como al doblar un pasillo en L
+	// demasiado pegado, devuelve donde acaba
+	fn walk_past_corner(overlap: f32, rounded: bool) -> Vec2 {
+		let mut app = physics_app();
+		app.add_system(walk_right);
+		let player = spawn_player(&mut app.world, Vec2::ZERO);
+		if !rounded {
+			app.world.entity_mut(player).insert(CollisionShape::Cuboid { half_extends: Vec3::new(7.0, 14.0, 0.0), border_radius: None });
+		}
+		spawn_wall(&mut app.world, Vec2::new(40.0, -14.0 - 8.0 + overlap), Vec2::new(8.0, 8.0));
+		for _ in 0..60 { app.update() }
+		position(&app, player)
+	}
+
+	#[test]
+	fn rounded_player_glides_over_corners() {
+		// Con esquinas rectas se engancha en el borde de la pared
+		assert!(walk_past_corner(1.0, false).x < 30.0);
+		let rounded = walk_past_corner(1.0, true);
+		assert!(rounded.x > 90.0);
+		assert!(rounded.y.abs() < 2.0);
+	}
+
+	#[test]
+	fn ai_time_uses_the_fixed_step() {
+		let mut app = test_app();
+		app.insert_resource(GameConfig { fixed_timestep: Some(0.05), ..Default::default() })
+			.add_system(update_ai_time);
+		// Con paso fijo no importa cuanto dure el frame
+		app.update();
+		assert_eq!(app.world.get_resource::<AiTime>().unwrap().delta, Duration::from_secs_f32(0.05));
+	}
+
+	/// Frame time the test renders at, in place of `pause::frame_delta`
+	struct RenderDelta(Duration);
+
+	fn render_delta(delta: Res<RenderDelta>) -> Duration {
+		delta.0
+	}
+
+	#[derive(Default)]
+	struct FixedSteps(usize);
+
+	fn count_fixed_steps(mut steps: ResMut<FixedSteps>) {
+		steps.0 += 1;
+	}
+
+	// Una manada apretada persiguiendo al jugador, con esquivas entre ellos, montada como en main.rs
+	// salvo que el tiempo de cada frame lo pone la prueba
+	fn chase_run(frame: f32, steps: usize) -> Vec<Vec2> {
+		let mut app = physics_app();
+		app.insert_resource(GameConfig { fixed_timestep: Some(1.0 / 30.0), ..Default::default() })
+			.insert_resource(RenderDelta(Duration::from_secs_f32(frame)))
+			.init_resource::<FixedSteps>()
+			.add_state(GameState::Playing)
+			.add_system_to_stage(CoreStage::First, crate::pause::hold_physics)
+			.add_system(update_ai_time.before("vision"))
+			.add_system_set(
+				SystemSet::new()
+					.with_run_criteria(render_delta.chain(crate::pause::fixed_step).chain(crate::pause::playing_fixed_step))
+					.with_system(enemy_vision.label("vision"))
+					.with_system(enemy_movement.after("vision"))
+					.with_system(crate::pause::step_physics)
+					.with_system(count_fixed_steps),
+			);
+		let player = spawn_player(&mut app.world, Vec2::new(150.0, 10.0));
+		let mut entities = vec![player];
+		for i in 0..5 {
+			entities.push(spawn_enemy(&mut app.world, Vec2::new((i % 2) as f32 * 12.0, i as f32 * 10.0 - 20.0)));
+		}
+		while app.world.get_resource::<FixedSteps>().unwrap().0 < steps { app.update() }
+		assert_eq!(app.world.get_resource::<FixedSteps>().unwrap().0, steps);
+		entities.iter().map(|&entity| position(&app, entity)).collect()
+	}
+
+	#[test]
+	fn fixed_steps_replay_the_same() {
+		let first = chase_run(1.0 / 30.0, 45);
+		assert!(first[1..].iter().any(|position| position.x > 20.0));
+		assert_eq!(chase_run(1.0 / 30.0, 45), first);
+		assert_eq!(chase_run(1.0 / 144.0, 45), first);
+	}
+
+	fn push_from_origin(distance: f32, force: f32) -> Vec3 {
+		let mut velocity = Velocity::default();
+		apply_radial_impulse(Vec3::ZERO, 100.0, force, Vec3::new(distance, 0.0, 0.0), &mut velocity);
+		velocity.linear
+	}
+
+	#[test]
+	fn radial_impulse_fades_with_distance() {
+		let near = push_from_origin(10.0, 200.0);
+		let far = push_from_origin(90.0, 200.0);
+		assert!((near.x - 180.0).abs() < 1e-3 && near.y == 0.0);
+		assert!((far.x - 20.0).abs() < 1e-3);
+		assert_eq!(push_from_origin(100.0, 200.0), Vec3::ZERO);
+		assert_eq!(push_from_origin(150.0, 200.0), Vec3::ZERO);
+		assert_eq!(push_from_origin(0.0, 200.0), Vec3::Y * 200.0);
+	}
+
+	#[test]
+	fn radial_impulse_is_capped() {
+		assert_eq!(push_from_origin(10.0, 10000.0).x, MAX_RADIAL_IMPULSE);
+		let mut velocity = Velocity::from_linear(Vec3::new(-50.0, 0.0, 0.0));
+		apply_radial_impulse(Vec3::ZERO, 100.0, 10000.0, Vec3::new(10.0, 0.0, 0.0), &mut velocity);
+		assert_eq!(velocity.linear.x, MAX_RADIAL_IMPULSE - 50.0);
+	}
+
+	#[test]
+	fn engaged_enemies_use_the_lose_range() {
+		let enemy_config = EnemyConfig::default();
+		assert!(enemy_config.detection_range > enemy_config.lose_range);
+		assert_eq!(vision_range(EnemyState::Idle, &enemy_config), enemy_config.detection_range);
+		assert_eq!(vision_range(EnemyState::Return, &enemy_config), enemy_config.detection_range);
+		assert_eq!(vision_range(EnemyState::Chase, &enemy_config), enemy_config.lose_range);
+		assert_eq!(vision_range(EnemyState::Search, &enemy_config), enemy_config.lose_range);
+	}
+
+	// Enemigo quieto en el origen y jugador a `distance`, devuelve el estado tras cada update
+	fn standoff(distance: f32, updates: usize) -> (Vec<EnemyState>, Vec2) {
+		let mut app = physics_app();
+		app.insert_resource(EnemyConfig { speed: 0.0, min_chase_time: 0.5, ..Default::default() })
+			.add_system(enemy_vision.label("vision"))
+			.add_system(enemy_movement.after("vision"));
+		spawn_player(&mut app.world, Vec2::new(distance, 0.0));
+		let enemy = spawn_enemy(&mut app.world, Vec2::ZERO);
+		let states = (0..updates).map(|_| {
+			app.update();
+			*app.world.get::<EnemyState>(enemy).unwrap()
+		}).collect();
+		(states, app.world.get::<Alert>(enemy).unwrap().target)
+	}
+
+	#[test]
+	fn chase_starts_at_detection_range_and_drops_after_min_chase_time() {
+		// 230 esta entre lose_range y detection_range
+		let (states, target) = standoff(230.0, 90);
+		assert_eq!(states[0], EnemyState::Chase);
+		// 0.5 segundos a 60 updates por segundo
+		assert!(states[..29].iter().all(|state| *state == EnemyState::Chase));
+		let lost = states.iter().position(|state| *state == EnemyState::Search).unwrap();
+		assert!(lost <= 31);
+		// Buscando tampoco lo ve a esa distancia, asi que no alterna entre perseguir y buscar
+		assert!(states[lost..].iter().all(|state| *state == EnemyState::Search));
+		assert!(target.distance(Vec2::new(230.0, 0.0)) < 1.0);
+	}
+
+	#[test]
+	fn chase_holds_inside_lose_range() {
+		let (states, _) = standoff(190.0, 90);
+		assert!(states.iter().all(|state| *state == EnemyState::Chase));
+	}
+
+	#[test]
+	fn nothing_is_seen_past_detection_range() {
+		let (states, _) = standoff(270.0, 10);
+		assert!(states.iter().all(|state| *state == EnemyState::Idle));
+	}
+
+	#[test]
+	fn views_stay_inside_the_bounds() {
+		let bounds = (Vec2::ZERO, Vec2::new(400.0, 300.0));
+		let half_size = Vec2::new(100.0, 50.0);
+		assert_eq!(clamp_to_bounds(Vec2::new(200.0, 150.0), half_size, bounds), Vec2::new(200.0, 150.0));
+		assert_eq!(clamp_to_bounds(Vec2::new(-50.0, 280.0), half_size, bounds), Vec2::new(100.0, 250.0));
+		assert_eq!(clamp_to_bounds(Vec2::new(390.0, 10.0), half_size, bounds), Vec2::new(300.0, 50.0));
+		// Un nivel mas estrecho que la vista queda centrado
+		assert_eq!(clamp_to_bounds(Vec2::new(390.0, 10.0), Vec2::new(250.0, 50.0), bounds), Vec2::new(200.0, 50.0));
+	}
+
+	fn cursor_world(cursor: Vec2, mut projection: OrthographicProjection) -> Vec2 {
+		projection.update(1280.0, 720.0);
+		unproject_cursor(cursor, Vec2::new(1280.0, 720.0), projection.get_projection_matrix())
+	}
+
+	fn assert_near(a: Vec2, b: Vec2) {
+		assert!(a.distance(b) < 1e-3, "{:?} != {:?}", a, b);
+	}
+
+	#[test]
+	fn cursor_unprojects_through_the_camera() {
+		let window_size = OrthographicProjection::default();
+		assert_near(cursor_world(Vec2::new(640.0, 360.0), window_size.clone()), Vec2::ZERO);
+		assert_near(cursor_world(Vec2::new(1280.0, 720.0), window_size.clone()), Vec2::new(640.0, 360.0));
+		assert_near(cursor_world(Vec2::ZERO, OrthographicProjection { scale: 0.5, ..window_size }), Vec2::new(-320.0, -180.0));
+		// Con la altura fija el ancho sigue la proporcion de la ventana
+		let fixed = OrthographicProjection { scaling_mode: ScalingMode::FixedVertical, scale: 100.0, ..Default::default() };
+		assert_near(cursor_world(Vec2::new(1280.0, 720.0), fixed), Vec2::new(100.0 * 16.0 / 9.0, 100.0));
+	}
+
+	#[test]
+	fn each_pickup_counts_once() {
+		let mut app = test_app();
+		app.init_resource::<Score>()
+			.init_resource::<CollectedEntities>()
+			.add_event::<CollisionEvent>()
+			.add_system(collect);
+		let player = spawn_player(&mut app.world, Vec2::ZERO);
+		let pickup = app.world.spawn().insert(Collectible).insert(CollectibleId::default()).id();
+		for _ in 0..3 {
+			send(&mut app, contact_started(player, "Player", pickup, "Collectible"));
+		}
+		app.update();
+		// Contactos con una ya recogida en el frame de despues
+		send(&mut app, contact_started(pickup, "Collectible", player, "Player"));
+		app.update();
+
+		assert!(app.world.get_entity(pickup).is_none());
+		assert_eq!(app.world.get_resource::<Score>().unwrap().collected, 1);
+		assert_eq!(app.world.get_resource::<CollectedEntities>().unwrap().0.len(), 1);
+	}
+
+	// Enemigos persiguiendo desde la celda (1, 1) de una rejilla de 10x10, con el jugador en la (8, 8)
+	fn pathfinding_app(walls: impl IntoIterator<Item = GridCoords>, enemies: usize) -> (App, Vec<Entity>) {
+		let mut app = test_app();
+		let mut nav_grid = NavGrid { width: 10, height: 10, grid_size: 16, ..Default::default() };
+		nav_grid.walls.extend(walls);
+		app.insert_resource(nav_grid).add_system(enemy_pathfinding);
+		spawn_player(&mut app.world, Vec2::splat(8.5 * 16.0));
+		let enemies = (0..enemies).map(|_| {
+			let enemy = spawn_enemy(&mut app.world, Vec2::splat(1.5 * 16.0));
+			*app.world.get_mut::<EnemyState>(enemy).unwrap() = EnemyState::Chase;
+			enemy
+		}).collect();
+		(app, enemies)
+	}
+
+	#[test]
+	fn unreachable_player_waits_for_the_repath_interval() {
+		// El jugador encerrado en su celda
+		let walls = (7..=9).flat_map(|x| (7..=9).map(move |y| GridCoords { x, y })).filter(|&coords| coords != GridCoords { x: 8, y: 8 });
+		let (mut app, enemies) = pathfinding_app(walls, 1);
+		app.update();
+		assert!(app.world.get::<NavPath>(enemies[0]).unwrap().waypoints.is_empty());
+		// Sin camino tampoco lo vuelve a buscar en cada paso
+		for _ in 0..10 { app.update() }
+		let path = app.world.get::<NavPath>(enemies[0]).unwrap();
+		assert!(!path.repath.finished());
+		assert_eq!(path.repath.elapsed(), Duration::from_secs_f32(STEP) * 10);
+	}
+
+	#[test]
+	fn path_searches_are_spread_over_frames() {
+		let (mut app, enemies) = pathfinding_app([], 12);
+		let with_path = |app: &App| enemies.iter().filter(|&&enemy| !app.world.get::<NavPath>(enemy).unwrap().waypoints.is_empty()).count();
+		app.update();
+		assert_eq!(with_path(&app), 8);
+		app.update();
+		assert_eq!(with_path(&app), 12);
+	}
+
+	fn main_project() -> ldtk::LdtkJson {
+		serde_json::from_str(include_str!("../assets/tilemap/main.ldtk")).unwrap()
+	}
+
+	#[test]
+	fn wall_colliders_follow_the_layer_offset() {
+		let mut app = test_app();
+		app.add_plugin(bevy::asset::AssetPlugin)
+			.add_asset::<LdtkLevel>()
+			.add_system(spawn_wall_collision);
+		// LDtk mide la y hacia abajo, 24px de desplazamiento bajan la capa
+		let mut level = main_project().levels.remove(0);
+		let layer = level.layer_instances.as_mut().unwrap().iter_mut().find(|layer| layer.layer_instance_type == Type::IntGrid).unwrap();
+		layer.px_total_offset_x = 8;
+		layer.px_total_offset_y = 24;
+		let size = layer.grid_size as f32;
+		let handle = app.world.get_resource_mut::<Assets<LdtkLevel>>().unwrap().add(LdtkLevel { level });
+		let level_entity = app.world.spawn().insert(handle).id();
+		let chunk = app.world.spawn().insert(Parent(level_entity)).id();
+		for (x, y) in [(0, 0), (2, 1), (3, 1)] {
+			app.world.spawn().insert_bundle((Wall, GridCoords { x, y }, Parent(chunk)));
+		}
+		app.update();
+
+		let mut centers: Vec<Vec2> = app.world.query_filtered::<&Transform, With<CollisionShape>>().iter(&app.world)
+			.map(|transform| transform.translation.truncate())
+			.collect();
+		centers.sort_by(|a, b| a.x.total_cmp(&b.x));
+		assert_eq!(centers, vec![
+			Vec2::new(0.5 * size + 8.0, 0.5 * size - 24.0),
+			Vec2::new(3.0 * size + 8.0, 1.5 * size - 24.0),
+		]);
+		assert_eq!(app.world.get_resource::<NavGrid>().unwrap().offset, Vec2::new(8.0, -24.0));
+	}
+
+	fn count<T: Component>(app: &mut App) -> usize {
+		app.world.query_filtered::<(), With<T>>().iter(&app.world).count()
+	}
+
+	#[test]
+	fn one_goal_wins_once() {
+		let mut app = test_app();
+		app.add_plugin(bevy::asset::AssetPlugin)
+			.add_asset::<LdtkAsset>()
+			.insert_resource(UiFont(Handle::default()))
+			.insert_resource(LevelSelection::Index(0))
+			.init_resource::<Score>()
+			.init_resource::<LevelTimer>()
+			.init_resource::<BestTimes>()
+			.init_resource::<Stats>()
+			.init_resource::<LevelTransition>()
+			.add_event::<LevelEvent>()
+			.add_event::<CollisionEvent>()
+			.add_system(win.label("win"))
+			.add_system(advance_level.after("win"));
+		// Tres niveles, si avanzara dos veces acabaria en el ultimo
+		let mut project = main_project();
+		let level = project.levels[0].clone();
+		project.levels = (0..3).map(|uid| ldtk::Level { uid, ..level.clone() }).collect();
+		let handle = app.world.get_resource_mut::<Assets<LdtkAsset>>().unwrap()
+			.add(LdtkAsset { project, tileset_map: Default::default(), level_map: Default::default() });
+		app.world.spawn().insert(handle);
+
+		let player = spawn_player(&mut app.world, Vec2::ZERO);
+		let goal = app.world.spawn().insert(Win).id();
+		let other_goal = app.world.spawn().insert(Win).id();
+		for (a, b) in [(player, goal), (player, goal), (player, other_goal)] {
+			send(&mut app, contact_started(a, "Player", b, "Win"));
+		}
+		app.update();
+		assert_eq!(count::<WinUi>(&mut app), 1);
+		assert!(app.world.get_resource::<LevelTransition>().unwrap().timer.is_some());
+
+		// Sigue tocando la otra meta mientras esta la pantalla de victoria
+		send(&mut app, contact_started(other_goal, "Win", player, "Player"));
+		app.update();
+		assert_eq!(count::<WinUi>(&mut app), 1);
+
+		// Se agota a mano para no depender del reloj, y llegan mas contactos antes del Transformed
+		let mut level_transition = app.world.get_resource_mut::<LevelTransition>().unwrap();
+		level_transition.timer.as_mut().unwrap().set_elapsed(Duration::from_secs_f32(WIN_SCREEN_SECONDS));
+		for _ in 0..3 {
+			send(&mut app, contact_started(player, "Player", other_goal, "Win"));
+			app.update();
+		}
+
+		assert_eq!(count::<WinUi>(&mut app), 0);
+		assert!(matches!(*app.world.get_resource::<LevelSelection>().unwrap(), LevelSelection::Index(1)));
+		let level_transition = app.world.get_resource::<LevelTransition>().unwrap();
+		assert!(level_transition.timer.is_none() && level_transition.loading);
+	}
+
+	#[test]
+	fn spawn_effect_comes_from_settings() {
+		let mut app = test_app();
+		app.insert_resource(Settings { spawn_effect: Some(SpawnEffect::Scale), ..Default::default() })
+			.add_system(apply_settings.label("settings"))
+			.add_system(insert_spawn_anim.label("insert").after("settings"))
+			.add_system(spawn_anim.after("insert"));
+		let enemy = spawn_enemy(&mut app.world, Vec2::ZERO);
+		app.world.entity_mut(enemy).insert(TextureAtlasSprite::default());
+		app.update();
+
+		assert_eq!(app.world.get::<Transform>(enemy).unwrap().scale, Vec3::ZERO);
+		assert_eq!(app.world.get::<TextureAtlasSprite>(enemy).unwrap().color.a(), 1.0);
+		assert!(app.world.get::<SpawnAnim>(enemy).is_some());
+	}
+
+	#[test]
+	fn leash_home_is_where_the_enemy_was_placed() {
+		let mut app = test_app();
+		app.add_system(set_leash_home);
+		let enemy = spawn_enemy(&mut app.world, Vec2::new(30.0, 40.0));
+		app.world.entity_mut(enemy).insert(Leash { radius: Some(100.0), home: Vec2::ZERO });
+		app.update();
+		assert_eq!(app.world.get::<Leash>(enemy).unwrap().home, Vec2::new(30.0, 40.0));
+
+		// Solo al aparecer, no cada frame
+		app.world.get_mut::<Transform>(enemy).unwrap().translation = Vec3::new(90.0, 0.0, 0.0);
+		app.update();
+		assert_eq!(app.world.get::<Leash>(enemy).unwrap().home, Vec2::new(30.0, 40.0));
+	}
+
+	#[test]
+	fn finished_lifetimes_despawn_with_their_children() {
+		let mut app = test_app();
+		app.add_system(tick_lifetimes);
+		let expired = app.world.spawn().insert(Lifetime { timer: Timer::from_seconds(0.0, false) }).id();
+		let child = app.world.spawn().id();
+		app.world.entity_mut(expired).push_children(&[child]);
+		let alive = app.world.spawn().insert(Lifetime { timer: Timer::from_seconds(60.0, false) }).id();
+		app.update();
+
+		assert!(app.world.get_entity(expired).is_none());
+		assert!(app.world.get_entity(child).is_none());
+		assert!(app.world.get_entity(alive).is_some());
+	}
+}
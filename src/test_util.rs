@@ -0,0 +1,136 @@
+// Las pruebas corren sin ventana ni render, solo ECS, heron y los recursos de la configuracion
+use std::time::Duration;
+
+use bevy::{app::Events, ecs::world::EntityMut, prelude::*, math::vec3};
+use bevy_ecs_ldtk::prelude::*;
+use heron::{prelude::*, CollisionData, PhysicsSteps};
+
+use crate::{
+	components::*,
+	navigation::NavGrid,
+	resources::*,
+	spawning::GameRng,
+};
+
+/// Length of every `App::update`, for `AiTime` and the physics steps
+pub const STEP: f32 = 1.0 / 60.0;
+
+/// Headless app with the default configs and nothing else, add the systems under test to it
+pub fn test_app() -> App {
+	let mut app = App::new();
+	app.add_plugins(MinimalPlugins)
+		.init_resource::<GameConfig>()
+		.init_resource::<CombatConfig>()
+		.init_resource::<DashConfig>()
+		.init_resource::<DebugConfig>()
+		.init_resource::<EnemyConfig>()
+		.init_resource::<LevelDifficulty>()
+		.init_resource::<LevelGrace>()
+		.init_resource::<DamageQueue>()
+		.init_resource::<HitStop>()
+		.init_resource::<NavGrid>()
+		.init_resource::<LightingConfig>()
+		.init_resource::<SpawnAnimConfig>()
+		.insert_resource(AiTime { delta: Duration::from_secs_f32(STEP) })
+		.insert_resource(GameRng::new(Some(0)));
+	app
+}
+
+/// `test_app` with heron stepping exactly `STEP` each update, so runs are reproducible
+pub fn physics_app() -> App {
+	let mut app = test_app();
+	app.add_plugin(TransformPlugin)
+		.add_plugin(PhysicsPlugin::default())
+		.insert_resource(PhysicsSteps::every_frame(Duration::from_secs_f32(STEP)));
+	app
+}
+
+fn instance(identifier: &str) -> EntityInstance {
+	EntityInstance {
+		identifier: identifier.to_string(),
+		..Default::default()
+	}
+}
+
+fn spawn_at(world: &mut World, position: Vec2) -> EntityMut<'_> {
+	let mut entity = world.spawn();
+	entity.insert_bundle((Transform::from_translation(position.extend(0.0)), GlobalTransform::default()));
+	entity
+}
+
+/// Player with the same collider and components as `PlayerBundle`, minus the sprite
+pub fn spawn_player(world: &mut World, position: Vec2) -> Entity {
+	spawn_at(world, position)
+		.insert_bundle(ColliderEntityBundle::from(instance("Player")))
+		.insert_bundle((Player, Health::from(instance("Player")), Riding::default(), Flashlight::default(), Battery::default(), Dash::default()))
+		.id()
+}
+
+/// Stalker with the same collider and components as `EnemyBundle`, minus the sprite
+pub fn spawn_enemy(world: &mut World, position: Vec2) -> Entity {
+	let leash = Leash { radius: None, home: position };
+	spawn_at(world, position)
+		.insert_bundle(ColliderEntityBundle::from(instance("Enemy")))
+		.insert_bundle((Enemy, EnemyKind::Stalker, Health::from(instance("Enemy")), Shooter::default(), leash, PatrolRoute::default()))
+		.insert_bundle((Attack::default(), ScentTrail::default(), EnemyState::default(), Alert::default(), Vision::default(), NavPath::default()))
+		.id()
+}
+
+/// Static box like the ones `spawn_wall_collision` merges out of the IntGrid
+pub fn spawn_wall(world: &mut World, center: Vec2, half_size: Vec2) -> Entity {
+	spawn_at(world, center)
+		.insert_bundle((
+			CollisionShape::Cuboid { half_extends: vec3(half_size.x, half_size.y, 0.), border_radius: None },
+			RigidBody::Static,
+			layers_for("Wall"),
+		))
+		.id()
+}
+
+/// Contact start between two entities with the layers of their LDtk identifiers, as heron sends it
+pub fn contact_started(a: Entity, a_identifier: &str, b: Entity, b_identifier: &str) -> CollisionEvent {
+	CollisionEvent::Started(
+		CollisionData::new(a, a, layers_for(a_identifier), Default::default()),
+		CollisionData::new(b, b, layers_for(b_identifier), Default::default()),
+	)
+}
+
+pub fn send<T: Send + Sync + 'static>(app: &mut App, event: T) {
+	app.world.get_resource_mut::<Events<T>>().unwrap().send(event);
+}
+
+pub fn position(app: &App, entity: Entity) -> Vec2 {
+	app.world.get::<Transform>(entity).unwrap().translation.truncate()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::systems::{enemy_movement, enemy_vision};
+
+	#[test]
+	fn enemy_chases_a_visible_player() {
+		let mut app = physics_app();
+		app.add_system(enemy_vision.label("vision"))
+			.add_system(enemy_movement.after("vision"));
+		let player = spawn_player(&mut app.world, Vec2::new(100.0, 0.0));
+		let enemy = spawn_enemy(&mut app.world, Vec2::ZERO);
+		for _ in 0..30 { app.update() }
+
+		assert_eq!(*app.world.get::<EnemyState>(enemy).unwrap(), EnemyState::Chase);
+		assert!(position(&app, enemy).x > 10.0);
+		assert!(position(&app, player).x > 99.0);
+	}
+
+	#[test]
+	fn walls_block_sight() {
+		let mut app = physics_app();
+		app.add_system(enemy_vision);
+		spawn_player(&mut app.world, Vec2::new(100.0, 0.0));
+		let enemy = spawn_enemy(&mut app.world, Vec2::ZERO);
+		spawn_wall(&mut app.world, Vec2::new(50.0, 0.0), Vec2::new(8.0, 64.0));
+		for _ in 0..3 { app.update() }
+
+		assert!(!app.world.get::<Vision>(enemy).unwrap().can_see_player);
+	}
+}
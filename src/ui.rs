@@ -0,0 +1,73 @@
+use bevy::prelude::*;
+
+pub const FONT_PATH: &str = "fonts/FiraSans-Bold.ttf";
+
+/// The one font every screen uses, loaded once
+pub struct UiFont(pub Handle<Font>);
+
+impl FromWorld for UiFont {
+	fn from_world(world: &mut World) -> Self {
+		Self(world.get_resource::<AssetServer>().unwrap().load(FONT_PATH))
+	}
+}
+
+impl UiFont {
+	pub fn style(&self, font_size: f32, color: Color) -> TextStyle {
+		TextStyle { font: self.0.clone(), font_size, color }
+	}
+}
+
+/// Full screen node with `text` in the middle, despawn it with `despawn_recursive`
+pub fn centered_text(commands: &mut Commands, font: &UiFont, text: &str, font_size: f32, color: Color) -> Entity {
+	commands.spawn_bundle(NodeBundle {
+		style: Style {
+			size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+			position_type: PositionType::Absolute,
+			justify_content: JustifyContent::Center,
+			align_items: AlignItems::Center,
+			..Default::default()
+		},
+		color: Color::NONE.into(),
+		..Default::default()
+	}).with_children(|parent| {
+		parent.spawn_bundle(TextBundle {
+			text: Text::with_section(text, font.style(font_size, color), TextAlignment {
+				horizontal: HorizontalAlign::Center,
+				..Default::default()
+			}),
+			..Default::default()
+		});
+	}).id()
+}
+
+/// Text placed in the world rather than on screen, centered on `translation`
+pub fn world_text(commands: &mut Commands, font: &UiFont, text: &str, font_size: f32, color: Color, translation: Vec3) -> Entity {
+	commands.spawn_bundle(Text2dBundle {
+		text: Text::with_section(text, font.style(font_size, color), TextAlignment {
+			vertical: VerticalAlign::Center,
+			horizontal: HorizontalAlign::Center,
+		}),
+		transform: Transform::from_translation(translation),
+		..Default::default()
+	}).id()
+}
+
+/// Clickable box with a label, read its `Interaction` to react to it
+pub fn button(commands: &mut Commands, font: &UiFont, text: &str, font_size: f32) -> Entity {
+	commands.spawn_bundle(ButtonBundle {
+		style: Style {
+			margin: Rect::all(Val::Px(5.0)),
+			padding: Rect::all(Val::Px(10.0)),
+			justify_content: JustifyContent::Center,
+			align_items: AlignItems::Center,
+			..Default::default()
+		},
+		color: Color::rgba(0.1, 0.1, 0.1, 0.8).into(),
+		..Default::default()
+	}).with_children(|parent| {
+		parent.spawn_bundle(TextBundle {
+			text: Text::with_section(text, font.style(font_size, Color::WHITE), Default::default()),
+			..Default::default()
+		});
+	}).id()
+}